@@ -0,0 +1,87 @@
+//! Scanning and conversion of the modules in `benches/modules`, without any
+//! builds. `example.nix` is a synthetic module shaped like the NixOS ones;
+//! copy in modules of a tree to measure on those too.
+
+use std::fs;
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use nix_doc_munge::{ConvertOptions, convert_docbook, convert_one, pass::Pass};
+use regex::RegexBuilder;
+
+/// A description of the usual size and markup.
+const DESCRIPTION: &str = "Extra flags for <command>example</command>, as described in \
+    <citerefentry><refentrytitle>example</refentrytitle><manvolnum>8</manvolnum></citerefentry>. \
+    Set <option>services.example.enable</option> first, and see \
+    <link xlink:href=\"https://example.org\">the <emphasis>upstream</emphasis> docs</link> or \
+    <xref linkend=\"opt-services.example.port\"/> for <literal>0.0.0.0</literal>.";
+
+/// Attributes, as the conversion patterns accept them.
+const ATTRS: &str = r#"(?:\s+[\w:.-]+\s*=\s*(?:"[^"]*"|'[^']*'))*"#;
+
+/// Patterns like those of the conversion, which compiled them on each call
+/// before they were kept around.
+fn patterns() -> Vec<String> {
+    let code = ["literal", "filename", "option", "command", "varname", "envar"]
+        .map(|tag| format!(r#"<{tag}{ATTRS}\s*>([^`]*?)</{tag}>"#));
+    [
+        format!(r#"<link{ATTRS}\s+xlink:href="([^"]+)"{ATTRS}\s*/>"#),
+        format!(r#"<link{ATTRS}\s+xlink:href="([^"]+)"{ATTRS}\s*>(.*?)</link>"#),
+        format!(r#"<xref{ATTRS}\s+linkend="([^"]+)"{ATTRS}\s*/>"#),
+        format!(r#"<link{ATTRS}\s+linkend="([^"]+)"{ATTRS}\s*>(.*?)</link>"#),
+        format!(r#"<emphasis({ATTRS})\s*>([^*]*?)</emphasis>"#),
+        r"<citerefentry(?:\s[^>]*)?>\s*<refentrytitle(?:\s[^>]*)?>\s*(.*?)\s*</refentrytitle>\s*\
+          <manvolnum(?:\s[^>]*)?>\s*(.*?)\s*</manvolnum>\s*</citerefentry>".to_string(),
+    ].into_iter().chain(code).collect()
+}
+
+fn modules() -> Vec<(String, String)> {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/modules");
+    let mut modules = fs::read_dir(dir).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "nix"))
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read_to_string(path).unwrap())
+        })
+        .collect::<Vec<_>>();
+    modules.sort();
+    modules
+}
+
+fn scan(c: &mut Criterion) {
+    let mut group = c.benchmark_group("scan");
+    let opts = ConvertOptions::default();
+    for (name, content) in modules() {
+        group.bench_function(name, |b| b.iter(|| Pass::MdDoc.scan(&content, &opts)));
+    }
+}
+
+fn convert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("convert_one");
+    let opts = ConvertOptions::default();
+    for (name, content) in modules() {
+        let (candidates, _) = Pass::MdDoc.scan(&content, &opts).unwrap();
+        group.bench_function(name, |b| b.iter(|| {
+            candidates.iter()
+                .map(|candidate| convert_one(&content, candidate, &opts).len())
+                .sum::<usize>()
+        }));
+    }
+}
+
+/// A conversion of `DESCRIPTION`, and compiling the patterns it would take
+/// if they weren't compiled once.
+fn compiled_patterns(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compiled_patterns");
+    let opts = ConvertOptions::default();
+    group.bench_function("convert_docbook", |b| b.iter(|| convert_docbook(DESCRIPTION, &opts)));
+    let patterns = patterns();
+    group.bench_function("compile", |b| b.iter(|| {
+        patterns.iter()
+            .map(|pattern| RegexBuilder::new(pattern).multi_line(true).dot_matches_new_line(true).build().unwrap())
+            .collect::<Vec<_>>()
+    }));
+}
+
+criterion_group!(benches, scan, convert, compiled_patterns);
+criterion_main!(benches);
@@ -0,0 +1,541 @@
+{ config, lib, pkgs, ... }:
+
+with lib;
+
+let
+  cfg = config.services.example;
+in {
+  options.services.example = {
+    enable = mkEnableOption "the example service";
+    package = mkOption {
+      type = (types.listOf types.str);
+      description = "User account under which the service runs. If left as the default, the user is created automatically, see <xref linkend=\"opt-users.users\"/>.";
+    };
+    user = mkOption {
+      type = types.package;
+      description = "Whether to open the port in the firewall, see <option>networking.firewall.allowedTCPPorts</option>.";
+    };
+    group = mkOption {
+      type = types.package;
+      description = "Address to listen on, <literal>0.0.0.0</literal> for all addresses.";
+    };
+    dataDir = mkOption {
+      type = types.bool;
+      description = "Extra command line flags, as passed to <citerefentry><refentrytitle>example</refentrytitle><manvolnum>8</manvolnum></citerefentry>.";
+    };
+    port = mkOption {
+      type = types.path;
+      description = "Address to listen on, <literal>0.0.0.0</literal> for all addresses.";
+    };
+    openFirewall = mkOption {
+      type = types.package;
+      description = "Whether to open the port in the firewall, see <option>networking.firewall.allowedTCPPorts</option>.";
+    };
+    settings = mkOption {
+      type = (types.listOf types.str);
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    extraConfig = mkOption {
+      type = types.path;
+      description = "The package to use, for example <literal>pkgs.example-unstable</literal>.";
+    };
+    extraFlags = mkOption {
+      type = (types.attrsOf types.anything);
+      description = "The package to use, for example <literal>pkgs.example-unstable</literal>.";
+    };
+    environmentFile = mkOption {
+      type = types.bool;
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    logLevel = mkOption {
+      type = types.package;
+      description = ''
+      Hooks to run, as shell commands with <varname>$STATE_DIRECTORY</varname>
+      set. <warning><para>They run as root.</para></warning>
+    '';
+    };
+    listenAddress = mkOption {
+      type = types.bool;
+      description = ''
+      Directory where the service keeps its state. It is created with
+      <command>systemd-tmpfiles</command> and owned by
+      <option>services.example.user</option>.
+
+      <note><para>
+        Changing this after the first start requires moving the data by hand.
+      </para></note>
+    '';
+    };
+    database.host = mkOption {
+      type = (types.listOf types.str);
+      description = ''
+      Hooks to run, as shell commands with <varname>$STATE_DIRECTORY</varname>
+      set. <warning><para>They run as root.</para></warning>
+    '';
+    };
+    database.port = mkOption {
+      type = types.path;
+      description = "Whether to open the port in the firewall, see <option>networking.firewall.allowedTCPPorts</option>.";
+    };
+    database.name = mkOption {
+      type = (types.attrsOf types.anything);
+      description = "Whether to open the port in the firewall, see <option>networking.firewall.allowedTCPPorts</option>.";
+    };
+    database.passwordFile = mkOption {
+      type = types.port;
+      description = "User account under which the service runs. If left as the default, the user is created automatically, see <xref linkend=\"opt-users.users\"/>.";
+    };
+    database.createLocally = mkOption {
+      type = types.str;
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    tls.enable = mkOption {
+      type = types.package;
+      description = "Address to listen on, <literal>0.0.0.0</literal> for all addresses.";
+    };
+    tls.certFile = mkOption {
+      type = types.port;
+      description = ''
+      Hooks to run, as shell commands with <varname>$STATE_DIRECTORY</varname>
+      set. <warning><para>They run as root.</para></warning>
+    '';
+    };
+    tls.keyFile = mkOption {
+      type = types.str;
+      description = "Address to listen on, <literal>0.0.0.0</literal> for all addresses.";
+    };
+    backup.enable = mkOption {
+      type = types.path;
+      description = "The package to use, for example <literal>pkgs.example-unstable</literal>.";
+    };
+    backup.schedule = mkOption {
+      type = types.package;
+      description = "Extra command line flags, as passed to <citerefentry><refentrytitle>example</refentrytitle><manvolnum>8</manvolnum></citerefentry>.";
+    };
+    backup.path = mkOption {
+      type = types.package;
+      description = "Address to listen on, <literal>0.0.0.0</literal> for all addresses.";
+    };
+    plugins = mkOption {
+      type = types.bool;
+      description = ''
+      Hooks to run, as shell commands with <varname>$STATE_DIRECTORY</varname>
+      set. <warning><para>They run as root.</para></warning>
+    '';
+    };
+    hooks.preStart = mkOption {
+      type = types.path;
+      description = ''
+      Hooks to run, as shell commands with <varname>$STATE_DIRECTORY</varname>
+      set. <warning><para>They run as root.</para></warning>
+    '';
+    };
+    hooks.postStart = mkOption {
+      type = (types.attrsOf types.anything);
+      description = "The log level, one of <literal>\"debug\"</literal>, <literal>\"info\"</literal> or <literal>\"error\"</literal>.";
+    };
+    workers = mkOption {
+      type = types.lines;
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    maxUploadSize = mkOption {
+      type = types.int;
+      description = "The log level, one of <literal>\"debug\"</literal>, <literal>\"info\"</literal> or <literal>\"error\"</literal>.";
+    };
+    timezone = mkOption {
+      type = types.port;
+      description = "Extra command line flags, as passed to <citerefentry><refentrytitle>example</refentrytitle><manvolnum>8</manvolnum></citerefentry>.";
+    };
+    instance1.enable = mkEnableOption "instance 1 of the example service";
+    instance1.package = mkOption {
+      type = types.path;
+      description = "User account under which the service runs. If left as the default, the user is created automatically, see <xref linkend=\"opt-users.users\"/>.";
+    };
+    instance1.user = mkOption {
+      type = types.port;
+      description = "The package to use, for example <literal>pkgs.example-unstable</literal>.";
+    };
+    instance1.group = mkOption {
+      type = types.int;
+      description = "Address to listen on, <literal>0.0.0.0</literal> for all addresses.";
+    };
+    instance1.dataDir = mkOption {
+      type = types.int;
+      description = "Extra command line flags, as passed to <citerefentry><refentrytitle>example</refentrytitle><manvolnum>8</manvolnum></citerefentry>.";
+    };
+    instance1.port = mkOption {
+      type = types.package;
+      description = ''
+      Configuration written to <filename>/etc/example/config.yaml</filename>,
+      see <link xlink:href="https://example.org/docs/configuration"/> for all
+      the settings. For example:
+      <programlisting>
+      {
+        server.name = "example.org";
+        server.workers = 4;
+      }
+      </programlisting>
+    '';
+    };
+    instance1.openFirewall = mkOption {
+      type = (types.attrsOf types.anything);
+      description = "The package to use, for example <literal>pkgs.example-unstable</literal>.";
+    };
+    instance1.settings = mkOption {
+      type = types.str;
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    instance1.extraConfig = mkOption {
+      type = types.str;
+      description = "Extra command line flags, as passed to <citerefentry><refentrytitle>example</refentrytitle><manvolnum>8</manvolnum></citerefentry>.";
+    };
+    instance1.extraFlags = mkOption {
+      type = (types.listOf types.str);
+      description = "The log level, one of <literal>\"debug\"</literal>, <literal>\"info\"</literal> or <literal>\"error\"</literal>.";
+    };
+    instance1.environmentFile = mkOption {
+      type = types.package;
+      description = "Whether to open the port in the firewall, see <option>networking.firewall.allowedTCPPorts</option>.";
+    };
+    instance1.logLevel = mkOption {
+      type = types.lines;
+      description = "Address to listen on, <literal>0.0.0.0</literal> for all addresses.";
+    };
+    instance1.listenAddress = mkOption {
+      type = types.lines;
+      description = "Extra command line flags, as passed to <citerefentry><refentrytitle>example</refentrytitle><manvolnum>8</manvolnum></citerefentry>.";
+    };
+    instance1.database.host = mkOption {
+      type = types.int;
+      description = ''
+      Hooks to run, as shell commands with <varname>$STATE_DIRECTORY</varname>
+      set. <warning><para>They run as root.</para></warning>
+    '';
+    };
+    instance1.database.port = mkOption {
+      type = types.int;
+      description = ''
+      Hooks to run, as shell commands with <varname>$STATE_DIRECTORY</varname>
+      set. <warning><para>They run as root.</para></warning>
+    '';
+    };
+    instance1.database.name = mkOption {
+      type = types.package;
+      description = "The package to use, for example <literal>pkgs.example-unstable</literal>.";
+    };
+    instance1.database.passwordFile = mkOption {
+      type = types.int;
+      description = ''
+      Configuration written to <filename>/etc/example/config.yaml</filename>,
+      see <link xlink:href="https://example.org/docs/configuration"/> for all
+      the settings. For example:
+      <programlisting>
+      {
+        server.name = "example.org";
+        server.workers = 4;
+      }
+      </programlisting>
+    '';
+    };
+    instance1.database.createLocally = mkOption {
+      type = types.bool;
+      description = "The package to use, for example <literal>pkgs.example-unstable</literal>.";
+    };
+    instance1.tls.enable = mkOption {
+      type = types.int;
+      description = ''
+      Configuration written to <filename>/etc/example/config.yaml</filename>,
+      see <link xlink:href="https://example.org/docs/configuration"/> for all
+      the settings. For example:
+      <programlisting>
+      {
+        server.name = "example.org";
+        server.workers = 4;
+      }
+      </programlisting>
+    '';
+    };
+    instance1.tls.certFile = mkOption {
+      type = (types.listOf types.str);
+      description = ''
+      Configuration written to <filename>/etc/example/config.yaml</filename>,
+      see <link xlink:href="https://example.org/docs/configuration"/> for all
+      the settings. For example:
+      <programlisting>
+      {
+        server.name = "example.org";
+        server.workers = 4;
+      }
+      </programlisting>
+    '';
+    };
+    instance1.tls.keyFile = mkOption {
+      type = types.bool;
+      description = "Extra command line flags, as passed to <citerefentry><refentrytitle>example</refentrytitle><manvolnum>8</manvolnum></citerefentry>.";
+    };
+    instance1.backup.enable = mkOption {
+      type = types.lines;
+      description = "The log level, one of <literal>\"debug\"</literal>, <literal>\"info\"</literal> or <literal>\"error\"</literal>.";
+    };
+    instance1.backup.schedule = mkOption {
+      type = types.package;
+      description = "User account under which the service runs. If left as the default, the user is created automatically, see <xref linkend=\"opt-users.users\"/>.";
+    };
+    instance1.backup.path = mkOption {
+      type = types.bool;
+      description = "The log level, one of <literal>\"debug\"</literal>, <literal>\"info\"</literal> or <literal>\"error\"</literal>.";
+    };
+    instance1.plugins = mkOption {
+      type = types.port;
+      description = ''
+      Directory where the service keeps its state. It is created with
+      <command>systemd-tmpfiles</command> and owned by
+      <option>services.example.user</option>.
+
+      <note><para>
+        Changing this after the first start requires moving the data by hand.
+      </para></note>
+    '';
+    };
+    instance1.hooks.preStart = mkOption {
+      type = types.path;
+      description = "User account under which the service runs. If left as the default, the user is created automatically, see <xref linkend=\"opt-users.users\"/>.";
+    };
+    instance1.hooks.postStart = mkOption {
+      type = (types.listOf types.str);
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    instance1.workers = mkOption {
+      type = types.package;
+      description = "The log level, one of <literal>\"debug\"</literal>, <literal>\"info\"</literal> or <literal>\"error\"</literal>.";
+    };
+    instance1.maxUploadSize = mkOption {
+      type = types.int;
+      description = "User account under which the service runs. If left as the default, the user is created automatically, see <xref linkend=\"opt-users.users\"/>.";
+    };
+    instance1.timezone = mkOption {
+      type = (types.attrsOf types.anything);
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    instance2.enable = mkEnableOption "instance 2 of the example service";
+    instance2.package = mkOption {
+      type = (types.listOf types.str);
+      description = "User account under which the service runs. If left as the default, the user is created automatically, see <xref linkend=\"opt-users.users\"/>.";
+    };
+    instance2.user = mkOption {
+      type = types.port;
+      description = "Address to listen on, <literal>0.0.0.0</literal> for all addresses.";
+    };
+    instance2.group = mkOption {
+      type = types.lines;
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    instance2.dataDir = mkOption {
+      type = types.path;
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    instance2.port = mkOption {
+      type = types.package;
+      description = "User account under which the service runs. If left as the default, the user is created automatically, see <xref linkend=\"opt-users.users\"/>.";
+    };
+    instance2.openFirewall = mkOption {
+      type = types.str;
+      description = "User account under which the service runs. If left as the default, the user is created automatically, see <xref linkend=\"opt-users.users\"/>.";
+    };
+    instance2.settings = mkOption {
+      type = types.path;
+      description = ''
+      Directory where the service keeps its state. It is created with
+      <command>systemd-tmpfiles</command> and owned by
+      <option>services.example.user</option>.
+
+      <note><para>
+        Changing this after the first start requires moving the data by hand.
+      </para></note>
+    '';
+    };
+    instance2.extraConfig = mkOption {
+      type = types.int;
+      description = "Whether to open the port in the firewall, see <option>networking.firewall.allowedTCPPorts</option>.";
+    };
+    instance2.extraFlags = mkOption {
+      type = types.str;
+      description = ''
+      Hooks to run, as shell commands with <varname>$STATE_DIRECTORY</varname>
+      set. <warning><para>They run as root.</para></warning>
+    '';
+    };
+    instance2.environmentFile = mkOption {
+      type = types.port;
+      description = ''
+      Configuration written to <filename>/etc/example/config.yaml</filename>,
+      see <link xlink:href="https://example.org/docs/configuration"/> for all
+      the settings. For example:
+      <programlisting>
+      {
+        server.name = "example.org";
+        server.workers = 4;
+      }
+      </programlisting>
+    '';
+    };
+    instance2.logLevel = mkOption {
+      type = types.str;
+      description = "Whether to open the port in the firewall, see <option>networking.firewall.allowedTCPPorts</option>.";
+    };
+    instance2.listenAddress = mkOption {
+      type = (types.attrsOf types.anything);
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    instance2.database.host = mkOption {
+      type = types.lines;
+      description = "Extra command line flags, as passed to <citerefentry><refentrytitle>example</refentrytitle><manvolnum>8</manvolnum></citerefentry>.";
+    };
+    instance2.database.port = mkOption {
+      type = (types.attrsOf types.anything);
+      description = "User account under which the service runs. If left as the default, the user is created automatically, see <xref linkend=\"opt-users.users\"/>.";
+    };
+    instance2.database.name = mkOption {
+      type = types.bool;
+      description = ''
+      Hooks to run, as shell commands with <varname>$STATE_DIRECTORY</varname>
+      set. <warning><para>They run as root.</para></warning>
+    '';
+    };
+    instance2.database.passwordFile = mkOption {
+      type = (types.attrsOf types.anything);
+      description = "The log level, one of <literal>\"debug\"</literal>, <literal>\"info\"</literal> or <literal>\"error\"</literal>.";
+    };
+    instance2.database.createLocally = mkOption {
+      type = (types.listOf types.str);
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    instance2.tls.enable = mkOption {
+      type = (types.listOf types.str);
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    instance2.tls.certFile = mkOption {
+      type = types.int;
+      description = "The package to use, for example <literal>pkgs.example-unstable</literal>.";
+    };
+    instance2.tls.keyFile = mkOption {
+      type = types.bool;
+      description = ''
+      File with environment variables such as <envar>EXAMPLE_SECRET</envar>,
+      loaded by <literal>EnvironmentFile=</literal> of the unit. Use this for
+      secrets, which must <emphasis>not</emphasis> end up in the store.
+    '';
+    };
+    instance2.backup.enable = mkOption {
+      type = types.package;
+      description = ''
+      Directory where the service keeps its state. It is created with
+      <command>systemd-tmpfiles</command> and owned by
+      <option>services.example.user</option>.
+
+      <note><para>
+        Changing this after the first start requires moving the data by hand.
+      </para></note>
+    '';
+    };
+    instance2.backup.schedule = mkOption {
+      type = types.int;
+      description = ''
+      Directory where the service keeps its state. It is created with
+      <command>systemd-tmpfiles</command> and owned by
+      <option>services.example.user</option>.
+
+      <note><para>
+        Changing this after the first start requires moving the data by hand.
+      </para></note>
+    '';
+    };
+    instance2.backup.path = mkOption {
+      type = types.package;
+      description = "User account under which the service runs. If left as the default, the user is created automatically, see <xref linkend=\"opt-users.users\"/>.";
+    };
+    instance2.plugins = mkOption {
+      type = types.bool;
+      description = "Extra command line flags, as passed to <citerefentry><refentrytitle>example</refentrytitle><manvolnum>8</manvolnum></citerefentry>.";
+    };
+    instance2.hooks.preStart = mkOption {
+      type = types.bool;
+      description = "The package to use, for example <literal>pkgs.example-unstable</literal>.";
+    };
+    instance2.hooks.postStart = mkOption {
+      type = types.str;
+      description = ''
+      Hooks to run, as shell commands with <varname>$STATE_DIRECTORY</varname>
+      set. <warning><para>They run as root.</para></warning>
+    '';
+    };
+    instance2.workers = mkOption {
+      type = types.package;
+      description = "Address to listen on, <literal>0.0.0.0</literal> for all addresses.";
+    };
+    instance2.maxUploadSize = mkOption {
+      type = types.bool;
+      description = "Extra command line flags, as passed to <citerefentry><refentrytitle>example</refentrytitle><manvolnum>8</manvolnum></citerefentry>.";
+    };
+    instance2.timezone = mkOption {
+      type = types.path;
+      description = "The package to use, for example <literal>pkgs.example-unstable</literal>.";
+    };
+  };
+
+  config = mkIf cfg.enable {
+    systemd.services.example = {
+      description = "Example service";
+      wantedBy = [ "multi-user.target" ];
+      serviceConfig.ExecStart = "${cfg.package}/bin/example ${escapeShellArgs cfg.extraFlags}";
+    };
+  };
+}
@@ -1,4 +1,5 @@
 { rustPlatform
+, installShellFiles
 , pkg-config
 , openssl
 , lib
@@ -10,8 +11,17 @@ rustPlatform.buildRustPackage {
 
   src = lib.cleanSource ./.;
 
-  nativeBuildInputs = [ ];
+  nativeBuildInputs = [ installShellFiles ];
   buildInputs = [ ];
 
   cargoLock.lockFile = ./Cargo.lock;
+
+  postInstall = ''
+    installShellCompletion --cmd nix-doc-munge \
+      --bash <($out/bin/nix-doc-munge completions bash) \
+      --fish <($out/bin/nix-doc-munge completions fish) \
+      --zsh <($out/bin/nix-doc-munge completions zsh)
+    $out/bin/nix-doc-munge manpage > nix-doc-munge.1
+    installManPage nix-doc-munge.1
+  '';
 }
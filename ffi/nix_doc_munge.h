@@ -0,0 +1,17 @@
+#ifndef NIX_DOC_MUNGE_H
+#define NIX_DOC_MUNGE_H
+
+/* Strings returned are owned by the caller and freed with ndm_free. They
+ * are NULL if the argument isn't UTF-8, or on an internal error. */
+
+/* The Markdown of a DocBook description. */
+char *ndm_convert_docbook_fragment(const char *docbook);
+
+/* The candidates in a Nix file as a JSON array of objects with the byte
+ * offsets "start" and "end", "kind" and "option", or NULL if the file
+ * doesn't parse. */
+char *ndm_find_candidates(const char *source);
+
+void ndm_free(char *s);
+
+#endif
@@ -0,0 +1,168 @@
+//! The conversion of the `md-doc` pass for linters and review bots, through
+//! the C functions declared in `nix_doc_munge.h` or the Python module
+//! `nix_doc_munge`, so that they convert exactly like the tool without
+//! running it.
+
+use std::{
+    ffi::{CStr, CString, c_char},
+    panic::{AssertUnwindSafe, catch_unwind},
+};
+
+use nix_doc_munge::{ConvertOptions, convert_docbook, pass::Pass};
+use serde::Serialize;
+
+/// A candidate of the `md-doc` pass, as `find_candidates` lists it.
+#[derive(Serialize)]
+pub struct Found {
+    /// Byte offsets of the candidate in the source.
+    pub start: usize,
+    pub end: usize,
+    pub kind: &'static str,
+    /// Attribute path of the option, empty if it can't be told.
+    pub option: String,
+}
+
+/// The Markdown of the DocBook description `docbook`.
+pub fn convert_docbook_fragment(docbook: &str) -> String {
+    convert_docbook(docbook, &ConvertOptions::default())
+}
+
+/// The documentation in the Nix `source` that the tool would convert,
+/// overlapping candidates included, or why `source` doesn't parse.
+pub fn find_candidates(source: &str) -> Result<Vec<Found>, String> {
+    let (mut candidates, overlapping) = Pass::MdDoc.scan(source, &ConvertOptions::default())
+        .map_err(|e| e.to_string())?;
+    candidates.extend(overlapping);
+    candidates.sort_by_key(|c| c.range.start());
+    Ok(candidates.into_iter()
+        .map(|c| Found {
+            start: c.range.start().into(),
+            end: c.range.end().into(),
+            kind: c.kind.name(),
+            option: c.option_path,
+        })
+        .collect())
+}
+
+/// Call `f` with the string at `s`, returning what it makes of it as a
+/// string to be freed with `ndm_free`, or null if `s` isn't UTF-8 or `f`
+/// fails. A panic must not unwind into the caller, which would abort it, so
+/// it counts as a failure too.
+unsafe fn with_str(s: *const c_char, f: impl FnOnce(&str) -> Option<String>) -> *mut c_char {
+    let Ok(s) = CStr::from_ptr(s).to_str() else {
+        return std::ptr::null_mut();
+    };
+    let Some(result) = catch_unwind(AssertUnwindSafe(|| f(s))).ok().flatten() else {
+        return std::ptr::null_mut();
+    };
+    // the results are made from the input, which has no NUL in it
+    CString::new(result).map_or(std::ptr::null_mut(), CString::into_raw)
+}
+
+/// See `convert_docbook_fragment`.
+///
+/// # Safety
+///
+/// `docbook` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ndm_convert_docbook_fragment(docbook: *const c_char) -> *mut c_char {
+    with_str(docbook, |docbook| Some(convert_docbook_fragment(docbook)))
+}
+
+/// See `find_candidates`, returning them as a JSON array of objects, or null
+/// if `source` doesn't parse.
+///
+/// # Safety
+///
+/// `source` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ndm_find_candidates(source: *const c_char) -> *mut c_char {
+    with_str(source, |source| serde_json::to_string(&find_candidates(source).ok()?).ok())
+}
+
+/// Free a string returned by the functions above.
+///
+/// # Safety
+///
+/// `s` must have been returned by them, and not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn ndm_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(feature = "python")]
+#[pyo3::pymodule(name = "nix_doc_munge")]
+mod python {
+    use pyo3::{exceptions::PyValueError, prelude::*};
+
+    /// The Markdown of the DocBook description `docbook`.
+    #[pyfunction]
+    fn convert_docbook_fragment(docbook: &str) -> String {
+        super::convert_docbook_fragment(docbook)
+    }
+
+    /// The documentation in the Nix `source` that the tool would convert,
+    /// as `(start, end, kind, option)` with byte offsets. Raises `ValueError`
+    /// if `source` doesn't parse.
+    #[pyfunction]
+    fn find_candidates(source: &str) -> PyResult<Vec<(usize, usize, &'static str, String)>> {
+        Ok(super::find_candidates(source).map_err(PyValueError::new_err)?.into_iter()
+            .map(|found| (found.start, found.end, found.kind, found.option))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+
+    use super::*;
+
+    const MODULE: &str = "{ lib, ... }: {\n  options.foo = lib.mkOption {\n    \
+                          description = \"Some <literal>bar</literal>.\";\n  };\n}\n";
+
+    #[test]
+    fn c_abi() {
+        let source = CString::new(MODULE).unwrap();
+        let broken = CString::new(&MODULE[.. MODULE.len() / 2]).unwrap();
+        unsafe {
+            let json = ndm_find_candidates(source.as_ptr());
+            assert!(!json.is_null());
+            let found: serde_json::Value = serde_json::from_str(CStr::from_ptr(json).to_str().unwrap()).unwrap();
+            ndm_free(json);
+            assert_eq!(found[0]["option"], "foo");
+            assert_eq!(found[0]["kind"], "description");
+            assert!(ndm_find_candidates(broken.as_ptr()).is_null());
+
+            let markdown = ndm_convert_docbook_fragment(c"Some <literal>bar</literal>.".as_ptr());
+            assert_eq!(CStr::from_ptr(markdown).to_str(), Ok("Some `bar`."));
+            ndm_free(markdown);
+        }
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn python() {
+        use pyo3::{exceptions::PyValueError, prelude::*};
+
+        Python::initialize();
+        Python::attach(|py| {
+            let module = pyo3::wrap_pymodule!(super::python)(py).into_bound(py);
+            let found = module.getattr("find_candidates").unwrap().call1((MODULE,)).unwrap();
+            let found: Vec<(usize, usize, String, String)> = found.extract().unwrap();
+            assert_eq!(found.len(), 1);
+            assert_eq!((found[0].2.as_str(), found[0].3.as_str()), ("description", "foo"));
+            let error = module.getattr("find_candidates").unwrap()
+                .call1((&MODULE[.. MODULE.len() / 2],))
+                .unwrap_err();
+            assert!(error.is_instance_of::<PyValueError>(py));
+
+            let markdown = module.getattr("convert_docbook_fragment").unwrap()
+                .call1(("Some <literal>bar</literal>.",))
+                .unwrap();
+            assert_eq!(markdown.extract::<String>().unwrap(), "Some `bar`.");
+        });
+    }
+}
@@ -0,0 +1,43 @@
+//! Run with `cargo fuzz run convert -- -timeout=10` to also catch regexes
+//! that take forever on some input.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nix_doc_munge::{ConvertOptions, convert_one, pass::Pass};
+
+/// Escape `text` into the literal of a `"…"` or `''…''` string with that
+/// value, as far as quotes next to escapes allow.
+fn nix_literal(text: &str, indented: bool) -> String {
+    match indented {
+        false => {
+            let text = text.replace('\\', "\\\\").replace('"', "\\\"").replace("${", "\\${");
+            format!("\"{text}\"")
+        }
+        true => {
+            let text = text.replace("''", "'''").replace("${", "''${");
+            format!("''\n      {text}\n    ''")
+        }
+    }
+}
+
+fuzz_target!(|input: (bool, bool, &str)| {
+    let (indented, enable_option, text) = input;
+    let description = nix_literal(text, indented);
+    let file = match enable_option {
+        false => format!("{{ lib, ... }}: {{\n  options.foo = lib.mkOption {{\n    \
+                          description = {description};\n  }};\n}}\n"),
+        true => format!("{{ lib, ... }}: {{\n  options.foo = lib.mkEnableOption {description};\n}}\n"),
+    };
+    // only trees that parse are ever converted
+    let opts = ConvertOptions::default();
+    let Ok((candidates, _)) = Pass::MdDoc.scan(&file, &opts) else {
+        return;
+    };
+    for candidate in candidates {
+        let new = convert_one(&file, &candidate, &opts);
+        // the rewrite is spliced in place, nothing around it may change
+        assert!(new.starts_with(&file[.. usize::from(candidate.range.start())]));
+        assert!(new.ends_with(&file[usize::from(candidate.range.end()) ..]));
+    }
+});
@@ -1,19 +1,29 @@
-shopt -s nullglob
+shopt -s nullglob globstar
 
 export PAGER=cat
 
-for before in munge-failures/*.before.nix; do
-	prefix=${before%.before.nix}
-	after=$prefix.after.nix
-	before_xml=$prefix.before.xml
-	after_xml=$prefix.after.xml
-	printf '===> %s\n' "$before"
-	git diff --no-index --color=always -- "$before" "$after" || true
+metas=(munge-failures/**/meta.json)
+# near misses last, the other failures are more likely real problems
+if (( ${#metas[@]} )); then
+	mapfile -t metas < <(
+		grep -L '"outcome": "near-miss"' "${metas[@]}"
+		grep -l '"outcome": "near-miss"' "${metas[@]}"
+	)
+fi
+
+for meta in "${metas[@]}"; do
+	dir=${meta%/meta.json}
+	printf '===> %s\n' "$dir"
+	cat "$meta"
 	printf '\n'
-	if [[ -e $before_xml ]]; then
-		git diff --no-index --color=always -- "$before_xml" "$after_xml" || true
+	if [[ -e $dir/after.nix ]]; then
+		git diff --no-index --color=always -- "$dir/before.nix" "$dir/after.nix" || true
+		printf '\n'
+	fi
+	if [[ -e $dir/before.xml ]]; then
+		git diff --no-index --color=always -- "$dir/before.xml" "$dir/after.xml" || true
 	else
-		grep -E '^(building|Exception:|RuntimeError:) ' "$prefix.after.error" || true
+		grep -E '^(building|Exception:|RuntimeError:) ' "$dir/after.error" || cat "$dir/after.error"
 	fi
 	printf '\n\n'
 done | less -R
@@ -0,0 +1,80 @@
+use std::fs;
+
+use nix_doc_munge::pass::common_affixes;
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AllowListFile {
+    #[serde(default)]
+    allow: Vec<AllowedDiffFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AllowedDiffFile {
+    name: String,
+    expected: String,
+    actual: String,
+}
+
+#[derive(Clone, Debug)]
+struct AllowedDiff {
+    name: String,
+    expected: Regex,
+    actual: String,
+}
+
+/// Differences between the expected and the built manual known to be
+/// harmless, from a TOML file passed to `--allowed-diffs`, e.g.
+///
+/// ```toml
+/// [[allow]]
+/// name = "ellipsis in literals"
+/// expected = '(<literal>[^<]*)\.\.\.'
+/// actual = '$1…'
+/// ```
+///
+/// In the lines that differ, each `expected` is replaced by its `actual`,
+/// which can refer to groups. If that turns the expected manual into the
+/// built one, the rewrite is kept.
+#[derive(Clone, Debug)]
+pub struct AllowList(Vec<AllowedDiff>);
+
+impl AllowList {
+    /// Load an allow list from `path`. Meant to be used as a clap value
+    /// parser.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let file: AllowListFile = toml::from_str(&text).map_err(|e| format!("{path}: {e}"))?;
+        let diffs = file.allow.into_iter()
+            .map(|d| {
+                let expected = Regex::new(&d.expected).map_err(|e| format!("{path}: {}: {e}", d.name))?;
+                Ok(AllowedDiff { name: d.name, expected, actual: d.actual })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self(diffs))
+    }
+
+    /// The names of the allowed differences that account for all of those
+    /// between `expected` and `changed`, if they do.
+    pub fn allows(&self, expected: &str, changed: &str) -> Option<Vec<String>> {
+        let (prefix, suffix) = common_affixes(expected, changed);
+        // whole lines, so that patterns see the context of the difference
+        let start = expected[.. prefix].rfind('\n').map_or(0, |i| i + 1);
+        let common_end = &expected[expected.len() - suffix ..];
+        let tail = suffix - common_end.find('\n').unwrap_or(suffix);
+        let changed = &changed[start .. changed.len() - tail];
+
+        let mut text = expected[start .. expected.len() - tail].to_string();
+        let mut used = vec![];
+        for diff in &self.0 {
+            if diff.expected.is_match(&text) {
+                text = diff.expected.replace_all(&text, diff.actual.as_str()).into_owned();
+                used.push(diff.name.clone());
+            }
+        }
+        (text == changed).then_some(used)
+    }
+}
@@ -0,0 +1,93 @@
+use std::{collections::{HashMap, hash_map::Entry}, fs, io::ErrorKind};
+
+use anyhow::Result;
+use rnix::{parser::ParseError, TextRange, TextSize};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use nix_doc_munge::{
+    ConvertOptions,
+    nix_string::StringKind,
+    pass::{Candidate, CandidateKind, Candidates, Pass},
+};
+
+/// A `Candidate`, whose range can't be serialized as it is.
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedCandidate {
+    start: u32,
+    end: u32,
+    kind: CandidateKind,
+    option_path: String,
+    string_kind: Option<StringKind>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Scan {
+    candidates: Vec<CachedCandidate>,
+    overlapping: Vec<CachedCandidate>,
+}
+
+/// Results of `Pass::scan` from earlier runs, for `--cache`. Entries are
+/// keyed by a hash of the program version, the pass and the file contents,
+/// so edited files are simply scanned again.
+#[derive(Default, Serialize, Deserialize)]
+pub struct CandidateCache(HashMap<String, Scan>);
+
+fn to_cached(candidates: Candidates) -> Vec<CachedCandidate> {
+    candidates.into_iter()
+        .map(|c| CachedCandidate {
+            start: c.range.start().into(),
+            end: c.range.end().into(),
+            kind: c.kind,
+            option_path: c.option_path,
+            string_kind: c.string_kind,
+        })
+        .collect()
+}
+
+fn from_cached(cached: &[CachedCandidate]) -> Candidates {
+    cached.iter()
+        .map(|c| Candidate {
+            range: TextRange::new(TextSize::from(c.start), TextSize::from(c.end)),
+            kind: c.kind,
+            option_path: c.option_path.clone(),
+            string_kind: c.string_kind,
+        })
+        .collect()
+}
+
+impl CandidateCache {
+    /// Load the cache at `path`, starting empty if there is none yet.
+    pub fn load(path: &str) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Candidates and overlapping candidates of `content`, parsing it only
+    /// if it hasn't been seen before. Files that don't parse aren't cached.
+    pub fn scan(&mut self, pass: Pass, content: &str, opts: &ConvertOptions)
+                -> Result<(Candidates, Candidates), ParseError> {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update(format!("{pass:?}\0{:?}\0", opts.doc_attrs()));
+        hasher.update(content);
+        let key = format!("{:x}", hasher.finalize());
+
+        let scan = match self.0.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let (candidates, overlapping) = pass.scan(content, opts)?;
+                entry.insert(Scan { candidates: to_cached(candidates), overlapping: to_cached(overlapping) })
+            },
+        };
+        Ok((from_cached(&scan.candidates), from_cached(&scan.overlapping)))
+    }
+}
@@ -0,0 +1,284 @@
+use std::sync::LazyLock;
+
+use anyhow::{Result, bail};
+use regex::{Regex, RegexBuilder};
+
+use crate::{ConvertOptions, convert_markup, decode_entities, escape_prose, markup, reflow::reflow, tag_map::TagMap};
+
+const ADMONITIONS: &[&str] = &["note", "warning", "important", "tip", "caution"];
+
+/// Block tags without a Markdown equivalent we produce. Chapters using them
+/// are reported as failures rather than converted into something else.
+const UNSUPPORTED: &[&str] = &[
+    "example", "figure", "table", "informaltable", "simplesect", "sidebar", "blockquote",
+    "procedure", "step", "qandaset", "xi:include",
+];
+
+enum List {
+    Bullet,
+    Ordered(usize),
+    Definition,
+}
+
+/// Collects the Markdown blocks of a chapter. Inline DocBook accumulates in
+/// `text` until a block boundary flushes it as a paragraph.
+struct Writer {
+    tags: TagMap,
+    option_refs: bool,
+    blocks: Vec<String>,
+    text: String,
+    depth: usize,
+    id: Option<String>,
+    lists: Vec<List>,
+    /// Indentation of each enclosing list item.
+    indent: Vec<String>,
+    /// Marker of a list item whose first block hasn't been written yet.
+    marker: Option<String>,
+}
+
+impl Writer {
+    fn push_block(&mut self, block: &str) {
+        let prefix = self.indent.concat();
+        let mut result = vec![];
+        for (i, line) in block.split('\n').enumerate() {
+            if i == 0 {
+                if let Some(marker) = self.marker.take() {
+                    let outer = self.indent[.. self.indent.len() - 1].concat();
+                    result.push(format!("{outer}{marker}{line}"));
+                    continue;
+                }
+            }
+            result.push(match line {
+                "" => String::new(),
+                line => format!("{prefix}{line}"),
+            });
+        }
+        self.blocks.push(result.join("\n"));
+    }
+
+    fn inline(&self, text: &str) -> String {
+        escape_prose(&convert_markup(&text.split_whitespace().collect::<Vec<_>>().join(" "), &self.tags, self.option_refs))
+    }
+
+    fn flush(&mut self) {
+        let text = std::mem::take(&mut self.text);
+        if text.trim().is_empty() {
+            return;
+        }
+        let width = 80usize.saturating_sub(self.indent.concat().len());
+        let paragraph = reflow(&self.inline(&text), width);
+        self.push_block(&paragraph);
+    }
+
+    fn heading(&mut self, title: &str) {
+        let mut heading = format!("{} {}", "#".repeat(self.depth.max(1)), self.inline(title));
+        if let Some(id) = self.id.take() {
+            heading.push_str(&format!(" {{#{id}}}"));
+        }
+        self.push_block(&heading);
+    }
+
+    /// A fenced code block of `body`, longer than any run of backticks in
+    /// it, with the `language` of the listing as its info string.
+    fn code(&mut self, body: &str, language: Option<&str>) {
+        let body = match body.trim().strip_prefix("<![CDATA[").and_then(|b| b.strip_suffix("]]>")) {
+            Some(cdata) => cdata.to_string(),
+            None => decode_entities(body),
+        };
+        let longest = body.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+        let fence = "`".repeat((longest + 1).max(3));
+        let language = language.unwrap_or_default();
+        self.push_block(&format!("{fence}{language}\n{}\n{fence}", body.trim_matches('\n')));
+    }
+
+    fn open_item(&mut self) {
+        let marker = match self.lists.last_mut() {
+            Some(List::Ordered(n)) => {
+                *n += 1;
+                format!("{}. ", *n - 1)
+            }
+            Some(List::Definition) => ":   ".to_string(),
+            _ => "- ".to_string(),
+        };
+        self.indent.push(" ".repeat(marker.len()));
+        self.marker = Some(marker);
+    }
+}
+
+static XML_ID: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"xml:id\s*=\s*"([^"]*)""#).unwrap());
+static LANGUAGE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?:^|\s)language\s*=\s*"([^"]*)""#).unwrap());
+static TOKEN: LazyLock<Regex> = LazyLock::new(|| {
+    RegexBuilder::new(r#"<!--.*?-->|<\?.*?\?>|<(/?)([\w:.-]+)([^>]*?)(/?)>"#)
+        .dot_matches_new_line(true)
+        .build().unwrap()
+});
+
+fn xml_id(attrs: &str) -> Option<String> {
+    XML_ID
+        .captures(attrs)
+        .map(|caps| caps[1].to_string())
+}
+
+/// Convert a standalone DocBook chapter, such as those referenced by
+/// `meta.doc`, into a nixpkgs Markdown document. Sections become headings
+/// carrying their ids, inline markup is converted as in option descriptions.
+pub fn chapter_to_markdown(xml: &str, opts: &ConvertOptions) -> Result<String> {
+    let mut w = Writer {
+        tags: opts.tag_map.clone().unwrap_or_default(),
+        option_refs: opts.option_refs,
+        blocks: vec![],
+        text: String::new(),
+        depth: 0,
+        id: None,
+        lists: vec![],
+        indent: vec![],
+        marker: None,
+    };
+
+    let mut pos = 0;
+    while let Some(caps) = TOKEN.captures_at(xml, pos) {
+        let m = caps.get(0).unwrap();
+        w.text.push_str(&xml[pos .. m.start()]);
+        pos = m.end();
+        // comments and processing instructions
+        let Some(name) = caps.get(2).map(|n| n.as_str()) else {
+            continue;
+        };
+        let closing = !caps[1].is_empty();
+        let mut contents = |tag: &str| -> Result<&str> {
+            let close = format!("</{tag}>");
+            let Some(end) = xml[pos ..].find(&close).map(|i| pos + i) else {
+                bail!("unclosed <{tag}>");
+            };
+            let body = &xml[pos .. end];
+            pos = end + close.len();
+            Ok(body)
+        };
+
+        match (closing, name) {
+            (false, "chapter" | "section") => {
+                w.flush();
+                w.depth += 1;
+                w.id = xml_id(&caps[3]);
+            }
+            (true, "chapter" | "section") => {
+                w.flush();
+                let Some(depth) = w.depth.checked_sub(1) else {
+                    bail!("unbalanced </{name}>");
+                };
+                w.depth = depth;
+            }
+            (false, "title") => {
+                let title = contents("title")?;
+                w.flush();
+                w.heading(title);
+            }
+            (_, "para" | "simpara") => w.flush(),
+            (false, "anchor") => {
+                if let Some(id) = xml_id(&caps[3]) {
+                    w.text.push_str(&markup(&format!("[]{{#{id}}}")));
+                }
+            }
+            (false, tag @ ("programlisting" | "screen")) => {
+                let language = LANGUAGE.captures(&caps[3]).map(|caps| caps.get(1).unwrap().as_str());
+                let body = contents(tag)?;
+                w.flush();
+                w.code(body, language);
+            }
+            (false, tag) if ADMONITIONS.contains(&tag) => {
+                w.flush();
+                w.push_block(&format!("::: {{.{tag}}}"));
+            }
+            (true, tag) if ADMONITIONS.contains(&tag) => {
+                w.flush();
+                w.push_block(":::");
+            }
+            (false, "itemizedlist") => {
+                w.flush();
+                w.lists.push(List::Bullet);
+            }
+            (false, "orderedlist") => {
+                w.flush();
+                w.lists.push(List::Ordered(1));
+            }
+            (false, "variablelist") => {
+                w.flush();
+                w.lists.push(List::Definition);
+            }
+            (true, "itemizedlist" | "orderedlist" | "variablelist") => {
+                w.flush();
+                w.lists.pop();
+            }
+            (_, "varlistentry") => w.flush(),
+            (false, "term") => {
+                let term = contents("term")?;
+                w.flush();
+                let term = w.inline(term);
+                w.push_block(&term);
+            }
+            (false, "listitem") => {
+                w.flush();
+                w.open_item();
+            }
+            (true, "listitem") => {
+                w.flush();
+                w.indent.pop();
+                w.marker = None;
+            }
+            (_, tag) if UNSUPPORTED.contains(&tag) => bail!("unsupported tag <{tag}>"),
+            _ => w.text.push_str(m.as_str()),
+        }
+    }
+    w.text.push_str(&xml[pos ..]);
+    w.flush();
+
+    Ok(w.blocks.join("\n\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::chapter_to_markdown;
+    use crate::ConvertOptions;
+
+    fn markdown(xml: &str) -> String {
+        chapter_to_markdown(xml, &ConvertOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn sections_nest() {
+        let xml = r#"<chapter xml:id="ch-a"><title>A</title>
+            <section xml:id="sec-b"><title>B</title><para>Text.</para>
+              <section><title>C</title></section>
+            </section>
+            <section><title>D</title></section>
+          </chapter>"#;
+        assert_eq!(markdown(xml), "# A {#ch-a}\n\n## B {#sec-b}\n\nText.\n\n### C\n\n## D\n");
+    }
+
+    #[test]
+    fn anchors_keep_their_ids() {
+        let xml = r#"<chapter><title>A</title><para><anchor xml:id="x"/>Text with_an [anchor].</para></chapter>"#;
+        assert_eq!(markdown(xml), "# A\n\n[]{#x}Text with_an \\[anchor\\].\n");
+    }
+
+    #[test]
+    fn program_listings_are_verbatim() {
+        let xml = "<chapter><title>A</title><programlisting>\n{ a = \"&lt;*b*&gt;\";\n    c = [ ]; }\n</programlisting>\n\
+                   <programlisting><![CDATA[x &lt; y]]></programlisting></chapter>";
+        assert_eq!(markdown(xml), "# A\n\n```\n{ a = \"<*b*>\";\n    c = [ ]; }\n```\n\n```\nx &lt; y\n```\n");
+    }
+
+    #[test]
+    fn program_listings_keep_their_language_and_backticks() {
+        let xml = r#"<chapter><title>A</title><programlisting language="markdown">```
+b
+```</programlisting></chapter>"#;
+        assert_eq!(markdown(xml), "# A\n\n````markdown\n```\nb\n```\n````\n");
+    }
+
+    #[test]
+    fn unbalanced_sections_fail() {
+        let error = chapter_to_markdown("<chapter><title>A</title></chapter></section>", &ConvertOptions::default());
+        assert_eq!(error.unwrap_err().to_string(), "unbalanced </section>");
+    }
+}
@@ -0,0 +1,65 @@
+use std::{ffi::OsString, fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+use toml::{Table, Value};
+
+/// The file in the tree its conversions are set up in, e.g.
+///
+/// ```toml
+/// manual-expr = "(import ./docs { pkgs = import <nixpkgs> {}; }).@attr@"
+/// doc-attr = ["description", "defaultText", "example", "extraDoc"]
+/// skip = ["^modules/programs/vim.nix$", "^programs\\.neovim\\."]
+/// normalize = ['<anchor xml:id="[^"]*"/>']
+/// threads = 8
+/// formatter = "nixpkgs-fmt"
+///
+/// [tag-map.code]
+/// package = "package"
+/// ```
+///
+/// Each key is the long name of an argument, given before those on the
+/// command line, which override it or add to it.
+pub const CONFIG: &str = "nix-doc-munge.toml";
+
+/// The arguments `value` of `key` stands for. A table for `tag-map` is
+/// written to `dir` as the file `--tag-map` takes.
+fn to_args(key: &str, value: Value, dir: &Path, args: &mut Vec<OsString>) -> Result<()> {
+    let flag = format!("--{key}");
+    match value {
+        Value::Boolean(true) => args.push(flag.into()),
+        Value::Boolean(false) => {},
+        Value::String(s) => args.extend([flag.into(), s.into()]),
+        Value::Integer(n) => args.extend([flag.into(), n.to_string().into()]),
+        Value::Array(values) => {
+            for value in values {
+                to_args(key, value, dir, args)?;
+            }
+        },
+        Value::Table(table) if key == "tag-map" => {
+            let path = dir.join("tag-map.toml");
+            fs::write(&path, toml::to_string(&table)?)?;
+            args.extend([flag.into(), path.into()]);
+        },
+        value => bail!("{CONFIG}: {key} can't be {}", value.type_str()),
+    }
+    Ok(())
+}
+
+/// The command line with the arguments of `CONFIG` in the current directory
+/// inserted after the program name, unless there is none or `--no-config`
+/// is given. Files the arguments refer to are written to `dir`.
+pub fn command_line(dir: &Path) -> Result<Vec<OsString>> {
+    let mut args = std::env::args_os().collect::<Vec<_>>();
+    let path = Path::new(CONFIG);
+    if !path.exists() || args.iter().any(|a| a == "--no-config") {
+        return Ok(args);
+    }
+    let text = fs::read_to_string(path)?;
+    let table = text.parse::<Table>().with_context(|| format!("reading {CONFIG}"))?;
+    let mut config = vec![];
+    for (key, value) in table {
+        to_args(&key, value, dir, &mut config)?;
+    }
+    args.splice(1 .. 1, config);
+    Ok(args)
+}
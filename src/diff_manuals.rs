@@ -0,0 +1,61 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{Context, Result};
+use nix_doc_munge::pass::option_entries;
+use similar::TextDiff;
+
+use crate::normalized;
+
+fn read(path: &Path) -> Result<String> {
+    let raw = fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    Ok(normalized(&raw))
+}
+
+/// `manual` without the entries of its options.
+fn rest(manual: &str, entries: &[(String, &str)]) -> String {
+    entries.iter().fold(manual.to_string(), |rest, (_, entry)| rest.replacen(entry, "", 1))
+}
+
+/// Run `diff-manuals`: print the differences between the manuals at `a` and
+/// `b`, normalized like those of checked candidates, grouped by option.
+pub fn run(a: &Path, b: &Path) -> Result<()> {
+    let (old, new) = (read(a)?, read(b)?);
+    let (a_name, b_name) = (a.display().to_string(), b.display().to_string());
+    let (old_entries, new_entries) = (option_entries(&old), option_entries(&new));
+    let mut options = BTreeMap::<&str, (Option<&str>, Option<&str>)>::new();
+    for (name, entry) in &old_entries {
+        options.entry(name).or_default().0 = Some(entry);
+    }
+    for (name, entry) in &new_entries {
+        options.entry(name).or_default().1 = Some(entry);
+    }
+
+    let (mut changed, mut added, mut removed) = (0, 0, 0);
+    for (name, entries) in &options {
+        match *entries {
+            (Some(old), Some(new)) if old != new => {
+                changed += 1;
+                println!("changed  {name}");
+                let diff = TextDiff::from_lines(old, new);
+                print!("{}", diff.unified_diff().header(&a_name, &b_name));
+            },
+            (Some(_), None) => {
+                removed += 1;
+                println!("removed  {name}");
+            },
+            (None, Some(_)) => {
+                added += 1;
+                println!("added    {name}");
+            },
+            _ => {},
+        }
+    }
+    let (old_rest, new_rest) = (rest(&old, &old_entries), rest(&new, &new_entries));
+    if old_rest != new_rest {
+        println!("changed  outside of options");
+        let diff = TextDiff::from_lines(&old_rest, &new_rest);
+        print!("{}", diff.unified_diff().header(&a_name, &b_name));
+    }
+    println!("{changed} options changed, {added} added, {removed} removed of {}", options.len());
+    Ok(())
+}
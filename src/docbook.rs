@@ -0,0 +1,205 @@
+use std::{collections::BTreeMap, sync::LazyLock};
+
+use regex::{Captures, Regex};
+
+use crate::{leading_space, tag_map::TagMap};
+
+/// Translate the Markdown produced by the DocBook conversion back into
+/// DocBook, for modules that have to stay compatible with releases whose
+/// manual only renders DocBook. Only the constructs the forward conversion
+/// emits are understood; anything else passes through as text. The line
+/// structure is kept, paragraph breaks become `</para><para>` lines.
+pub fn markdown_to_docbook(md: &str, tags: &TagMap) -> String {
+    // several tags may share a role, the first one in the map wins
+    let mut roles = BTreeMap::new();
+    for (tag, role) in &tags.code {
+        roles.entry(role.as_str()).or_insert(tag.as_str());
+    }
+    let converter = Converter { roles };
+
+    let lines = md.split('\n').collect::<Vec<_>>();
+    let Some(first) = lines.iter().position(|l| !l.trim().is_empty()) else {
+        return md.to_string();
+    };
+    let last = lines.iter().rposition(|l| !l.trim().is_empty()).unwrap();
+    let mut result = lines[.. first].iter().map(|l| l.to_string()).collect::<Vec<_>>();
+    result.extend(converter.blocks(&lines[first ..= last]));
+    result.extend(lines[last + 1 ..].iter().map(|l| l.to_string()));
+    result.join("\n")
+}
+
+static INLINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"\\(.)",
+        r"|(?:\{(\w+)\})?(?:``\s?(.+?)\s?``|`([^`]*)`)",
+        r"|<([a-zA-Z][a-zA-Z0-9+.-]*:[^<>\s]*)>",
+        r"|\[((?:\\.|[^\]\\])*)\]\(((?:\\.|[^)\\])*)\)",
+        r"|\*\*((?:\\.|[^*\\])+)\*\*",
+        r"|\*((?:\\.|[^*\\])+)\*",
+    )).unwrap()
+});
+static ESCAPE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\\(.)").unwrap());
+
+struct Converter<'a> {
+    roles: BTreeMap<&'a str, &'a str>,
+}
+
+pub fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// The kind of a fenced div opened by `line` and the length of its fence.
+fn admonition_start(line: &str) -> Option<(&str, usize)> {
+    let line = line.trim();
+    let rest = line.trim_start_matches(':');
+    let len = line.len() - rest.len();
+    let kind = rest.trim().strip_prefix("{.")?.strip_suffix('}')?;
+    (len >= 3).then_some((kind, len))
+}
+
+impl Converter<'_> {
+    fn blocks(&self, lines: &[&str]) -> Vec<String> {
+        let mut result = vec![];
+        let mut blank = false;
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            let indent = leading_space(line);
+            if line.trim().is_empty() {
+                blank = !result.is_empty();
+                i += 1;
+                continue;
+            }
+            if blank {
+                result.push(format!("{indent}</para><para>"));
+                blank = false;
+            }
+
+            if line.trim_start().starts_with("```") {
+                let end = (i + 1 .. lines.len())
+                    .find(|&j| lines[j].trim_start().starts_with("```"))
+                    .unwrap_or(lines.len());
+                result.push(format!("{indent}<programlisting>"));
+                result.extend(lines[i + 1 .. end].iter().map(|l| xml_escape(l)));
+                result.push(format!("{indent}</programlisting>"));
+                i = end + 1;
+            } else if let Some((kind, len)) = admonition_start(line) {
+                let end = (i + 1 .. lines.len())
+                    .find(|&j| {
+                        let l = lines[j].trim();
+                        l.len() == len && l.chars().all(|c| c == ':')
+                    })
+                    .unwrap_or(lines.len());
+                result.push(format!("{indent}<{kind}><para>"));
+                result.extend(self.blocks(&lines[i + 1 .. end]));
+                result.push(format!("{indent}</para></{kind}>"));
+                i = end + 1;
+            } else {
+                result.push(self.inline(line));
+                i += 1;
+            }
+        }
+        result
+    }
+
+    fn inline(&self, s: &str) -> String {
+        let mut result = String::new();
+        let mut last = 0;
+        for caps in INLINE.captures_iter(s) {
+            let m = caps.get(0).unwrap();
+            result.push_str(&xml_escape(&s[last .. m.start()]));
+            result.push_str(&self.construct(&caps));
+            last = m.end();
+        }
+        result.push_str(&xml_escape(&s[last ..]));
+        result
+    }
+
+    fn construct(&self, caps: &Captures<'_>) -> String {
+        if let Some(c) = caps.get(1) {
+            return xml_escape(c.as_str());
+        }
+        if let Some(code) = caps.get(3).or(caps.get(4)) {
+            let code = code.as_str();
+            let role = caps.get(2).map_or("", |r| r.as_str());
+            if role == "manpage" {
+                if let Some((title, vol)) = code.strip_suffix(')').and_then(|c| c.rsplit_once('(')) {
+                    return format!("<citerefentry><refentrytitle>{}</refentrytitle>\
+                                    <manvolnum>{}</manvolnum></citerefentry>",
+                                   xml_escape(title), xml_escape(vol));
+                }
+            }
+            return match self.roles.get(role) {
+                Some(tag) => format!("<{tag}>{}</{tag}>", xml_escape(code)),
+                None => xml_escape(&caps[0]),
+            };
+        }
+        if let Some(url) = caps.get(5) {
+            return format!(r#"<link xlink:href="{}"/>"#, xml_escape(url.as_str()));
+        }
+        if let (Some(text), Some(target)) = (caps.get(6), caps.get(7)) {
+            let target = ESCAPE
+                .replace_all(target.as_str(), "$1")
+                .replace("%20", " ");
+            return match (target.strip_prefix('#'), text.as_str()) {
+                (Some(id), "") => format!(r#"<xref linkend="{}"/>"#, xml_escape(id)),
+                (Some(id), text) => format!(r#"<link linkend="{}">{}</link>"#,
+                                            xml_escape(id), self.inline(text)),
+                (None, text) => format!(r#"<link xlink:href="{}">{}</link>"#,
+                                        xml_escape(&target), self.inline(text)),
+            };
+        }
+        if let Some(text) = caps.get(8) {
+            return format!(r#"<emphasis role="strong">{}</emphasis>"#, self.inline(text.as_str()));
+        }
+        if let Some(text) = caps.get(9) {
+            return format!("<emphasis>{}</emphasis>", self.inline(text.as_str()));
+        }
+        xml_escape(&caps[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::markdown_to_docbook;
+    use crate::tag_map::TagMap;
+
+    fn docbook(md: &str) -> String {
+        markdown_to_docbook(md, &TagMap::default())
+    }
+
+    #[test]
+    fn entities_are_escaped() {
+        assert_eq!(docbook("a < b && `c > d`"), "a &lt; b &amp;&amp; <literal>c &gt; d</literal>");
+        assert_eq!(docbook(r"\*not emphasis\* and \`not code\`"), "*not emphasis* and `not code`");
+        assert_eq!(docbook("```\n<para> & ${x}\n```"), "<programlisting>\n&lt;para&gt; &amp; ${x}\n</programlisting>");
+    }
+
+    #[test]
+    fn tags_nest() {
+        assert_eq!(docbook("[the {option}`a.b` *option*](#opt-a.b)"),
+                   r#"<link linkend="opt-a.b">the <option>a.b</option> <emphasis>option</emphasis></link>"#);
+        assert_eq!(docbook("**see [x](https://x.org)**"),
+                   r#"<emphasis role="strong">see <link xlink:href="https://x.org">x</link></emphasis>"#);
+        assert_eq!(docbook(":::: {.warning}\nOuter.\n\n::: {.note}\nInner.\n:::\n::::"),
+                   "<warning><para>\nOuter.\n</para><para>\n<note><para>\nInner.\n</para></note>\n</para></warning>");
+    }
+
+    #[test]
+    fn roles_become_their_tags() {
+        assert_eq!(docbook("{file}`/etc` {manpage}`nix.conf(5)` {unknown}`x`"),
+                   "<filename>/etc</filename> <citerefentry><refentrytitle>nix.conf</refentrytitle>\
+                    <manvolnum>5</manvolnum></citerefentry> {unknown}`x`");
+        assert_eq!(docbook("[](#opt-a) <https://x.org>"), r#"<xref linkend="opt-a"/> <link xlink:href="https://x.org"/>"#);
+    }
+
+    /// The forward conversion makes neither lists nor tables, so they are
+    /// kept as the text they are, line by line.
+    #[test]
+    fn lists_and_tables_are_text() {
+        assert_eq!(docbook("Items:\n\n- one\n- *two*\n1. three"),
+                   "Items:\n</para><para>\n- one\n- <emphasis>two</emphasis>\n1. three");
+        assert_eq!(docbook("| a | b |\n|---|---|\n| `<` | > |"),
+                   "| a | b |\n|---|---|\n| <literal>&lt;</literal> | &gt; |");
+    }
+}
@@ -0,0 +1,128 @@
+use std::{path::Path, process::Command};
+
+use anyhow::{Result, bail};
+use tempfile::tempdir;
+
+use crate::{Args, clone_flag, error::ErrorKind, manual_expr};
+
+/// Oldest Nix with the `nix store` commands used for `--store`.
+const MIN_NIX: (u32, u32) = (2, 4);
+
+#[derive(Default)]
+struct Doctor {
+    problems: usize,
+}
+
+impl Doctor {
+    fn ok(&self, what: &str) {
+        println!("ok       {what}");
+    }
+
+    fn warning(&self, what: &str, fix: &str) {
+        println!("warning  {what}\n         {fix}");
+    }
+
+    fn problem(&mut self, what: &str, fix: &str) {
+        println!("problem  {what}\n         {fix}");
+        self.problems += 1;
+    }
+}
+
+/// Run `command`, returning its stdout, or its stderr if it failed.
+fn output(command: &mut Command) -> Result<String, String> {
+    let result = command.output().map_err(|e| e.to_string())?;
+    match result.status.success() {
+        true => Ok(String::from_utf8_lossy(&result.stdout).trim().to_string()),
+        false => Err(String::from_utf8_lossy(&result.stderr).into_owned()),
+    }
+}
+
+/// The major and minor version in `nix --version` output such as
+/// `nix (Nix) 2.18.1`.
+fn nix_version(output: &str) -> Option<(u32, u32)> {
+    let version = output.split_whitespace().last()?;
+    let mut parts = version.split('.').map(|p| {
+        p.chars().take_while(char::is_ascii_digit).collect::<String>().parse().ok()
+    });
+    Some((parts.next()??, parts.next()??))
+}
+
+/// Check what a run in the tree at `path` with `args` needs, printing a fix
+/// for each problem found.
+pub fn run(path: &Path, args: &Args) -> Result<()> {
+    let mut d = Doctor::default();
+    let opts = &args.build;
+
+    match output(opts.command("nix").arg("--version")) {
+        Ok(version) => match nix_version(&version) {
+            Some(v) if v >= MIN_NIX => d.ok(&version),
+            _ => d.problem(&format!("{version} is too old"),
+                           &format!("install Nix {}.{} or newer", MIN_NIX.0, MIN_NIX.1)),
+        },
+        Err(error) => d.problem(&format!("nix doesn't run: {}", error.trim()),
+                                "install Nix, or put it on PATH or give --nix-bin"),
+    }
+    match output(opts.command("nix-build").arg("--version")) {
+        Ok(_) => d.ok("nix-build runs"),
+        Err(error) => d.problem(&format!("nix-build doesn't run: {}", error.trim()),
+                                "put the Nix installation with nix-build on PATH or give --nix-bin"),
+    }
+
+    let default = path.join("default.nix");
+    if !default.exists() {
+        d.problem(&format!("{} has no default.nix", path.display()),
+                  "point at the nixos directory of a nixpkgs checkout, or at nix-darwin");
+    } else {
+        let (attr, _) = args.pass.manual();
+        let mut command = opts.command("nix-instantiate");
+        if opts.sandboxed {
+            command.env_remove("NIX_PATH").arg("-I").arg(path);
+        }
+        command.current_dir(path).args(opts.args()).arg("-E").arg(manual_expr(None, false, attr, opts));
+        match output(&mut command) {
+            Ok(_) => d.ok(&format!("the {attr} manual evaluates")),
+            Err(stderr) => {
+                let error = ErrorKind::Build { stderr };
+                let headline = error.headline().unwrap_or("unknown error").to_string();
+                d.problem(&format!("the {attr} manual doesn't evaluate: {headline}"),
+                          "fix the evaluation first, every build of the run would fail on it");
+            }
+        }
+    }
+
+    // nixpkgs checkouts have the tree in `nixos`, and nix-darwin imports
+    // `<nixpkgs>` from NIX_PATH
+    if !opts.sandboxed {
+        let checkout = path.join("..").canonicalize().ok()
+            .filter(|parent| parent.join("pkgs/top-level").is_dir());
+        match output(opts.command("nix-instantiate").args(["--find-file", "nixpkgs"])) {
+            Ok(found) => match checkout {
+                Some(checkout) if Path::new(&found).canonicalize().ok().as_ref() != Some(&checkout) => {
+                    d.warning(&format!("<nixpkgs> is {found}, not the checkout of the tree"),
+                              &format!("set NIX_PATH=nixpkgs={} so that both agree", checkout.display()));
+                }
+                _ => d.ok(&format!("<nixpkgs> is {found}")),
+            },
+            Err(_) if checkout.is_some() => d.ok("<nixpkgs> isn't in NIX_PATH"),
+            Err(_) => d.warning("<nixpkgs> isn't in NIX_PATH",
+                                "trees importing it, like nix-darwin, need NIX_PATH=nixpkgs=PATH"),
+        }
+    }
+
+    // the copies of the tree go to the temporary directory
+    if default.exists() {
+        let tmp = tempdir()?;
+        match output(Command::new("cp").arg(clone_flag()).arg(&default).arg(tmp.path())) {
+            Ok(_) => d.ok("files of the tree can be cloned into the temporary directory"),
+            Err(_) => d.warning(
+                "files of the tree can't be cloned into the temporary directory, \
+                 each file checked makes a full copy of the tree",
+                "set TMPDIR to a directory on the file system of the tree, if it is btrfs, XFS or APFS"),
+        }
+    }
+
+    if d.problems > 0 {
+        bail!("{} problems found", d.problems);
+    }
+    Ok(())
+}
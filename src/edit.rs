@@ -0,0 +1,67 @@
+use rnix::{TextRange, TextSize};
+
+use crate::pass::common_affixes;
+
+/// A replacement of `range` of some text by `replacement`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+    pub range: TextRange,
+    pub replacement: String,
+}
+
+impl Edit {
+    /// The edit turning `old` into `new`, spanning everything between their
+    /// common prefix and suffix, if they differ.
+    pub fn between(old: &str, new: &str) -> Option<Self> {
+        if old == new {
+            return None;
+        }
+        let (prefix, suffix) = common_affixes(old, new);
+        let range = TextRange::new(offset(prefix), offset(old.len() - suffix));
+        Some(Self { range, replacement: new[prefix .. new.len() - suffix].to_string() })
+    }
+
+    pub fn apply(&self, s: &str) -> String {
+        let mut s = s.to_string();
+        s.replace_range(usize::from(self.range.start()) .. self.range.end().into(), &self.replacement);
+        s
+    }
+
+    /// How much longer the text gets.
+    fn delta(&self) -> i64 {
+        self.replacement.len() as i64 - i64::from(u32::from(self.range.len()))
+    }
+}
+
+fn offset(n: usize) -> TextSize {
+    TextSize::from(n as u32)
+}
+
+/// The edits made to a text one after another, each in terms of the text
+/// left by those before it. Ranges found in the original text are carried
+/// through them, so that candidates can be rewritten in any order.
+#[derive(Clone, Debug, Default)]
+pub struct EditList(Vec<Edit>);
+
+impl Extend<Edit> for EditList {
+    fn extend<T: IntoIterator<Item = Edit>>(&mut self, edits: T) {
+        self.0.extend(edits);
+    }
+}
+
+impl EditList {
+    /// Where `range` of the original text is now, or `None` if an edit
+    /// touched it.
+    pub fn map(&self, range: TextRange) -> Option<TextRange> {
+        self.0.iter().try_fold(range, |range, edit| {
+            if range.end() <= edit.range.start() {
+                Some(range)
+            } else if range.start() >= edit.range.end() {
+                let start = i64::from(u32::from(range.start())) + edit.delta();
+                Some(TextRange::at(offset(start as usize), range.len()))
+            } else {
+                None
+            }
+        })
+    }
+}
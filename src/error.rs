@@ -0,0 +1,130 @@
+use std::{borrow::Cow, fmt, fs, io, path::{Path, PathBuf}};
+
+use rnix::parser::ParseError;
+
+/// Lines of `nix-build` output kept in messages, from the end where the
+/// error is. The whole output goes into `build.log` of the failure.
+const STDERR_TAIL: usize = 40;
+
+fn tail(stderr: &str) -> Cow<'_, str> {
+    let lines = stderr.lines().collect::<Vec<_>>();
+    if lines.len() <= STDERR_TAIL {
+        return stderr.into();
+    }
+    let omitted = lines.len() - STDERR_TAIL;
+    format!("[{omitted} lines omitted]\n{}", lines[omitted ..].join("\n")).into()
+}
+
+/// What went wrong while checking a candidate or setting up a file.
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// Nix that doesn't parse, either a file as it was or a rewrite of it.
+    Parse(String),
+    /// Copying the tree to build manuals in failed.
+    Copy { stderr: String },
+    /// `nix-build` of the manual failed.
+    Build { stderr: String },
+    /// The manual built, but not into what the rewrite expected.
+    DiffMismatch,
+    /// The manual built as expected, but its evaluation warned of other
+    /// things than expected after the rewrite.
+    Warnings { added: Vec<String>, missing: Vec<String> },
+    /// `--formatter` failed.
+    Format { stderr: String },
+    /// The rewrites accepted in the copies of `--jobs-per-file` didn't
+    /// combine, for the reason given.
+    Merge(&'static str),
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Parse(error) => write!(f, "invalid Nix: {error}"),
+            ErrorKind::Copy { stderr } => write!(f, "copy failed: {stderr}"),
+            ErrorKind::Build { stderr } => write!(f, "build failed: {}", tail(stderr)),
+            ErrorKind::DiffMismatch => write!(f, "the manual doesn't match the expected one"),
+            ErrorKind::Warnings { added, missing } => {
+                write!(f, "evaluation warnings changed")?;
+                for warning in added {
+                    write!(f, "\n+ {warning}")?;
+                }
+                for warning in missing {
+                    write!(f, "\n- {warning}")?;
+                }
+                Ok(())
+            },
+            ErrorKind::Format { stderr } => write!(f, "formatter failed: {}", tail(stderr)),
+            ErrorKind::Merge(reason) => {
+                write!(f, "the rewrites checked in parallel {reason}, try --jobs-per-file 1")
+            },
+            ErrorKind::Io { path, source } => write!(f, "{}: {source}", path.display()),
+        }
+    }
+}
+
+impl ErrorKind {
+    /// The line of a build failure saying what went wrong. Evaluation errors
+    /// end their trace with it.
+    pub fn headline(&self) -> Option<&str> {
+        let ErrorKind::Build { stderr } = self else {
+            return None;
+        };
+        let mut lines = stderr.lines().map(str::trim).filter(|l| !l.is_empty());
+        lines.clone().rfind(|l| l.starts_with("error: ")).or_else(|| lines.next_back())
+    }
+}
+
+impl std::error::Error for ErrorKind {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ErrorKind::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// For `map_err`, attributing an I/O error to `path`.
+pub fn io(path: impl AsRef<Path>) -> impl FnOnce(io::Error) -> ErrorKind {
+    let path = path.as_ref().to_path_buf();
+    move |source| ErrorKind::Io { path, source }
+}
+
+/// For `map_err`, attributing a syntax error to `file`, which can't be
+/// scanned for candidates.
+pub fn parse(file: &str) -> impl FnOnce(ParseError) -> Error {
+    let file = file.to_string();
+    move |error| Error { file, option: None, kind: ErrorKind::Parse(error.to_string()) }
+}
+
+/// An error that stopped the conversion of a file, with the option whose
+/// candidate was being checked at the time, if any.
+#[derive(Debug)]
+pub struct Error {
+    pub file: String,
+    pub option: Option<String>,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.option {
+            Some(option) if !option.is_empty() => write!(f, "{} ({option}): {}", self.file, self.kind),
+            _ => write!(f, "{}: {}", self.file, self.kind),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.kind)
+    }
+}
+
+pub fn write_file(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> Result<(), ErrorKind> {
+    fs::write(&path, contents).map_err(io(path))
+}
+
+pub fn remove_file(path: impl AsRef<Path>) -> Result<(), ErrorKind> {
+    fs::remove_file(&path).map_err(io(path))
+}
@@ -0,0 +1,107 @@
+use std::{fs, path::Path, sync::Arc};
+
+use anyhow::{Result, bail};
+use nix_doc_munge::pass::common_affixes;
+use similar::TextDiff;
+use tempfile::tempdir;
+
+use crate::{
+    Args, BASELINE, Converted, Manual, build_imported, build_manual, copy_tree,
+    error::{remove_file, write_file},
+};
+
+/// Where the manuals of a failed final check are saved.
+const FAILURE_DIR: &str = "munge-failures/final";
+
+/// The lines of `old` that changed into `new`, and what they became. Whole
+/// lines are kept so that the change can be found again in another manual.
+pub fn manual_change(old: &str, new: &str) -> Option<(String, String)> {
+    if old == new {
+        return None;
+    }
+    let (prefix, suffix) = common_affixes(old, new);
+    let start = old[.. prefix].rfind('\n').map_or(0, |i| i + 1);
+    let end = old.len() - suffix + old[old.len() - suffix ..].find('\n').unwrap_or(suffix);
+    let new_end = new.len() - suffix + new[new.len() - suffix ..].find('\n').unwrap_or(suffix);
+    Some((old[start .. end].to_string(), new[start .. new_end].to_string()))
+}
+
+/// Build the manual of the unchanged tree and of the tree with all
+/// `converted` files applied at once.
+fn build_both(converted: &[Converted], args: &Args) -> Result<(Arc<Manual>, Arc<Manual>)> {
+    println!("building the manual with all {} converted files", converted.len());
+    let tmp = tempdir()?;
+    copy_tree(tmp.path())?;
+    let imports = args.import.then(|| {
+        converted.iter()
+            .map(|c| tmp.path().join(&c.file).to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join(" ")
+    });
+    let built = BASELINE.lock().unwrap().clone();
+    let baseline = match (&imports, built) {
+        (None, Some(baseline)) => baseline,
+        (Some(imports), _) => {
+            let contents = converted.iter().map(|c| c.content.as_str()).collect::<Vec<_>>();
+            Manual::new(build_imported(tmp.path(), imports, &contents, args.pass, &args.build, &|_| {}, &|| false)?)
+        },
+        (None, None) => Manual::new(build_manual(&tmp, None, args.pass, &args.build, &|_| {})?),
+    };
+
+    for c in converted {
+        write_file(tmp.path().join(&c.file), &c.content)?;
+        for (path, new) in &c.files {
+            match new {
+                Some(new) => write_file(tmp.path().join(path), new)?,
+                None => remove_file(tmp.path().join(path))?,
+            }
+        }
+    }
+    let changed = Manual::new(build_manual(&tmp, imports.as_deref(), args.pass, &args.build, &|_| {})?);
+    Ok((baseline, changed))
+}
+
+/// Save the normalized manuals and their diff to `dir`, as proof for the
+/// review of the changes of how they render.
+fn save_artifacts(dir: &Path, baseline: &Manual, changed: &Manual) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    write_file(dir.join("baseline.xml"), &baseline.normalized)?;
+    write_file(dir.join("final.xml"), &changed.normalized)?;
+    let diff = TextDiff::from_lines(&baseline.normalized, &changed.normalized)
+        .unified_diff()
+        .header("baseline.xml", "final.xml")
+        .to_string();
+    write_file(dir.join("manual.diff"), diff)?;
+    println!("saved the manuals of the run to {}", dir.display());
+    Ok(())
+}
+
+/// Build the manual of the tree with all `converted` files applied at once,
+/// saving it for `--artifacts`, and for `--final-check` compare it with the
+/// unchanged manual plus the changes accepted in each file. Per-file checks
+/// can't see rewrites of different files interacting, like two of them
+/// changing a string both use.
+pub fn run(converted: &[Converted], args: &Args) -> Result<()> {
+    let (baseline, changed) = build_both(converted, args)?;
+    if let Some(dir) = &args.artifacts {
+        save_artifacts(dir, &baseline, &changed)?;
+    }
+    if !args.final_check {
+        return Ok(());
+    }
+    let expected = converted.iter()
+        .flat_map(|c| &c.manual_changes)
+        .fold(baseline.normalized.clone(), |manual, (from, to)| manual.replacen(from, to, 1));
+    if changed.normalized == expected {
+        println!("final check: the manual is as expected");
+        return Ok(());
+    }
+
+    let dir = Path::new(FAILURE_DIR);
+    fs::create_dir_all(dir)?;
+    write_file(dir.join("before.xml"), &baseline.raw)?;
+    write_file(dir.join("after.xml"), &changed.raw)?;
+    write_file(dir.join("expected.xml"), &expected)?;
+    bail!("the manual of the fully converted tree isn't the expected one, see {FAILURE_DIR}; \
+           no changes were written")
+}
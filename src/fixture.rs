@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{ErrorKind, io, write_file},
+    report::Outcome,
+};
+
+/// Save the text of an accepted `mdDoc` candidate to the fixture corpus, so
+/// that its conversion is pinned by the snapshot tests. `name` tells
+/// candidates of the same file apart.
+pub fn record(dir: &Path, file: &str, name: &str, chunk: &str) -> Result<(), ErrorKind> {
+    let file = file.trim_end_matches(".nix").replace('/', "-");
+    let name = name.replace('/', "-");
+    std::fs::create_dir_all(dir).map_err(io(dir))?;
+    write_file(dir.join(format!("{file}-{name}.nix")), format!("{chunk}\n"))
+}
+
+/// What became of a candidate saved by `dump`.
+#[derive(Serialize)]
+struct Verdict<'a> {
+    after: Option<&'a str>,
+    outcome: Outcome,
+}
+
+/// Save the text of a candidate checked in the run to `dir`, like `record`,
+/// along with its rewrite and outcome in a JSON file next to it. Both are
+/// named by a hash of the text instead of its file and option, so that
+/// they can be shared, and text seen before isn't saved again.
+pub fn dump(dir: &Path, chunk: &str, after: Option<&str>, outcome: Outcome) -> Result<(), ErrorKind> {
+    let hash = format!("{:x}", Sha256::digest(chunk));
+    let path = dir.join(format!("{}.nix", &hash[.. 16]));
+    if path.exists() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(dir).map_err(io(dir))?;
+    let json = serde_json::to_string_pretty(&Verdict { after, outcome }).map_err(|e| io(&path)(e.into()))?;
+    write_file(path.with_extension("json"), format!("{json}\n"))?;
+    write_file(path, format!("{chunk}\n"))
+}
+
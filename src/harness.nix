@@ -0,0 +1,34 @@
+# The options of `modules` evaluated on their own rather than with all of
+# NixOS, and rendered like those of the manual. Written into the tree for
+# `nix-doc-munge --import`. Definitions of options the modules don't declare
+# themselves are ignored, and those they read are stubbed by the attribute
+# paths in `stubs`, unless the modules declare them after all.
+{ modules, stubs ? [ ] }:
+let
+  pkgs = import ./.. { };
+  inherit (pkgs) lib;
+  base = { config, ... }: {
+    _module.check = false;
+    _module.args = {
+      inherit pkgs;
+      utils = import (pkgs.path + "/nixos/lib/utils.nix") { inherit lib config pkgs; };
+    };
+  };
+  declared = (lib.evalModules { modules = modules ++ [ base ]; }).options;
+  # an option at `path` or one of its prefixes, or options below it
+  isDeclared = path:
+    lib.hasAttrByPath path declared
+    || lib.any (n: lib.isOption (lib.attrByPath (lib.take n path) null declared))
+      (lib.range 1 (lib.length path));
+  stub = path: lib.setAttrByPath path (lib.mkOption {
+    type = lib.types.anything;
+    default = "‹${lib.concatStringsSep "." path}›";
+    visible = false;
+    internal = true;
+  });
+  stubbed.options = lib.foldl' lib.recursiveUpdate { } (map stub (lib.filter (path: !isDeclared path) stubs));
+  eval = lib.evalModules { modules = modules ++ [ base stubbed ]; };
+in (import (pkgs.path + "/nixos/lib/make-options-doc") {
+  inherit pkgs lib;
+  options = builtins.removeAttrs eval.options [ "_module" ];
+}).optionsDocBook
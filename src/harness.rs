@@ -0,0 +1,85 @@
+use std::{collections::BTreeSet, path::Path};
+
+use rnix::types::{Ident, Select, TokenWrapper, TypedNode};
+
+use crate::error::{ErrorKind, remove_file, write_file};
+
+/// Where the harness is written, in the root of the tree.
+const FILE: &str = ".nix-doc-munge-eval.nix";
+/// Where the stubs it is given are written.
+const STUBS: &str = ".nix-doc-munge-stubs.nix";
+
+const HARNESS: &str = include_str!("harness.nix");
+
+/// The attribute paths the Nix `content` reads from `config`, such as
+/// `networking.hostName` for `config.networking.hostName`. Only the longest
+/// of those sharing a prefix are kept, reading the prefix then gives a set
+/// of stubs.
+fn config_paths(content: &str) -> BTreeSet<Vec<String>> {
+    let Ok(ast) = rnix::parse(content).as_result() else {
+        return BTreeSet::new();
+    };
+    let mut paths = BTreeSet::new();
+    for node in ast.node().descendants() {
+        if Ident::cast(node.clone()).is_none_or(|ident| ident.as_str() != "config") {
+            continue;
+        }
+        let (mut path, mut at) = (vec![], node);
+        while let Some(select) = at.parent().and_then(Select::cast) {
+            let Some(index) = select.index().and_then(Ident::cast) else {
+                break;
+            };
+            if select.set().as_ref() != Some(&at) {
+                break;
+            }
+            path.push(index.as_str().to_string());
+            at = select.node().clone();
+        }
+        if path.first().is_some_and(|first| first != "_module") {
+            paths.insert(path);
+        }
+    }
+    let prefixes = paths.iter()
+        .filter(|p| paths.iter().any(|q| q.len() > p.len() && q.starts_with(p)))
+        .cloned()
+        .collect::<Vec<_>>();
+    for prefix in prefixes {
+        paths.remove(&prefix);
+    }
+    paths
+}
+
+/// Write the harness into the tree in `dir`, with stubs for the options
+/// the files of `contents` read.
+pub fn write(dir: &Path, contents: &[&str]) -> Result<(), ErrorKind> {
+    let stubs = contents.iter()
+        .flat_map(|content| config_paths(content))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|path| {
+            let attrs = path.iter().map(|attr| serde_json::to_string(attr).unwrap()).collect::<Vec<_>>();
+            format!("  [ {} ]\n", attrs.join(" "))
+        })
+        .collect::<String>();
+    write_file(dir.join(STUBS), format!("[\n{stubs}]\n"))?;
+    write_file(dir.join(FILE), HARNESS)
+}
+
+/// Whether the harness is written into the tree in `dir`.
+pub fn written(dir: &Path) -> bool {
+    dir.join(FILE).exists()
+}
+
+/// Remove the harness from the tree in `dir`, leaving its builds to the
+/// manual of the whole system.
+pub fn remove(dir: &Path) -> Result<(), ErrorKind> {
+    remove_file(dir.join(STUBS))?;
+    remove_file(dir.join(FILE))
+}
+
+/// The expression for the DocBook of the options of `imports`, the files of
+/// `--import`, evaluated by the harness instead of as part of the system.
+/// Much faster for a leaf module than evaluating the whole manual.
+pub fn expr(imports: &str) -> String {
+    format!("import ./{FILE} {{ modules = [ {imports} ]; stubs = import ./{STUBS}; }}")
+}
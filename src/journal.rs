@@ -0,0 +1,107 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    sync::Mutex,
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use nix_doc_munge::pass::common_affixes;
+
+use crate::error::{ErrorKind, io};
+
+/// An accepted change, as recorded in the journal.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum Change {
+    /// `old` at `start` in `file` was replaced by `new`.
+    Edit { file: String, start: usize, old: String, new: String },
+    /// Another file written, or removed if there are no contents.
+    File { file: String, content: Option<String> },
+}
+
+/// Append-only record of the changes accepted so far, so that an interrupted
+/// run can be completed with `--recover`. Removed once all changes have been
+/// written to the tree.
+pub struct Journal {
+    path: String,
+    file: Mutex<File>,
+}
+
+impl Journal {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = OpenOptions::new().write(true).create_new(true).open(path)
+            .with_context(|| format!("{path} exists, use --recover to apply its changes first"))?;
+        Ok(Self { path: path.to_string(), file: Mutex::new(file) })
+    }
+
+    fn append(&self, change: &Change) -> Result<(), ErrorKind> {
+        let mut line = serde_json::to_string(change).map_err(|e| io(&self.path)(e.into()))?;
+        line.push('\n');
+        // a single write per line, so lines of different workers don't mix
+        self.file.lock().unwrap().write_all(line.as_bytes()).map_err(io(&self.path))
+    }
+
+    /// Record that `file` went from `old` to `new`, and the other files the
+    /// change wrote.
+    pub fn record(&self, file: &str, old: &str, new: &str,
+                  files: &[(String, Option<String>)]) -> Result<(), ErrorKind> {
+        let (prefix, suffix) = common_affixes(old, new);
+        self.append(&Change::Edit {
+            file: file.to_string(),
+            start: prefix,
+            old: old[prefix .. old.len() - suffix].to_string(),
+            new: new[prefix .. new.len() - suffix].to_string(),
+        })?;
+        for (path, content) in files {
+            self.append(&Change::File { file: path.clone(), content: content.clone() })?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Apply the changes recorded in the journal at `path` to the tree, then
+/// remove it. Edits that don't match the file, such as those already
+/// applied by the run itself, are reported and left out.
+pub fn recover(path: &str) -> Result<()> {
+    let journal = fs::read_to_string(path)?;
+    let mut skipped = 0;
+    for (i, line) in journal.lines().enumerate() {
+        // the last line may have been cut short by the crash
+        let Ok(change) = serde_json::from_str::<Change>(line) else {
+            if i + 1 == journal.lines().count() {
+                break;
+            }
+            bail!("{path}:{}: malformed entry", i + 1);
+        };
+        match change {
+            Change::Edit { file, start, old, new } => {
+                let mut content = fs::read_to_string(&file)?;
+                if content.get(start .. start + old.len()) != Some(old.as_str()) {
+                    eprintln!("{file}: change at {start} doesn't apply, skipping");
+                    skipped += 1;
+                    continue;
+                }
+                content.replace_range(start .. start + old.len(), &new);
+                fs::write(&file, content)?;
+            }
+            Change::File { file, content: Some(content) } => fs::write(&file, content)?,
+            Change::File { file, content: None } => {
+                if fs::metadata(&file).is_ok() {
+                    fs::remove_file(&file)?;
+                }
+            }
+        }
+    }
+    if skipped > 0 {
+        bail!("{skipped} changes didn't apply, keeping {path}");
+    }
+    fs::remove_file(path)?;
+    Ok(())
+}
@@ -0,0 +1,1187 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{LazyLock, Mutex},
+};
+
+use regex::{Regex, RegexBuilder, Replacer};
+use rnix::{
+    types::{
+        Apply, AttrSet, BinOp, BinOpKind, EntryHolder, Ident, IfElse, Inherit, Lambda, LetIn, TokenWrapper, TypedNode, Select,
+        KeyValue, Paren, Pattern, With, Wrapper,
+    },
+    parser::ParseError, SyntaxKind, TextRange, SyntaxNode,
+};
+
+mod chapter;
+mod docbook;
+pub mod edit;
+mod md_to_db;
+pub mod nix_string;
+pub mod pass;
+mod reflow;
+mod rules;
+mod tag_map;
+pub mod trace;
+
+use nix_string::{NixString, StringKind};
+use pass::{Candidate, CandidateKind};
+use reflow::{reflow, tidy_whitespace};
+use rules::Rules;
+use tag_map::TagMap;
+use trace::fired;
+
+/// How many aliases deep call targets are followed, so that `let a = b; b =
+/// a;` can't loop forever.
+const ALIAS_DEPTH: u8 = 8;
+
+fn is_call_to(n: SyntaxNode, f: &str) -> bool {
+    match Apply::cast(n).and_then(|call| call.lambda()) {
+        Some(lambda) => is_reference_to(lambda, f, ALIAS_DEPTH),
+        None => false,
+    }
+}
+
+/// Whether `n` names the library function `f`, as `f`, `lib.f`,
+/// `lib.options.f`, `(import ./lib.nix).f` or through a `let` alias of any
+/// of these.
+fn is_reference_to(n: SyntaxNode, f: &str, depth: u8) -> bool {
+    if depth == 0 {
+        return false;
+    }
+    if let Some(id) = Ident::cast(n.clone()) {
+        return id.as_str() == f
+            || resolve_binding(&n, id.as_str()).is_some_and(|v| is_reference_to(v, f, depth - 1));
+    }
+    if let Some(sel) = Select::cast(n.clone()) {
+        return sel.index().and_then(Ident::cast).is_some_and(|i| i.as_str() == f)
+            && sel.set().is_some_and(|set| is_lib(set, depth - 1));
+    }
+    Paren::cast(n).and_then(|p| p.inner()).is_some_and(|inner| is_reference_to(inner, f, depth - 1))
+}
+
+/// Whether `n` is `lib` or part of it, like `lib.options`, an imported file
+/// of helpers, a `let` alias of these, or a free name inside `with lib;`.
+fn is_lib(n: SyntaxNode, depth: u8) -> bool {
+    if depth == 0 {
+        return false;
+    }
+    match n.kind() {
+        SyntaxKind::NODE_IDENT => {
+            let name = n.text().to_string();
+            if name == "lib" {
+                return true;
+            }
+            if let Some(value) = resolve_binding(&n, &name).or_else(|| resolve_inherit_from(&n, &name)) {
+                return is_lib(value, depth - 1);
+            }
+            !is_lambda_arg(&n, &name) && n.ancestors().skip(1).filter_map(With::cast).any(|with| {
+                with.namespace().is_some_and(|ns| {
+                    !ns.text_range().contains_range(n.text_range()) && is_lib(ns, depth - 1)
+                })
+            })
+        },
+        SyntaxKind::NODE_SELECT => {
+            Select::cast(n).and_then(|sel| sel.set()).is_some_and(|set| is_lib(set, depth - 1))
+        },
+        SyntaxKind::NODE_PAREN => {
+            Paren::cast(n).and_then(|p| p.inner()).is_some_and(|inner| is_lib(inner, depth - 1))
+        },
+        SyntaxKind::NODE_APPLY => {
+            Apply::cast(n).and_then(|call| call.lambda()).and_then(Ident::cast)
+                .is_some_and(|id| id.as_str() == "import")
+        },
+        _ => false,
+    }
+}
+
+/// The set a `let` or `rec` set enclosing `node` inherits `name` from, as
+/// in `inherit (lib) options;`.
+fn resolve_inherit_from(node: &SyntaxNode, name: &str) -> Option<SyntaxNode> {
+    node.ancestors().skip(1).find_map(|a| {
+        let inherits: Vec<_> = match (LetIn::cast(a.clone()), AttrSet::cast(a)) {
+            (Some(l), _) => l.inherits().collect(),
+            (_, Some(set)) if set.recursive() => set.inherits().collect(),
+            _ => return None,
+        };
+        inherits.into_iter()
+            .find(|i| i.idents().any(|id| id.as_str() == name))
+            .and_then(|i| i.from()?.inner())
+    })
+}
+
+/// Whether `name` is an argument of a function enclosing `node`, shadowing
+/// any `with` around it.
+fn is_lambda_arg(node: &SyntaxNode, name: &str) -> bool {
+    node.ancestors().filter_map(Lambda::cast).filter_map(|l| l.arg()).any(|arg| {
+        match (Ident::cast(arg.clone()), Pattern::cast(arg)) {
+            (Some(id), _) => id.as_str() == name,
+            (_, Some(pat)) => {
+                pat.at().is_some_and(|at| at.as_str() == name)
+                    || pat.entries().filter_map(|e| e.name()).any(|id| id.as_str() == name)
+            },
+            _ => false,
+        }
+    })
+}
+
+/// Whether `n` declares an option, with its argument being the option's
+/// attribute set.
+fn is_option_call(n: SyntaxNode) -> bool {
+    ["mkOption", "mkNullOrBoolOption", "mkNullOrStrOption", "mkInternalOption"]
+        .iter()
+        .any(|f| is_call_to(n.clone(), f))
+}
+
+/// Functions merging attribute sets, through which an option's attributes may
+/// be passed to `mkOption`.
+const MERGES: &[&str] = &["recursiveUpdate", "mergeAttrs"];
+
+/// The operands of `a // b` and of merge calls like `recursiveUpdate a b`,
+/// whose attributes end up in the same set.
+fn merged_operands(n: &SyntaxNode) -> Option<Vec<SyntaxNode>> {
+    if let Some(op) = BinOp::cast(n.clone()) {
+        return match op.operator() {
+            Some(BinOpKind::Update) => Some(op.lhs().into_iter().chain(op.rhs()).collect()),
+            _ => None,
+        };
+    }
+    let call = Apply::cast(n.clone())?;
+    let lambda = call.lambda()?;
+    if !MERGES.iter().any(|f| is_call_to(lambda.clone(), f)) {
+        return None;
+    }
+    let first = Apply::cast(lambda)?.value();
+    Some(first.into_iter().chain(call.value()).collect())
+}
+
+/// Whether `n` evaluates to an option declaration, like `mkOption { … }`
+/// or `(mkOption { … }) // { … }`.
+fn is_option_expr(n: &SyntaxNode) -> bool {
+    if let Some(inner) = Paren::cast(n.clone()).and_then(|p| p.inner()) {
+        return is_option_expr(&inner);
+    }
+    is_option_call(n.clone())
+        || merged_operands(n).is_some_and(|operands| operands.iter().any(is_option_expr))
+}
+
+// doesn't need to escape . because we're only interested in single-entry
+// paths anyway
+fn key_string(kv: &KeyValue) -> String {
+    kv.key().map_or_else(
+        String::new,
+        |kv| kv.path().map(|p| p.to_string()).collect::<Vec<_>>().join("."))
+}
+
+/// Attributes holding Nix values, in which only `literalDocBook` is
+/// documentation.
+const VALUE_ATTRS: &[&str] = &["defaultText", "example"];
+
+/// The candidate for the value of the `key` attribute of an option, if it is
+/// one of `doc_attrs` and not Markdown yet.
+fn doc_attr_candidate(key: &str, value: SyntaxNode, doc_attrs: &[String]) -> Option<(TextRange, CandidateKind)> {
+    if !doc_attrs.iter().any(|a| a == key) {
+        return None;
+    }
+    if is_call_to(value.clone(), "literalDocBook") {
+        return Some((value.text_range(), CandidateKind::LiteralDocBook));
+    }
+    if VALUE_ATTRS.contains(&key) || is_call_to(value.clone(), "mdDoc") {
+        return None;
+    }
+    Some((value.text_range(), CandidateKind::Description))
+}
+
+fn find_candidates(s: &str, doc_attrs: &[String]) -> Result<Vec<(TextRange, CandidateKind)>, ParseError> {
+    let ast = rnix::parse(s).as_result()?;
+    let mut nodes: VecDeque<_> = [(ast.node(), false)].into();
+    let mut result = vec![];
+
+    while let Some((node, parent_is_option)) = nodes.pop_front() {
+        // the attributes of an option may be merged from several sets before
+        // or after being passed to `mkOption`
+        if parent_is_option || is_option_expr(&node) {
+            if let Some(inner) = Paren::cast(node.clone()).and_then(|p| p.inner()) {
+                nodes.push_back((inner, true));
+                continue;
+            }
+            if let Some(operands) = merged_operands(&node) {
+                nodes.extend(operands.into_iter().map(|n| (n, true)));
+                continue;
+            }
+        }
+        if let Some(call) = Apply::cast(node.clone()) {
+            if let Some(arg) = call.value() {
+                nodes.push_back((
+                    arg.clone(),
+                    is_option_call(node.clone()),
+                ));
+                if is_call_to(node.clone(), "mkEnableOption")
+                    && Paren::cast(arg.clone()).is_none_or(|p| {
+                        !p.node().first_child().is_some_and(|inner| is_call_to(inner, "mdDoc"))
+                    })
+                {
+                    result.push((arg.text_range(), CandidateKind::EnableOption));
+                }
+                continue;
+            }
+        }
+        if let Some(attrs) = AttrSet::cast(node.clone()) {
+            if parent_is_option {
+                result.extend(attrs.entries().filter_map(|e| {
+                    doc_attr_candidate(&key_string(&e), e.value()?, doc_attrs)
+                }));
+                for inherit in attrs.inherits() {
+                    for id in inherit.idents() {
+                        let inherited = resolve_inherit(&node, &inherit, id.as_str())
+                            .filter(|v| v.kind() == SyntaxKind::NODE_STRING);
+                        result.extend(inherited.and_then(|v| doc_attr_candidate(id.as_str(), v, doc_attrs)));
+                    }
+                }
+            }
+        }
+
+        for c in node.children() {
+            nodes.push_back((c, false));
+        }
+    }
+
+    result.sort_by_key(|(r, _)| std::cmp::Reverse(r.start()));
+    // several options may inherit the same binding
+    result.dedup();
+    Ok(result)
+}
+
+/// The attribute path of the option containing `range`, e.g.
+/// `services.nginx.virtualHosts.<name>.root`. Keys of enclosing bindings
+/// are joined, leaving out `options` and the description or example itself,
+/// and the elements of `attrsOf` and `listOf` submodules show up as `<name>`
+/// and `*`.
+fn option_path(root: &SyntaxNode, range: TextRange) -> String {
+    let Some(node) = root.descendants().find(|n| n.text_range() == range) else {
+        return String::new();
+    };
+    let mut path = vec![];
+    for kv in node.ancestors().filter_map(KeyValue::cast) {
+        let key = key_string(&kv);
+        if key == "type" {
+            let ty = kv.value().map_or(String::new(), |v| v.text().to_string());
+            if ty.contains("attrsOf") {
+                path.push("<name>".to_string());
+            } else if ty.contains("listOf") {
+                path.push("*".to_string());
+            }
+            continue;
+        }
+        let key = key.strip_prefix("options.").unwrap_or(&key);
+        let leaf = key == "description" || VALUE_ATTRS.contains(&key);
+        if key == "options" || (path.is_empty() && leaf) {
+            continue;
+        }
+        path.push(key.to_string());
+    }
+    path.reverse();
+    path.join(".")
+}
+
+/// The value bound to `name` in the closest `let` or `rec` set enclosing
+/// `node`, if it is bound by a plain `name = …;`.
+fn resolve_binding(node: &SyntaxNode, name: &str) -> Option<SyntaxNode> {
+    node.ancestors().skip(1).find_map(|a| {
+        let entries = match (LetIn::cast(a.clone()), AttrSet::cast(a)) {
+            (Some(l), _) => l.entries(),
+            (_, Some(set)) if set.recursive() => set.entries(),
+            _ => return None,
+        };
+        entries.filter(|e| key_string(e) == name).find_map(|e| e.value())
+    })
+}
+
+/// The value `inherit` in the set `attrs` takes `name` from, for `inherit
+/// name;` and `inherit (set) name;` where `set` is bound to a set literal.
+fn resolve_inherit(attrs: &SyntaxNode, inherit: &Inherit, name: &str) -> Option<SyntaxNode> {
+    let Some(from) = inherit.from() else {
+        return resolve_binding(attrs, name);
+    };
+    let set = from.inner().and_then(Ident::cast)?;
+    let set = AttrSet::cast(resolve_binding(attrs, set.as_str())?)?;
+    set.entries().filter(|e| key_string(e) == name).find_map(|e| e.value())
+}
+
+/// Named entities we know how to decode. Anything else is left alone and will
+/// show up as a verification failure instead of being silently mangled.
+const ENTITIES: &[(&str, &str)] = &[
+    ("lt", "<"),
+    ("gt", ">"),
+    ("amp", "&"),
+    ("quot", "\""),
+    ("apos", "'"),
+    ("nbsp", "\u{a0}"),
+    ("ndash", "–"),
+    ("mdash", "—"),
+    ("hellip", "…"),
+    ("lsquo", "‘"),
+    ("rsquo", "’"),
+    ("ldquo", "“"),
+    ("rdquo", "”"),
+    ("copy", "©"),
+    ("reg", "®"),
+    ("trade", "™"),
+];
+
+/// Decode XML entities and character references. This must run exactly once
+/// over any text leaving the DocBook world, or `&amp;lt;` turns into `<`.
+fn decode_entities(s: &str) -> String {
+    static ENTITY: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r#"&(?:#([0-9]+)|#[xX]([0-9a-fA-F]+)|([a-zA-Z]+));"#).unwrap()
+    });
+    ENTITY
+        .replace_all(s, |caps: &regex::Captures<'_>| {
+            let num = match (caps.get(1), caps.get(2)) {
+                (Some(d), _) => d.as_str().parse().ok(),
+                (_, Some(x)) => u32::from_str_radix(x.as_str(), 16).ok(),
+                _ => None,
+            };
+            if let Some(c) = num.and_then(char::from_u32) {
+                return c.to_string();
+            }
+            caps.get(3)
+                .and_then(|name| ENTITIES.iter().find(|(n, _)| *n == name.as_str()))
+                .map_or_else(|| caps[0].to_string(), |(_, v)| v.to_string())
+        })
+        .into_owned()
+}
+
+/// Escape text so that it renders literally in the nixpkgs Markdown dialect.
+/// Characters that only matter in some positions (`_` inside words, `<` not
+/// starting a tag or autolink, block markers not at the start of a line) are
+/// left alone to keep the result readable.
+fn markdown_escape(s: &str) -> String {
+    escape_text(&decode_entities(s))
+}
+
+/// Marks the start of Markdown made by the conversion, which is left alone
+/// when the text around it is escaped.
+const MARKUP_START: char = '\u{E001}';
+/// Marks the end of Markdown made by the conversion.
+const MARKUP_END: char = '\u{E002}';
+
+/// `markdown` marked as made by the conversion.
+fn markup(markdown: &str) -> String {
+    format!("{MARKUP_START}{markdown}{MARKUP_END}")
+}
+
+/// Escape text like `markdown_escape` without decoding its entities, which
+/// Markdown decodes the same. Marked markup is kept as it is, along with its
+/// markers.
+fn escape_text(s: &str) -> String {
+    let is_marker = |c: &char| matches!(*c, MARKUP_START | MARKUP_END);
+    // markers aren't text, so they don't count as the neighbours of any
+    let chars = s.chars().filter(|c| !is_marker(c)).collect::<Vec<_>>();
+    let mut result = String::with_capacity(s.len());
+    let (mut i, mut depth, mut line_start) = (0usize, 0usize, true);
+    for c in s.chars() {
+        match c {
+            MARKUP_START => depth += 1,
+            MARKUP_END => depth = depth.saturating_sub(1),
+            _ => {},
+        }
+        if is_marker(&c) {
+            result.push(c);
+            continue;
+        }
+        let prev = i.checked_sub(1).map(|i| chars[i]);
+        let next = chars.get(i + 1).copied();
+        let escape = match c {
+            '\\' | '`' | '*' | '[' | ']' | '{' | '}' => true,
+            '_' => !(prev.is_some_and(char::is_alphanumeric)
+                     && next.is_some_and(char::is_alphanumeric)),
+            '<' => next.is_some_and(|n| n.is_ascii_alphabetic() || "/!?".contains(n)),
+            '#' | '>' | '|' => line_start,
+            '-' | '+' | '=' => line_start && next.is_none_or(|n| n == ' ' || n == c),
+            '.' | ')' => prev.is_some_and(|p| p.is_ascii_digit())
+                && next.is_none_or(|n| n == ' ')
+                && chars[.. i].iter().rev()
+                    .skip_while(|p| p.is_ascii_digit())
+                    .take_while(|&&p| p != '\n')
+                    .all(|&p| p == ' '),
+            _ => false,
+        };
+        if escape && depth == 0 {
+            result.push('\\');
+        }
+        result.push(c);
+        line_start = c == '\n' || (line_start && c == ' ');
+        i += 1;
+    }
+    result
+}
+
+/// Produces the emphasis of `<emphasis>` with the attributes in group 1 and
+/// the text in group 2: strong for the role `strong`, plain without a role.
+/// Other roles, like `bold` and `underline`, aren't what Markdown makes and
+/// are left alone.
+struct EmphasisPat;
+
+impl Replacer for EmphasisPat {
+    fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
+        static ROLE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"(?:^|\s)role\s*=\s*["']([^"']*)["']"#).unwrap()
+        });
+        let role = ROLE.captures(&caps[1]).map(|role| role.get(1).unwrap());
+        let delimiter = match role.map(|role| role.as_str()) {
+            None => "*",
+            Some("strong") => "**",
+            Some(_) => {
+                dst.push_str(&caps[0]);
+                return;
+            },
+        };
+        dst.push_str(&markup(&format!("{delimiter}{}{delimiter}", markdown_escape(&caps[2]))));
+    }
+}
+
+/// `text` escaped, but for its `<emphasis>`, which is converted.
+fn inline_markup(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = 0;
+    for caps in EMPHASIS.captures_iter(text) {
+        let m = caps.get(0).unwrap();
+        result.push_str(&markdown_escape(&text[rest .. m.start()]));
+        EmphasisPat.replace_append(&caps, &mut result);
+        rest = m.end();
+    }
+    result + &markdown_escape(&text[rest ..])
+}
+
+/// The Markdown of the text of a link. Markup in it, like code spans, was
+/// made by the rules before the link one and is kept, the rest is converted
+/// on its own.
+fn link_text(text: &str) -> String {
+    static MARKUP: LazyLock<Regex> = LazyLock::new(|| Regex::new("\u{E001}[^\u{E002}]*\u{E002}").unwrap());
+    let mut result = String::new();
+    let mut rest = 0;
+    for span in MARKUP.find_iter(text) {
+        result.push_str(&inline_markup(&text[rest .. span.start()]));
+        result.push_str(span.as_str());
+        rest = span.end();
+    }
+    result + &inline_markup(&text[rest ..])
+}
+
+/// Produces `[text](prefix target)` from a target in group 1 and optional
+/// link text in group 2. Targets only need their delimiters escaped.
+struct LinkPat(&'static str);
+
+impl Replacer for LinkPat {
+    fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
+        dst.push(MARKUP_START);
+        dst.push('[');
+        if let Some(text) = caps.get(2) {
+            dst.push_str(&link_text(text.as_str()));
+        }
+        dst.push_str("](");
+        dst.push_str(self.0);
+        dst.push_str(&decode_entities(&caps[1])
+            .replace('\\', "\\\\")
+            .replace('(', "\\(")
+            .replace(')', "\\)")
+            .replace(' ', "%20"));
+        dst.push(')');
+        dst.push(MARKUP_END);
+    }
+}
+
+/// Produces an `{option}` role from an `<xref>` to an option, and a link like
+/// `LinkPat("#")` from any other. Option ids spell `<name>` placeholders as
+/// `_name_`, these are turned back into the placeholders.
+struct OptionRefPat;
+
+impl Replacer for OptionRefPat {
+    fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
+        let Some(option) = caps[1].strip_prefix("opt-") else {
+            return LinkPat("#").replace_append(caps, dst);
+        };
+        let option = decode_entities(option).split('.')
+            .map(|part| match part.strip_prefix('_').and_then(|p| p.strip_suffix('_')) {
+                Some(name) if !name.is_empty() => format!("<{name}>"),
+                _ => part.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(".");
+        dst.push_str(&markup(&format!("{{option}}`{option}`")));
+    }
+}
+
+/// Produces `<url>`. Autolinks don't process backslash escapes at all, so the
+/// url is used verbatim and anything that can't be expressed is left to fail
+/// verification.
+struct AutolinkPat;
+
+impl Replacer for AutolinkPat {
+    fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
+        dst.push_str(&markup(&format!("<{}>", decode_entities(&caps[1]))));
+    }
+}
+
+/// Stands in for the backticks of the DocBook while code tags are converted,
+/// so that they can be told from those of the code spans made.
+const BACKTICK: char = '\u{E000}';
+
+/// Produces a code span with the given role, or a plain one if it's empty.
+/// Spans of code with backticks are delimited by more of them than it has
+/// in a row, and padded if it starts or ends with one.
+struct CodePat<'a>(&'a str);
+
+impl Replacer for CodePat<'_> {
+    fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
+        let code = decode_entities(&caps[1]).replace(BACKTICK, "`");
+        let longest = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+        let fence = "`".repeat(longest + 1);
+        let pad = match code.starts_with('`') || code.ends_with('`') {
+            true => " ",
+            false => "",
+        };
+        dst.push(MARKUP_START);
+        if !self.0.is_empty() {
+            dst.push('{');
+            dst.push_str(self.0);
+            dst.push('}');
+        }
+        dst.push_str(&fence);
+        dst.push_str(pad);
+        dst.push_str(&code);
+        dst.push_str(pad);
+        dst.push_str(&fence);
+        dst.push(MARKUP_END);
+    }
+}
+
+fn leading_space(line: &str) -> &str {
+    &line[.. line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+/// Turn `<para>` blocks into Markdown paragraphs. Each run of `<para>` and
+/// `</para>` tags is a paragraph boundary wherever it is on its line, so
+/// that text before, between and after them becomes paragraphs too. The
+/// whitespace around the value is kept, and the line after a boundary is
+/// indented like the line it starts on, so that it can't become a code
+/// block.
+fn convert_paragraphs(s: &str) -> String {
+    static BOUNDARY: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s*(</?para>\s*)+").unwrap());
+    static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"</?para>").unwrap());
+    let mut result = String::new();
+    let mut rest = 0;
+    for m in BOUNDARY.find_iter(s) {
+        result.push_str(&s[rest .. m.start()]);
+        rest = m.end();
+        let whitespace = TAG.replace_all(m.as_str(), "");
+        if m.start() == 0 || m.end() == s.len() {
+            result.push_str(&whitespace);
+            continue;
+        }
+        let line = &s[s[.. m.start()].rfind('\n').map_or(0, |i| i + 1) ..];
+        result.push_str("\n\n");
+        result.push_str(leading_space(line));
+    }
+    result + &s[rest ..]
+}
+
+/// Turn `<programlisting>` blocks into fenced code blocks, moving the fences
+/// onto lines of their own indented like the line the listing starts on.
+fn convert_code_blocks(s: &str) -> String {
+    const OPEN: &str = "<programlisting>";
+    const CLOSE: &str = "</programlisting>";
+
+    let mut result = String::new();
+    let mut rest = s;
+    while let Some(open) = rest.find(OPEN) {
+        let Some(close) = rest[open ..].find(CLOSE).map(|i| open + i) else {
+            break;
+        };
+        let before = &rest[.. open];
+        let line = &before[before.rfind('\n').map_or(0, |i| i + 1) ..];
+        let indent = leading_space(line);
+        let body = &rest[open + OPEN.len() .. close];
+        let after = &rest[close + CLOSE.len() ..];
+
+        if line.trim().trim_start_matches(['"', '\'']).is_empty() {
+            result.push_str(before);
+        } else {
+            result.push_str(before.trim_end_matches([' ', '\t']));
+            result.push('\n');
+            result.push_str(indent);
+        }
+        result.push(MARKUP_START);
+        result.push_str("```");
+        if !body.starts_with('\n') {
+            result.push('\n');
+            result.push_str(indent);
+        }
+        result.push_str(body);
+        if !body.trim_end_matches([' ', '\t']).ends_with('\n') {
+            result.push('\n');
+            result.push_str(indent);
+        }
+        result.push_str("```");
+        result.push(MARKUP_END);
+
+        let after_line = after.split('\n').next().unwrap_or("");
+        rest = if after_line.trim().is_empty() {
+            after
+        } else {
+            result.push('\n');
+            result.push_str(indent);
+            after.trim_start_matches([' ', '\t'])
+        };
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Indent every line of `converted` by at least `indent` spaces. Lines added
+/// by the conversion start at column zero, which would otherwise lower the
+/// common indentation of an indented string and shift all other lines.
+fn indent_new_lines(converted: &str, indent: usize) -> String {
+    converted.split('\n')
+        .enumerate()
+        .map(|(i, line)| {
+            let have = line.len() - line.trim_start_matches(' ').len();
+            if i == 0 || line.trim().is_empty() || have >= indent {
+                line.to_string()
+            } else {
+                " ".repeat(indent - have) + line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Put the contents of an indented string on lines of their own, between the
+/// line of the opening quotes and a closing line indented by `indent`.
+fn tidy_string_ends(value: &str, indent: &str) -> String {
+    let (first, rest) = value.split_once('\n').unwrap_or((value, ""));
+    let mut result = String::new();
+    if !first.trim().is_empty() {
+        let common = rest.split('\n')
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| l.len() - l.trim_start_matches(' ').len())
+            .min()
+            .unwrap_or(indent.len() + 2);
+        result.push('\n');
+        result.push_str(&" ".repeat(common));
+        result.push_str(first.trim_start());
+    } else {
+        result.push_str(first);
+    }
+    if !rest.is_empty() || value.contains('\n') {
+        result.push('\n');
+        result.push_str(rest);
+    }
+    let last = result.rsplit('\n').next().unwrap_or("");
+    if last.trim().is_empty() {
+        result.truncate(result.len() - last.len());
+    } else {
+        result.push('\n');
+    }
+    result.push_str(indent);
+    result
+}
+
+const ADMONITIONS: [&str; 3] = ["note", "warning", "important"];
+
+/// Find the innermost admonition in `s`, returning its kind, the start of its
+/// opening tag, the start of its closing tag and the end of its closing tag.
+fn innermost_admonition(s: &str) -> Option<(&'static str, usize, usize, usize)> {
+    let (close, kind) = ADMONITIONS.iter()
+        .filter_map(|&k| s.find(&format!("</{k}>")).map(|i| (i, k)))
+        .min()?;
+    let (open, open_kind) = ADMONITIONS.iter()
+        .filter_map(|&k| s[.. close].rfind(&format!("<{k}>")).map(|i| (i, k)))
+        .max()?;
+    if open_kind != kind {
+        return None;
+    }
+    Some((kind, open, close, close + kind.len() + 3))
+}
+
+/// Turn the contents of an admonition into Markdown paragraphs, stripping the
+/// `<para>` wrappers and the indentation common to all continuation lines.
+fn admonition_body(body: &str) -> Vec<String> {
+    let indent_of = |l: &str| l.len() - l.trim_start_matches([' ', '\t']).len();
+    let common = body.lines()
+        .skip(1)
+        .filter(|l| !matches!(l.trim(), "" | "<para>" | "</para>"))
+        .map(indent_of)
+        .min()
+        .unwrap_or(0);
+    let body = body.lines()
+        .enumerate()
+        .map(|(i, l)| match i {
+            0 => l,
+            _ => &l[common.min(indent_of(l)) ..],
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    static PARA: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(\s*</?para>)+\s*"#).unwrap());
+    let body = PARA.replace_all(&body, "\n\n");
+    body.trim()
+        .lines()
+        .map(|l| l.trim_end().to_string())
+        .collect()
+}
+
+/// Convert `<note>`, `<warning>` and `<important>` blocks into fenced divs,
+/// innermost first so that enclosing blocks can use longer fences.
+fn convert_admonitions(s: &str) -> String {
+    let mut s = s.to_string();
+    while let Some((kind, open, close, end)) = innermost_admonition(&s) {
+        let line_start = s[.. open].rfind('\n').map_or(0, |i| i + 1);
+        let before = &s[line_start .. open];
+        let indent = leading_space(before);
+        let lines = admonition_body(&s[open + kind.len() + 2 .. close]);
+        // the opening quote of the string itself doesn't count as preceding text
+        let at_line_start = before.trim().trim_start_matches(['"', '\'']).trim().is_empty();
+
+        let nested = lines.iter()
+            .map(|l| l.len() - l.trim_start_matches(':').len())
+            .max()
+            .unwrap_or(0);
+        let fence = ":".repeat(nested.max(2) + 1);
+
+        let mut block = String::new();
+        let mut open = open;
+        if !at_line_start {
+            open = line_start + before.trim_end().len();
+            block.push_str("\n\n");
+            block.push_str(indent);
+        }
+        block.push_str(&markup(&format!("{fence} {{.{kind}}}")));
+        block.push('\n');
+        for l in &lines {
+            if !l.is_empty() {
+                block.push_str(indent);
+                block.push_str(l);
+            }
+            block.push('\n');
+        }
+        block.push_str(indent);
+        block.push_str(&fence);
+        let rest = s[end ..].trim_start_matches([' ', '\t']);
+        let mut end = end;
+        if !rest.starts_with('\n') && !rest.trim().is_empty() {
+            block.push_str("\n\n");
+            block.push_str(indent);
+            end = s.len() - rest.len();
+        }
+
+        s.replace_range(open .. end, &block);
+    }
+    s
+}
+
+/// Build one of the conversion patterns, which all match across lines.
+fn markup_regex(pattern: &str) -> Regex {
+    RegexBuilder::new(pattern)
+        .multi_line(true)
+        .dot_matches_new_line(true)
+        .ignore_whitespace(pattern.starts_with('\n'))
+        .build().unwrap()
+}
+
+/// Any attributes of an inline tag, like `remap` or `xml:space` on those of
+/// real descriptions. Patterns accept and drop them, but for those they use.
+const ATTRS: &str = r#"(?:\s+[\w:.-]+\s*=\s*(?:"[^"]*"|'[^']*'))*"#;
+
+static AUTOLINK: LazyLock<Regex> = LazyLock::new(|| {
+    markup_regex(&format!(r#"<link{ATTRS}\s+xlink:href="([^"]+)"{ATTRS}\s*/>"#))
+});
+static LINK: LazyLock<Regex> = LazyLock::new(|| {
+    markup_regex(&format!(r#"<link{ATTRS}\s+xlink:href="([^"]+)"{ATTRS}\s*>(.*?)</link>"#))
+});
+static XREF: LazyLock<Regex> = LazyLock::new(|| {
+    markup_regex(&format!(r#"<xref{ATTRS}\s+linkend="([^"]+)"{ATTRS}\s*/>"#))
+});
+static OPTION_PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| {
+    markup_regex(&format!(r#"<option{ATTRS}\s*>(?:[^<`]|<replaceable{ATTRS}\s*>[^<`]*</replaceable>)*</option>"#))
+});
+static REPLACEABLE: LazyLock<Regex> = LazyLock::new(|| {
+    markup_regex(&format!(r#"<replaceable{ATTRS}\s*>([^<`]*)</replaceable>"#))
+});
+static LINKEND: LazyLock<Regex> = LazyLock::new(|| {
+    markup_regex(&format!(r#"<link{ATTRS}\s+linkend="([^"]+)"{ATTRS}\s*>(.*?)</link>"#))
+});
+static EMPHASIS: LazyLock<Regex> = LazyLock::new(|| {
+    markup_regex(&format!(r#"<emphasis({ATTRS})\s*>([^*]*?)</emphasis>"#))
+});
+static CITEREFENTRY: LazyLock<Regex> = LazyLock::new(|| markup_regex(r#"
+    <citerefentry(?:\s[^>]*)?>\s*
+        <refentrytitle(?:\s[^>]*)?>\s*(.*?)\s*</refentrytitle>\s*
+        <manvolnum(?:\s[^>]*)?>\s*(.*?)\s*</manvolnum>\s*
+    </citerefentry>"#));
+
+/// The pattern matching a code tag. Tags come from the tag map, so these are
+/// compiled on first use and kept around for all further conversions.
+fn code_tag_regex(tag: &str) -> Regex {
+    static CACHE: LazyLock<Mutex<HashMap<String, Regex>>> = LazyLock::new(Default::default);
+    CACHE.lock().unwrap()
+        .entry(tag.to_string())
+        .or_insert_with(|| markup_regex(&format!(r#"<{tag}{ATTRS}\s*>([^`]*?)</{tag}>"#)))
+        .clone()
+}
+
+/// Convert the DocBook markup in `chunk` to Markdown. With `option_refs`,
+/// `<xref>`s to options become `{option}` roles too. The Markdown made is
+/// marked, to be escaped around by `escape_prose` or unmarked.
+fn convert_markup(chunk: &str, tags: &TagMap, option_refs: bool) -> String {
+    let mut new_chunk = chunk.replace('`', &BACKTICK.to_string());
+    if option_refs {
+        // `<option>services.<replaceable>name</replaceable>.enable</option>`
+        let new = OPTION_PLACEHOLDER.replace_all(&new_chunk, |caps: &regex::Captures<'_>| {
+            REPLACEABLE.replace_all(&caps[0], "&lt;$1&gt;").into_owned()
+        });
+        new_chunk = fired("<replaceable>", &new_chunk, new);
+    }
+    for (tag, role) in &tags.code {
+        let new = code_tag_regex(tag).replace_all(&new_chunk, CodePat(role));
+        new_chunk = fired(&format!("<{tag}>"), &new_chunk, new);
+    }
+    let new_chunk = new_chunk.replace(BACKTICK, "`");
+    // before links, which keep the code span it becomes in their text
+    let new = CITEREFENTRY.replace_all(&new_chunk, |caps: &regex::Captures<'_>| {
+        markup(&format!("{{manpage}}`{}({})`", decode_entities(&caps[1]), decode_entities(&caps[2])))
+    });
+    let new_chunk = fired("<citerefentry>", &new_chunk, new);
+    let new_chunk = fired("autolink", &new_chunk, AUTOLINK.replace_all(&new_chunk, AutolinkPat));
+    let new_chunk = fired("<link>", &new_chunk, LINK.replace_all(&new_chunk, LinkPat("")));
+    let new = match option_refs {
+        true => XREF.replace_all(&new_chunk, OptionRefPat),
+        false => XREF.replace_all(&new_chunk, LinkPat("#")),
+    };
+    let new_chunk = fired("<xref>", &new_chunk, new);
+    let new_chunk = fired("<link linkend>", &new_chunk, LINKEND.replace_all(&new_chunk, LinkPat("#")));
+    let new_chunk = fired("<emphasis>", &new_chunk, EMPHASIS.replace_all(&new_chunk, EmphasisPat));
+    // before code blocks, whose `<para>` are still entities
+    let new_chunk = fired("<para>", &new_chunk, convert_paragraphs(&new_chunk).into());
+    let new_chunk = fired("code blocks", &new_chunk, convert_code_blocks(&new_chunk).into());
+    fired("admonitions", &new_chunk, convert_admonitions(&new_chunk).into())
+}
+
+/// `converted` without the marks of the markup made by `convert_markup`.
+fn unmarked(converted: &str) -> String {
+    converted.replace([MARKUP_START, MARKUP_END], "")
+}
+
+/// The Markdown of `converted`, the value of a string as `convert_markup`
+/// left it, with the text between the markup it made escaped.
+fn escape_prose(converted: &str) -> String {
+    // the rest is text, but for tags no rule converted
+    static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"</?[a-zA-Z][^<>]*>").unwrap());
+    let escaped = escape_text(&TAG.replace_all(converted, |caps: &regex::Captures<'_>| markup(&caps[0])));
+    fired("escapes", &unmarked(converted), unmarked(&escaped).into())
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "cli", derive(clap::Args))]
+pub struct ConvertOptions {
+    /// Rewrite converted multi-line `"…"` descriptions into `''…''` strings.
+    #[cfg_attr(feature = "cli", arg(long))]
+    indented_strings: bool,
+    /// Re-wrap converted paragraphs in `''…''` strings to this many columns.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "COLUMNS"))]
+    wrap: Option<usize>,
+    /// TOML file overriding how inline tags are mapped to Markdown roles.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "FILE", value_parser = TagMap::load))]
+    tag_map: Option<TagMap>,
+    /// TOML file of replacements, or commands, to apply to descriptions
+    /// before and after their conversion, for markup of the tree's own.
+    #[cfg_attr(feature = "cli", arg(long, value_name = "FILE", value_parser = Rules::load))]
+    rules: Option<Rules>,
+    /// Turn `<xref>`s to options into `{option}` roles rather than links,
+    /// with `<name>` placeholders in place of their `_name_` in the id.
+    #[cfg_attr(feature = "cli", arg(long))]
+    option_refs: bool,
+    /// Option attribute holding documentation to convert, like one rendered
+    /// through `extraOptionValues`. Only `literalDocBook` values are
+    /// converted in `defaultText` and `example`.
+    #[cfg_attr(feature = "cli", arg(long = "doc-attr", value_name = "NAME", default_values = DOC_ATTRS))]
+    doc_attrs: Vec<String>,
+}
+
+const DOC_ATTRS: [&str; 3] = ["description", "defaultText", "example"];
+
+impl Default for ConvertOptions {
+    fn default() -> Self {
+        ConvertOptions {
+            indented_strings: false,
+            wrap: None,
+            tag_map: None,
+            rules: None,
+            option_refs: false,
+            doc_attrs: DOC_ATTRS.map(String::from).into(),
+        }
+    }
+}
+
+impl ConvertOptions {
+    /// Option attributes whose values are converted by the `md-doc` pass.
+    pub fn doc_attrs(&self) -> &[String] {
+        &self.doc_attrs
+    }
+}
+
+/// Whether converted Markdown contains anything but a single paragraph.
+fn has_blocks(value: &str) -> bool {
+    let value = value.trim();
+    value.contains("\n\n")
+        || value.lines().any(|l| {
+            let l = l.trim_start();
+            l.starts_with(":::") || l.starts_with("```")
+        })
+}
+
+/// Remove the indentation common to all non-blank lines of `s`.
+fn dedent(s: &str) -> String {
+    let common = s.lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start_matches(' ').len())
+        .min()
+        .unwrap_or(0);
+    s.lines()
+        .map(|l| l.get(common ..).unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// `mkEnableOption` splices its argument into "Whether to enable …." which
+/// can't work once the argument contains more than one paragraph. Give it
+/// just the first paragraph and override the description with the full text,
+/// producing the replacement for the argument.
+fn enable_option_override(string: &NixString, value: &str, indent: &str) -> String {
+    let text = dedent(value.trim_matches('\n'));
+    let lines = text.trim().lines().collect::<Vec<_>>();
+    let split = lines.iter()
+        .position(|l| l.trim().is_empty() || l.starts_with(":::") || l.starts_with("```"))
+        .unwrap_or(lines.len());
+    let (first, rest) = lines.split_at(split);
+    let first = first.join(" ");
+    let rest = rest.join("\n");
+    let rest = rest.trim_start_matches('\n');
+    let name = first.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    let mut description = format!("Whether to enable {name}.");
+    if !rest.is_empty() {
+        description.push_str("\n\n");
+        description.push_str(rest);
+    }
+    description.push('\n');
+
+    format!("(lib.mdDoc {}) // {{\n{indent}  description = lib.mdDoc {};\n{indent}}}",
+            string.encode_quoted(&name),
+            string.encode_indented(&description, &format!("{indent}  ")))
+}
+
+/// The string literals a conditional description can evaluate to: both
+/// branches of an `if`, nested ones included, or the text of an
+/// `optionalString`. Only the branch taken when building the manual is
+/// verified by the build.
+fn conditional_branches(node: SyntaxNode) -> Option<Vec<SyntaxNode>> {
+    if let Some(p) = Paren::cast(node.clone()) {
+        return conditional_branches(p.inner()?);
+    }
+    if let Some(i) = IfElse::cast(node.clone()) {
+        let mut result = vec![];
+        for branch in [i.body()?, i.else_body()?] {
+            match branch.kind() {
+                SyntaxKind::NODE_STRING => result.push(branch),
+                _ => result.extend(conditional_branches(branch)?),
+            }
+        }
+        return Some(result);
+    }
+    let call = Apply::cast(node)?;
+    if !is_call_to(call.lambda()?, "optionalString") {
+        return None;
+    }
+    let text = call.value()?;
+    (text.kind() == SyntaxKind::NODE_STRING).then(|| vec![text])
+}
+
+/// Encode the converted `value` of `string`, whose binding is indented by
+/// `indent`, laying it out as the conversion options ask.
+fn encode_converted(string: &NixString, mut value: String, indent: &str,
+                    opts: &ConvertOptions) -> String {
+    if string.kind == StringKind::Indented {
+        value = indent_new_lines(&value, string.common_indent());
+        value = tidy_string_ends(&value, indent);
+    }
+    if opts.indented_strings && string.kind == StringKind::Quoted && value.contains('\n') {
+        if let Some(width) = opts.wrap {
+            value = fired("reflow", &value, reflow(&value, width.saturating_sub(indent.len() + 2)).into());
+        }
+        string.encode_indented(&value, indent)
+    } else {
+        if let (Some(width), StringKind::Indented) = (opts.wrap, string.kind) {
+            value = fired("reflow", &value, reflow(&value, width).into());
+        }
+        string.encode(&value)
+    }
+}
+
+/// Wrap the description `candidate` of `s` in `lib.mdDoc`, converting its
+/// DocBook to Markdown, and return all of `s` with the replacement. For a
+/// `literalDocBook` call, its argument is converted and the call becomes one
+/// to `literalMD` instead.
+pub fn convert_one(s: &str, candidate: &Candidate, opts: &ConvertOptions) -> String {
+    let node = rnix::parse(s).as_result().ok()
+        .and_then(|ast| ast.node().descendants().find(|n| n.text_range() == candidate.range));
+    let (call_start, pos, wrapper, add_parens) = match literal_docbook_call(s, candidate) {
+        // only the function called changes, the call stays where it was
+        Some((lambda, arg)) => (candidate.range.start(), arg, lambda.replace("literalDocBook", "literalMD") + " ", false),
+        None => {
+            let add_parens = node.as_ref().map_or(candidate.kind.needs_parens(), is_argument);
+            (candidate.range.start(), candidate.range, "lib.mdDoc ".to_string(), add_parens)
+        },
+    };
+    let prefix = &s[.. call_start.into()];
+    let chunk = &s[pos.start().into() .. pos.end().into()];
+    let suffix = &s[usize::from(pos.end()) ..];
+
+    let line = &prefix[prefix.rfind('\n').map_or(0, |i| i + 1) ..];
+    let indent = &line[.. line.len() - line.trim_start_matches(' ').len()];
+    let branches = rnix::parse(chunk).as_result().ok()
+        .and_then(|ast| conditional_branches(ast.node().first_child()?));
+    // work on the string's value where we can so that Markdown escapes and
+    // quotes produced by the conversion are encoded properly for Nix.
+    let new_chunk = match (NixString::parse(chunk), branches) {
+        (Some(string), _) => {
+            let value = convert_docbook(&string.value, opts);
+            if candidate.kind == CandidateKind::EnableOption && has_blocks(&value) {
+                return prefix.to_owned()
+                    + &enable_option_override(&string, &value, indent)
+                    + suffix;
+            }
+            encode_converted(&string, value, indent, opts)
+        },
+        (None, Some(branches)) => {
+            let mut new_chunk = chunk.to_string();
+            for branch in branches.iter().rev() {
+                let range = branch.text_range();
+                let Some(string) = NixString::parse(&branch.text().to_string()) else {
+                    continue;
+                };
+                let value = convert_docbook(&string.value, opts);
+                new_chunk.replace_range(usize::from(range.start()) .. range.end().into(),
+                                        &encode_converted(&string, value, indent, opts));
+            }
+            format!("({new_chunk})")
+        },
+        (None, None) => {
+            // the text is Nix source here, which can't be escaped as Markdown
+            let new_chunk = unmarked(&convert_markup(chunk, &opts.tag_map.clone().unwrap_or_default(), opts.option_refs));
+            match node.as_ref().is_none_or(binds_tightly) {
+                true => new_chunk,
+                false => format!("({new_chunk})"),
+            }
+        },
+    };
+
+    let (lpar, rpar) = if add_parens {
+        ("(", ")")
+    } else {
+        ("", "")
+    };
+
+    prefix.to_owned()
+        + lpar
+        + &wrapper
+        + &new_chunk
+        + rpar
+        + suffix
+}
+
+/// Whether a call in place of `node` has to be parenthesized, `node` being
+/// the argument of a call, like that of `mkEnableOption`, the default of an
+/// `or` or an element of a list.
+fn is_argument(node: &SyntaxNode) -> bool {
+    node.parent().is_some_and(|parent| match parent.kind() {
+        SyntaxKind::NODE_APPLY | SyntaxKind::NODE_OR_DEFAULT => parent.first_child().as_ref() != Some(node),
+        SyntaxKind::NODE_LIST => true,
+        _ => false,
+    })
+}
+
+/// Whether `node` can be the argument of a call as it is, without being
+/// parenthesized.
+fn binds_tightly(node: &SyntaxNode) -> bool {
+    matches!(node.kind(),
+        SyntaxKind::NODE_STRING | SyntaxKind::NODE_IDENT | SyntaxKind::NODE_SELECT
+        | SyntaxKind::NODE_OR_DEFAULT | SyntaxKind::NODE_PAREN | SyntaxKind::NODE_LIST
+        | SyntaxKind::NODE_ATTR_SET | SyntaxKind::NODE_LITERAL)
+}
+
+/// The Markdown `convert_one` makes of the DocBook `docbook`, the value of
+/// a description rather than the Nix string of one, with the rules of
+/// `--rules` applied around the conversion.
+pub fn convert_docbook(docbook: &str, opts: &ConvertOptions) -> String {
+    let rules = opts.rules.clone().unwrap_or_default();
+    let tags = opts.tag_map.clone().unwrap_or_default();
+    let value = escape_prose(&convert_markup(&rules.pre(docbook), &tags, opts.option_refs));
+    let value = fired("whitespace", &value, tidy_whitespace(&value).into());
+    rules.post(&value)
+}
+
+/// Whether the documentation `candidate` of `s` is a single paragraph of
+/// text without any markup, which Markdown renders the same as DocBook, so
+/// that converting it only wraps it in `lib.mdDoc`. Characters Markdown
+/// could read as markup count as markup.
+pub fn is_plain_text(s: &str, candidate: &Candidate) -> bool {
+    static LIST_ITEM: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^(\d+[.)]|[-+=>])").unwrap());
+    let range = literal_docbook_call(s, candidate).map_or(candidate.range, |(_, arg)| arg);
+    let Some(string) = s.get(usize::from(range.start()) .. range.end().into()).and_then(NixString::parse) else {
+        return false;
+    };
+    let value = string.value.trim();
+    !string.has_interpolations()
+        && !value.contains(['<', '>', '&', '*', '_', '`', '[', ']', '\\', '#', '|', '~', '{', '}'])
+        && !value.lines().any(|l| l.trim().is_empty() || LIST_ITEM.is_match(l.trim_start()))
+}
+
+/// The function called and the range of its argument in `s` for a
+/// `literalDocBook` candidate.
+fn literal_docbook_call(s: &str, candidate: &Candidate) -> Option<(String, TextRange)> {
+    if candidate.kind != CandidateKind::LiteralDocBook {
+        return None;
+    }
+    let ast = rnix::parse(&s[candidate.range]).as_result().ok()?;
+    let call = Apply::cast(ast.node().first_child()?)?;
+    let arg = call.value()?.text_range() + candidate.range.start();
+    Some((call.lambda()?.text().to_string(), arg))
+}
+
+/// Filter out inconsequential differences. The contents of `<programlisting>`
+/// and `<screen>` are kept as they are, any change to them shows.
+pub fn normalize(xml: &str) -> String {
+    static VERBATIM: LazyLock<Regex> = LazyLock::new(|| {
+        markup_regex(r"<programlisting[\s>].*?</programlisting>|<screen[\s>].*?</screen>")
+    });
+    let mut result = String::new();
+    let mut rest = 0;
+    for m in VERBATIM.find_iter(xml) {
+        result.push_str(&normalize_text(&xml[rest .. m.start()]));
+        result.push_str(m.as_str());
+        rest = m.end();
+    }
+    result + &normalize_text(&xml[rest ..])
+}
+
+fn normalize_text(xml: &str) -> String {
+    xml
+        .replace(['‘', '’'], "'")
+        .replace(['“', '”'], "\"")
+        .replace('…', "...")
+        // HACK: We get additional whitespace for DocBook
+        // descriptions in the nix-darwin manual for some reason.
+        .replace("<para>\n", "<para>")
+        .replace("\n</para>", "</para>")
+}
@@ -0,0 +1,34 @@
+use std::{fs, io::ErrorKind, process};
+
+use anyhow::{Result, bail};
+
+const LOCK: &str = "munge.lock";
+
+/// Held for the whole run so that two runs in the same tree don't write to
+/// the same files and `munge-failures/` directories. Removed when dropped.
+pub struct RunLock;
+
+impl RunLock {
+    pub fn acquire(force: bool) -> Result<Self> {
+        match fs::OpenOptions::new().write(true).create_new(true).open(LOCK) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                let pid = fs::read_to_string(LOCK).unwrap_or_default();
+                if !force {
+                    bail!("another run (pid {}) holds {LOCK}, use --force if it is gone",
+                          pid.trim());
+                }
+                eprintln!("warning: ignoring {LOCK} of pid {}", pid.trim());
+            }
+            Err(e) => return Err(e.into()),
+        }
+        fs::write(LOCK, process::id().to_string())?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(LOCK);
+    }
+}
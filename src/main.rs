@@ -1,475 +1,1792 @@
-use std::{collections::VecDeque, env, fs, process::Command, sync::{Arc, Mutex}, path::Path};
+use std::{
+    cell::Cell,
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex, OnceLock},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
 
-use anyhow::{Result, bail};
-use regex::{RegexBuilder, Replacer};
-use rnix::{
-    types::{Apply, AttrSet, EntryHolder, Ident, TokenWrapper, TypedNode, Select, KeyValue, Paren},
-    SyntaxKind, TextRange, SyntaxNode,
+use anyhow::{Context, Result, bail};
+use clap::{CommandFactory, Parser};
+use nix_doc_munge::{
+    ConvertOptions, is_plain_text, normalize,
+    edit::{Edit, EditList},
+    pass::{Candidate, Candidates, Pass, Rewrite, common_affixes, text_at},
+    trace,
 };
-use tempfile::tempdir;
+use regex::Regex;
+use rnix::TextRange;
+use similar::TextDiff;
+use tempfile::{TempDir, tempdir};
 use threadpool::ThreadPool;
 
-struct StatusReportData {
-    files: usize,
-    items: usize,
-    total_files: usize,
-    total_items: usize,
-    changed_items: usize,
-    last_file: String,
-    last_item: String,
+mod allow_list;
+mod cache;
+mod config;
+mod diff_manuals;
+mod doctor;
+mod error;
+mod final_check;
+mod fixture;
+mod harness;
+mod journal;
+mod lock;
+mod notify;
+mod options_db;
+mod report;
+mod scan;
+mod split;
+mod status;
+mod summary;
+mod tui;
+mod watch;
+
+use allow_list::AllowList;
+use cache::CandidateCache;
+use error::{Error, ErrorKind, io, parse, remove_file, write_file};
+use journal::Journal;
+use lock::RunLock;
+use notify::Notifier;
+use options_db::OptionsDb;
+use report::{Outcome, Report};
+use scan::UnsupportedTags;
+use split::{SplitBy, SplitOutput};
+use status::{StatusPart, StatusReport};
+
+#[derive(clap::Args, Clone, Debug, Default)]
+struct BuildOptions {
+    /// Build the manuals in this store, such as `ssh-ng://builder`, instead
+    /// of the local one.
+    #[arg(long, value_name = "URL")]
+    store: Option<String>,
+    /// Machines to build the manuals on, see `nix-build --builders`.
+    #[arg(long, value_name = "SPEC")]
+    builders: Option<String>,
+    /// Number of local build jobs, 0 to only build remotely and substitute
+    /// everything else.
+    #[arg(long, value_name = "N")]
+    max_jobs: Option<usize>,
+    /// Additional binary cache to substitute from.
+    #[arg(long, value_name = "URL")]
+    extra_substituter: Vec<String>,
+    /// Key to trust for the additional binary caches.
+    #[arg(long, value_name = "KEY")]
+    extra_trusted_public_key: Vec<String>,
+    /// Any other Nix setting for the builds. The variable takes several,
+    /// separated by `;`.
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_nix_option,
+          env = "NIX_DOC_MUNGE_NIX_OPTIONS", value_delimiter = ';')]
+    nix_option: Vec<(String, String)>,
+    /// Environment variable to run Nix with, such as
+    /// `NIXPKGS_ALLOW_UNFREE=1` for trees that fail to evaluate without it.
+    #[arg(long, value_name = "NAME=VALUE", value_parser = parse_nix_option)]
+    nix_env: Vec<(String, String)>,
+    /// Directory of the Nix commands to run, instead of those on `PATH`.
+    #[arg(long, value_name = "DIR", env = "NIX_DOC_MUNGE_NIX_BIN")]
+    nix_bin: Option<PathBuf>,
+    /// Expression to build the manual with instead of that of NixOS, with
+    /// `@attr@` for the output to compare, such as `optionsDocBook`, and
+    /// `@imports@` for the files of `--import`.
+    #[arg(long, value_name = "EXPR")]
+    manual_expr: Option<String>,
+    /// Evaluate in restricted mode with only the tree itself accessible and
+    /// build in the sandbox, for trees that aren't trusted. Fetchers and
+    /// import from derivation fail. These settings override those of
+    /// `--nix-option`. `sandbox` is a restricted setting, which a daemon
+    /// ignores for users it doesn't trust, so those rely on the sandbox
+    /// setting of the daemon itself.
+    #[arg(long)]
+    sandboxed: bool,
 }
 
-impl StatusReportData {
-    fn print(&self, clear: bool) {
-        if clear {
-            print!("\x1b[1F\x1b[2K\x1b[1F\x1b[2K");
-        }
-        println!("{}/{} files ({})", self.files, self.total_files, self.last_file);
-        println!("{}/{} ({}) items ({})", self.items, self.total_items,
-                 self.changed_items, self.last_item);
+fn parse_nix_option(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((name, value)) => Ok((name.to_string(), value.to_string())),
+        None => bail!("expected NAME=VALUE"),
     }
 }
 
-struct StatusReport(Mutex<StatusReportData>);
+impl BuildOptions {
+    /// The Nix command `program`, from `--nix-bin` if given, with the
+    /// variables of `--nix-env` set.
+    fn command(&self, program: &str) -> Command {
+        let mut command = match &self.nix_bin {
+            Some(dir) => Command::new(dir.join(program)),
+            None => Command::new(program),
+        };
+        command.envs(self.nix_env.iter().map(|(name, value)| (name, value)));
+        command
+    }
 
-impl StatusReport {
-    fn new(total_files: usize, total_items: usize) -> Self {
-        Self(Mutex::new(StatusReportData {
-            files: 0,
-            items: 0,
-            total_files,
-            total_items,
-            changed_items: 0,
-            last_file: "".to_string(),
-            last_item: "".to_string(),
-        }))
+    fn args(&self) -> Vec<String> {
+        let mut args = vec![];
+        let mut option = |name: &str, value: String| {
+            args.extend(["--option".to_string(), name.to_string(), value]);
+        };
+        if let Some(store) = &self.store {
+            option("store", store.clone());
+        }
+        if let Some(builders) = &self.builders {
+            option("builders", builders.clone());
+        }
+        if let Some(jobs) = self.max_jobs {
+            option("max-jobs", jobs.to_string());
+        }
+        if !self.extra_substituter.is_empty() {
+            option("extra-substituters", self.extra_substituter.join(" "));
+        }
+        if !self.extra_trusted_public_key.is_empty() {
+            option("extra-trusted-public-keys", self.extra_trusted_public_key.join(" "));
+        }
+        for (name, value) in &self.nix_option {
+            option(name, value.clone());
+        }
+        // last, as the last of the same setting wins
+        if self.sandboxed {
+            option("sandbox", "true".to_string());
+            option("restrict-eval", "true".to_string());
+            option("allow-import-from-derivation", "false".to_string());
+            option("allowed-uris", "".to_string());
+        }
+        args
     }
 
-    fn enter_file(&self, f: &str) {
-        let mut m = self.0.lock().unwrap();
-        m.files += 1;
-        m.last_file = f.to_string();
-        m.print(m.files > 1 || m.items >= 1);
+    /// Read `path` from the store the manuals are built in.
+    fn read(&self, path: &Path) -> Result<String, ErrorKind> {
+        let Some(store) = &self.store else {
+            return fs::read_to_string(path).map_err(io(path));
+        };
+        let result = self.command("nix")
+            .args(["--extra-experimental-features", "nix-command", "store", "cat", "--store", store])
+            .arg(path)
+            .output()
+            .map_err(io("nix"))?;
+        if !result.status.success() {
+            let error = String::from_utf8_lossy(&result.stderr).into_owned();
+            return Err(ErrorKind::Io { path: path.to_path_buf(), source: std::io::Error::other(error) });
+        }
+        Ok(String::from_utf8_lossy(&result.stdout).into_owned())
     }
+}
 
-    fn enter_item(&self, i: String) {
-        let mut m = self.0.lock().unwrap();
-        m.items += 1;
-        m.last_item = i;
-        m.print(m.files >= 1 || m.items > 1);
+/// The expression `body` in terms of the system `sys` of the tree in the
+/// current directory, with `import` added to its modules.
+fn system_expr(import: Option<&str>, body: &str) -> String {
+    let replace = match import {
+        Some(new) => format!(r#"imports = [ {new} ];"#),
+        _ => "".to_string(),
+    };
+    format!(r#"let sys = import ./. {{
+                   configuration = {{
+                       # include the overridden module!
+                       {replace}
+                   }};
+               }};
+               in {body}"#)
+}
+
+/// The expression for the manual `attr` of the tree in the current
+/// directory, with `import` added to its modules. With `harness`, the
+/// options of `import` alone are evaluated instead, see `harness::expr`.
+fn manual_expr(import: Option<&str>, harness: bool, attr: &str, opts: &BuildOptions) -> String {
+    match (&opts.manual_expr, import) {
+        (Some(expr), _) => expr.replace("@imports@", import.unwrap_or_default()).replace("@attr@", attr),
+        (None, Some(import)) if harness => harness::expr(import),
+        (None, _) => system_expr(import, &format!("sys.config.system.build.manual.{attr}")),
     }
+}
+
+/// The expression for the DocBook of `option` alone, rendered by the
+/// functions the tree renders its manual with.
+fn option_expr(import: Option<&str>, option: &str) -> String {
+    let path = option.split('.').map(|attr| serde_json::to_string(attr).unwrap()).collect::<Vec<_>>();
+    system_expr(import, &format!(r#"let
+                   inherit (sys) pkgs;
+                   inherit (pkgs) lib;
+                   path = [ {} ];
+                 in (import (pkgs.path + "/nixos/lib/make-options-doc") {{
+                   inherit pkgs lib;
+                   options = lib.setAttrByPath path (lib.getAttrFromPath path sys.options);
+                 }}).optionsDocBook"#, path.join(" ")))
+}
+
+/// Build the manual of the tree in `dir`, returning the document `pass`
+/// compares. The build isn't registered as a GC root, it's only read once.
+fn build_manual(dir: impl AsRef<Path>, import: Option<&str>, pass: Pass, opts: &BuildOptions,
+                progress: &dyn Fn(&str)) -> Result<Built, ErrorKind> {
+    let (attr, path) = pass.manual();
+    let harness = import.is_some() && harness::written(dir.as_ref());
+    build_expr(dir, &manual_expr(import, harness, attr, opts), path, opts, progress)
+}
 
-    fn update_item(&self, i: String) {
-        let mut m = self.0.lock().unwrap();
-        m.last_item = i;
-        m.print(true);
+/// Build the unchanged manual of the tree in `dir` with the files of
+/// `--import`, whose contents are `contents`. Their options are evaluated
+/// by the harness where the manual is that of options, or, if they can't
+/// be with the stubs it is given, as part of the whole system. The builds
+/// of the rewrites in `dir` follow suit.
+fn build_imported(dir: &Path, imports: &str, contents: &[&str], pass: Pass, opts: &BuildOptions,
+                  progress: &dyn Fn(&str), stopped: &dyn Fn() -> bool) -> Result<Built, ErrorKind> {
+    if pass.manual() == ("optionsDocBook", None) && opts.manual_expr.is_none() {
+        harness::write(dir, contents)?;
+        match build_manual(dir, Some(imports), pass, opts, progress) {
+            Ok(raw) => return Ok(raw),
+            Err(error) if stopped() => return Err(error),
+            Err(error) => status::log(status::VERBOSE, format!(
+                "{imports} can't be evaluated on its own, building the manual: {}",
+                error.headline().unwrap_or_default())),
+        }
+        harness::remove(dir)?;
     }
+    build_manual(dir, Some(imports), pass, opts, progress)
+}
 
-    fn changed_item(&self) {
-        let mut m = self.0.lock().unwrap();
-        m.changed_items += 1;
-        m.print(true);
+/// Build what a candidate of `option` is compared on: with `--options-db`
+/// the option alone, falling back to the manual if it can't be rendered
+/// on its own. Returns whether it was.
+fn build_checked(dir: &Path, import: Option<&str>, option: Option<&str>, pass: Pass,
+                 opts: &BuildOptions, progress: &dyn Fn(&str)) -> Result<(Built, bool), ErrorKind> {
+    if let Some(option) = option {
+        match build_expr(dir, &option_expr(import, option), None, opts, progress) {
+            Ok(raw) => return Ok((raw, true)),
+            Err(error) => status::log(status::VERBOSE, format!(
+                "{option} can't be rendered alone, building the manual: {}",
+                error.headline().unwrap_or_default())),
+        }
     }
+    Ok((build_manual(dir, import, pass, opts, progress)?, false))
+}
 
-    fn skip_items(&self, i: usize) {
-        let mut m = self.0.lock().unwrap();
-        m.items += i;
-        m.print(m.files >= 1 || m.items >= 1);
+/// Build `expr` in `dir`, returning the file at `path` of the output. Each
+/// line Nix logs is passed to `progress` as it comes.
+fn build_expr(dir: impl AsRef<Path>, expr: &str, path: Option<&str>, opts: &BuildOptions,
+              progress: &dyn Fn(&str)) -> Result<Built, ErrorKind> {
+    let mut command = opts.command("nix-build");
+    if opts.sandboxed {
+        // the tree is all restricted evaluation may read
+        command.env_remove("NIX_PATH").arg("-I").arg(dir.as_ref());
     }
+    command
+        .current_dir(dir)
+        .args(opts.args())
+        // the files evaluated, to tell a slow evaluation from a hung one
+        .args(["-v", "--no-out-link", "-E"])
+        .arg(expr)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    status::log(status::DEBUG, format!("{command:?}"));
+    let mut child = command.spawn().map_err(io("nix-build"))?;
+    let mut stderr = String::new();
+    for line in BufReader::new(child.stderr.take().unwrap()).split(b'\n') {
+        let line = line.map_err(io("nix-build"))?;
+        let line = String::from_utf8_lossy(&line);
+        progress(&line);
+        stderr.push_str(&line);
+        stderr.push('\n');
+    }
+    let result = child.wait_with_output().map_err(io("nix-build"))?;
+    if !result.status.success() {
+        return Err(ErrorKind::Build { stderr });
+    }
+    let out = String::from_utf8_lossy(&result.stdout);
+    let out = Path::new(out.lines().last().unwrap_or_default());
+    // Ok(fs::read_to_string(format!("{f}/share/doc/nixos/options.json"))?)
+    let out = match path {
+        Some(path) => out.join(path),
+        None => out.to_path_buf(),
+    };
+    Ok(Built { raw: opts.read(&out)?, warnings: eval_warnings(&stderr) })
 }
 
-struct StatusPart<'a>(&'a StatusReport, usize);
+/// The warnings printed by `lib.warn` in the output of a build, without
+/// the prefix older and newer versions of Nix print them with.
+fn eval_warnings(stderr: &str) -> BTreeSet<String> {
+    stderr.lines()
+        .filter_map(|l| l.strip_prefix("trace: warning: ").or_else(|| l.strip_prefix("evaluation warning: ")))
+        .map(str::to_string)
+        .collect()
+}
 
-impl<'a> StatusPart<'a> {
-    fn enter_item(&mut self, i: String) {
-        self.0.enter_item(i);
-        self.1 -= 1;
+/// Collect the garbage left by the manuals built during the run.
+fn collect_garbage(opts: &BuildOptions) -> Result<()> {
+    let mut command = opts.command("nix-store");
+    if let Some(store) = &opts.store {
+        command.args(["--store", store]);
     }
+    let result = command.arg("--gc").output()?;
+    if !result.status.success() {
+        bail!("garbage collection failed: {}", String::from_utf8_lossy(&result.stderr));
+    }
+    Ok(())
+}
+
+/// The output of a build, with the evaluation warnings printed on the way.
+struct Built {
+    raw: String,
+    warnings: BTreeSet<String>,
+}
+
+/// A built manual, along with the normalized form it is compared in, and
+/// the warnings of its evaluation if they are known.
+struct Manual {
+    raw: String,
+    normalized: String,
+    warnings: Option<BTreeSet<String>>,
+}
 
-    fn update_item(&mut self, i: String) {
-        self.0.update_item(i);
+impl Manual {
+    fn new(Built { raw, warnings }: Built) -> Arc<Self> {
+        let normalized = normalized(&raw);
+        Arc::new(Self { raw, normalized, warnings: Some(warnings) })
     }
+}
+
+/// The patterns of `--normalize`, set once the arguments are parsed.
+static NORMALIZE: OnceLock<Vec<Regex>> = OnceLock::new();
 
-    fn changed_item(&mut self) {
-        self.0.changed_item();
+/// `raw` with the differences left out that are filtered out before
+/// comparing manuals, by `normalize` and `--normalize`.
+fn normalized(raw: &str) -> String {
+    NORMALIZE.get().into_iter().flatten()
+        .fold(normalize(raw), |s, pattern| pattern.replace_all(&s, "").into_owned())
+}
+
+/// The manual of the unchanged tree, the same for every file unless they
+/// are imported into it. Built by the first worker to need it, or from
+/// `--baseline-ref` before the workers start.
+static BASELINE: Mutex<Option<Arc<Manual>>> = Mutex::new(None);
+
+/// The database of `--options-db`, loaded before the workers start.
+static OPTIONS_DB: OnceLock<OptionsDb> = OnceLock::new();
+
+/// The status of the workers, for Ctrl-C to stop them once there are any.
+static PRINTER: OnceLock<Arc<StatusReport>> = OnceLock::new();
+
+fn baseline_manual(dir: impl AsRef<Path>, pass: Pass, opts: &BuildOptions,
+                   p: &mut StatusPart) -> Result<Arc<Manual>, ErrorKind> {
+    // held during the build, so the others wait for it instead of building too
+    let mut baseline = BASELINE.lock().unwrap();
+    if let Some(manual) = &*baseline {
+        return Ok(Arc::clone(manual));
     }
+    let manual = Manual::new(build_manual(dir, None, pass, opts, &|line| p.build_line(line))?);
+    p.built();
+    *baseline = Some(Arc::clone(&manual));
+    Ok(manual)
+}
+
+/// Whether `expected` and `changed` only differ in text matching one of
+/// `patterns`, for `--force-apply-pattern`.
+fn benign(patterns: &[Regex], expected: &str, changed: &str) -> bool {
+    let strip = |s: &str| patterns.iter().fold(s.to_string(), |s, p| p.replace_all(&s, "").into_owned());
+    !patterns.is_empty() && strip(expected) == strip(changed)
+}
+
+/// How alike the parts of `expected` and `changed` that differ are, from 0
+/// to 1, and whether they differ in whitespace only.
+fn compare(expected: &str, changed: &str) -> (f32, bool) {
+    let (prefix, suffix) = common_affixes(expected, changed);
+    let expected = &expected[prefix .. expected.len() - suffix];
+    let changed = &changed[prefix .. changed.len() - suffix];
+    let text = |s: &str| s.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    // differences far apart can take long to diff by character
+    let diff = TextDiff::configure().timeout(Duration::from_secs(1)).diff_chars(expected, changed);
+    (diff.ratio(), text(expected) == text(changed))
 }
 
-impl<'a> Drop for StatusPart<'a> {
-    fn drop(&mut self) {
-        self.0.skip_items(self.1);
+/// Other files written or removed by accepted rewrites, see `Rewrite::files`.
+type FileChanges = Vec<(String, Option<String>)>;
+
+/// The outcome of converting a file, to be written once the run is over.
+struct Converted {
+    file: String,
+    content: String,
+    files: FileChanges,
+    /// How the accepted rewrites changed the manual, for `--final-check`.
+    manual_changes: Vec<(String, String)>,
+}
+
+/// The flag making `cp` clone files or fail.
+fn clone_flag() -> &'static str {
+    match cfg!(target_os = "macos") {
+        true => "-c",
+        false => "--reflink=always",
     }
 }
 
-fn is_call_to(n: SyntaxNode, f: &str) -> bool {
-    let tgt = match Apply::cast(n) {
-        Some(tgt) => tgt,
-        _ => return false,
+/// Copy the tree to `dest`, cloning the files where the file system can.
+fn copy_tree(dest: &Path) -> Result<(), ErrorKind> {
+    let clone = clone_flag();
+    // `./` copies the contents with both GNU and BSD cp
+    let copy = |flags: &[&str]| {
+        Command::new("cp").arg("-a").args(flags).arg("./").arg(dest).output().map_err(io("cp"))
     };
-    if let Some(id) = tgt.lambda().and_then(Ident::cast) {
-        return id.as_str() == f;
+    if copy(&[clone])?.status.success() {
+        return Ok(());
+    }
+    let result = copy(&[])?;
+    if !result.status.success() {
+        return Err(ErrorKind::Copy { stderr: String::from_utf8_lossy(&result.stderr).into_owned() });
+    }
+    Ok(())
+}
+
+/// Copy the tree as of the git revision `rev` to `dest`, for
+/// `--baseline-ref`. Like `copy_tree`, from the current directory down.
+fn copy_revision(rev: &str, dest: &Path) -> Result<(), ErrorKind> {
+    let mut archive = Command::new("git").args(["archive", "--format=tar", rev])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn().map_err(io("git"))?;
+    let extracted = Command::new("tar").arg("-x").arg("-C").arg(dest)
+        .stdin(archive.stdout.take().unwrap())
+        .output().map_err(io("tar"))?;
+    let archived = archive.wait_with_output().map_err(io("git"))?;
+    for result in [archived, extracted] {
+        if !result.status.success() {
+            return Err(ErrorKind::Copy { stderr: String::from_utf8_lossy(&result.stderr).into_owned() });
+        }
+    }
+    Ok(())
+}
+
+/// State of a file while its candidates are checked one after another.
+struct FileRun<'a> {
+    file: &'a str,
+    args: &'a Args,
+    report: &'a Report,
+    /// Where accepted changes are recorded, except in the extra copies of
+    /// `--jobs-per-file`.
+    journal: Option<&'a Journal>,
+    p: StatusPart<'a>,
+    tmp: TempDir,
+    /// The file inside `tmp`.
+    f: PathBuf,
+    initial_content: String,
+    content: String,
+    /// The accepted rewrites, which candidates are found past.
+    edits: EditList,
+    files: FileChanges,
+    manual_changes: Vec<(String, String)>,
+    /// The manual with the changes accepted so far.
+    old: Arc<Manual>,
+    /// See `candidate_names`.
+    names: HashMap<TextRange, String>,
+    /// The candidates whose rewrites were kept, as found.
+    accepted: Vec<Candidate>,
+    /// See `FileRun::start_ahead`.
+    ahead: Option<Ahead>,
+    db: Option<&'a OptionsDb>,
+    /// The entries of `db` changed by the rewrites accepted so far.
+    db_changes: HashMap<String, String>,
+    /// The options of the manual declared in the file, see
+    /// `options_db::declared_in`.
+    own: Option<HashSet<String>>,
+}
+
+/// A build of the next rewrite of `--pipeline`, started in the tree of a
+/// `FileRun` while the last one is compared.
+struct Ahead {
+    content: String,
+    files: FileChanges,
+    build: JoinHandle<Result<(Built, bool), ErrorKind>>,
+}
+
+impl<'a> FileRun<'a> {
+    fn import(&self) -> Option<&str> {
+        self.args.import.then(|| self.f.to_str().unwrap())
+    }
+
+    fn error(&self, option: Option<&str>, kind: ErrorKind) -> Error {
+        Error { file: self.file.to_string(), option: option.map(str::to_string), kind }
+    }
+
+    /// With `--options-db`, the entry of `option` to compare a rendering of
+    /// it alone with, if it has one and can be looked up in the options.
+    fn db_entry(&self, option: &str) -> Option<Arc<Manual>> {
+        let db = self.db?;
+        if option.contains(['<', '*']) || self.args.pass.manual() != ("optionsDocBook", None) {
+            return None;
+        }
+        // the file may be imported by the module declaring the option, and
+        // others, only a build of the whole manual shows all they render
+        if self.own.as_ref().is_some_and(|own| !own.contains(option)) {
+            return None;
+        }
+        let entry = self.db_changes.get(option).map(String::as_str).or_else(|| db.get(option))?;
+        Some(Arc::new(Manual { raw: entry.to_string(), normalized: entry.to_string(), warnings: None }))
+    }
+
+    /// The report entry of `candidate`, as found, with `outcome`.
+    fn entry(&self, candidate: &Candidate, outcome: Outcome) -> report::Entry {
+        report::Entry {
+            id: format!("{}#{}", self.file, self.names[&candidate.range]),
+            file: self.file.to_string(),
+            option: candidate.option_path.clone(),
+            start: candidate.range.start().into(),
+            end: candidate.range.end().into(),
+            kind: candidate.kind.name(),
+            outcome,
+            similarity: None,
+            diff: None,
+            allowed: vec![],
+            elsewhere: vec![],
+            tags: report::tags(&self.initial_content[candidate.range]),
+        }
+    }
+
+    /// Put back the files of the tree as they are in the file run, after
+    /// `changes` were written there.
+    fn restore(&self, changes: &FileChanges) -> Result<(), ErrorKind> {
+        for (path, _) in changes {
+            let tmp_path = self.tmp.path().join(path);
+            match fs::read(path) {
+                Ok(original) => write_file(tmp_path, original)?,
+                Err(_) => remove_file(tmp_path)?,
+            }
+        }
+        write_file(&self.f, &self.content)
     }
-    if let Some(sel) = tgt.lambda().and_then(Select::cast) {
-        return match (sel.set().and_then(Ident::cast), sel.index().and_then(Ident::cast)) {
-            (Some(s), Some(i)) => s.as_str() == "lib" && i.as_str() == f,
-            _ => false,
+
+    /// Write the rewrite of `next` on top of `rewrite` to the tree and start
+    /// building it, so that it is ready by the time `rewrite` is kept. The
+    /// manual of `rewrite` is already built, only comparing it is left.
+    fn start_ahead(&self, next: &Candidate, rewrite: &Rewrite) -> Result<Option<Ahead>, ErrorKind> {
+        let (file, args) = (self.file, self.args);
+        let mut edits = self.edits.clone();
+        edits.extend(Edit::between(&self.content, &rewrite.content));
+        let Some(range) = edits.map(next.range) else {
+            return Ok(None);
         };
+        let next = Candidate { range, ..next.clone() };
+        let Ok(ahead) = args.pass.rewrite(file, &rewrite.content, &next, &args.convert) else {
+            return Ok(None);
+        };
+        if file.ends_with(".nix") && rnix::parse(&ahead.content).as_result().is_err() {
+            return Ok(None);
+        }
+        write_file(&self.f, &ahead.content)?;
+        for (path, new) in &ahead.files {
+            let path = self.tmp.path().join(path);
+            match new {
+                Some(new) => write_file(path, new)?,
+                None => remove_file(path)?,
+            }
+        }
+        let (dir, import) = (self.tmp.path().to_path_buf(), self.import().map(str::to_string));
+        let alone = self.db_entry(&next.option_path).map(|_| next.option_path.clone());
+        let (pass, build) = (args.pass, args.build.clone());
+        let build = thread::spawn(move || {
+            // not shown, the worker is still on the candidate before
+            build_checked(&dir, import.as_deref(), alone.as_deref(), pass, &build, &|_| {})
+        });
+        Ok(Some(Ahead { content: ahead.content, files: ahead.files, build }))
     }
-    false
-}
-
-// doesn't need to escape . because we're only interested in single-entry
-// paths anyway
-fn key_string(kv: &KeyValue) -> String {
-    kv.key().map_or_else(
-        || String::new(),
-        |kv| kv.path().map(|p| p.to_string()).collect::<Vec<_>>().join("."))
-}
-
-fn find_candidates(s: &str) -> Vec<(TextRange, bool)> {
-    let ast = rnix::parse(s).as_result().unwrap();
-    let mut nodes: VecDeque<_> = [(ast.node(), false)].into();
-    let mut result = vec![];
-
-    while let Some((node, parent_is_option)) = nodes.pop_front() {
-        match node.kind() {
-            SyntaxKind::NODE_APPLY => {
-                let call = Apply::cast(node.clone()).unwrap();
-                if let Some(arg) = call.value() {
-                    nodes.push_back((
-                        arg.clone(),
-                        is_call_to(node.clone(), "mkOption")
-                        || is_call_to(node.clone(), "mkNullOrBoolOption")
-                        || is_call_to(node.clone(), "mkNullOrStrOption")
-                        || is_call_to(node.clone(), "mkInternalOption")
-                    ));
-                    if is_call_to(node.clone(), "mkEnableOption")
-                        && Paren::cast(call.value().unwrap()).map_or(true, |p| {
-                            !is_call_to(p.node().first_child().unwrap(), "mdDoc")
-                        })
-                    {
-                        result.push((arg.text_range(), true));
+
+    /// Wait for the build started ahead, if any, and undo its changes to the
+    /// tree.
+    fn cancel(&self, ahead: Option<Ahead>) -> Result<(), ErrorKind> {
+        let Some(ahead) = ahead else {
+            return Ok(());
+        };
+        let _ = ahead.build.join();
+        self.restore(&ahead.files)
+    }
+
+    /// Check `candidate`, found in the file as it was before the run,
+    /// keeping the rewrite if the manual comes out as expected. With
+    /// `--pipeline`, the build of `next` is started before comparing.
+    /// Returns `false` if interrupted.
+    fn check(&mut self, candidate: &Candidate, next: Option<&Candidate>) -> Result<bool, ErrorKind> {
+        let (file, args) = (self.file, self.args);
+        let (option, found) = (candidate.option_path.as_str(), candidate.range);
+        let similarity = Cell::new(None);
+        let mapped = self.edits.map(found);
+        let name = &self.names[&found];
+        let found_entry = self.entry(candidate, Outcome::Changed);
+        let entry = |outcome| report::Entry { outcome, similarity: similarity.get(), ..found_entry.clone() };
+        // where the candidate is now, unless an earlier rewrite reached into it
+        let Some(range) = mapped else {
+            self.report.add(entry(Outcome::Overlapping));
+            return Ok(true);
+        };
+        let candidate = &Candidate { range, ..candidate.clone() };
+        self.p.phase("rewriting");
+        let failure_dir = failure_dir(file, name);
+        let start_failure = |outcome| -> Result<(), ErrorKind> {
+            fs::create_dir_all(&failure_dir).map_err(io(&failure_dir))?;
+            let meta = failure_dir.join("meta.json");
+            let json = serde_json::to_string_pretty(&entry(outcome)).map_err(|e| io(&meta)(e.into()))?;
+            write_file(meta, json)?;
+            write_file(failure_dir.join("before.nix"), &self.initial_content)
+        };
+
+        let dump = |outcome, after: Option<&str>| match (&args.dump_candidates, args.pass) {
+            (Some(dir), Pass::MdDoc) => fixture::dump(dir, &self.content[range], after, outcome),
+            _ => Ok(()),
+        };
+
+        let explain = args.explain.is_some();
+        let finish = |entry: report::Entry| {
+            if explain {
+                match entry.outcome.accepted() {
+                    true => println!("--- {:?}\n", entry.outcome),
+                    false => println!("--- {:?}, see {}\n", entry.outcome, failure_dir.display()),
+                }
+            }
+            self.report.add(entry);
+        };
+
+        let rewrite = || args.pass.rewrite(file, &self.content, candidate, &args.convert);
+        let rewrite = match explain {
+            false => rewrite(),
+            true => {
+                let (rewrite, rules) = trace::record(rewrite);
+                println!("=== {}", entry(Outcome::Changed).option);
+                println!("--- original\n{}", text_at(&self.content, range).unwrap_or_default());
+                for (rule, text) in rules {
+                    println!("--- {rule}\n{text}");
+                }
+                if let Ok(rewrite) = &rewrite {
+                    // the changed region, widened to the whole candidate
+                    let (prefix, suffix) = common_affixes(&self.content, &rewrite.content);
+                    let start = prefix.min(usize::from(range.start()));
+                    let end = (rewrite.content.len() - suffix)
+                        .max((usize::from(range.end()) + rewrite.content.len())
+                             .saturating_sub(self.content.len()));
+                    println!("--- result\n{}", rewrite.content.get(start .. end).unwrap_or_default());
+                }
+                rewrite
+            }
+        };
+        let rewrite = match rewrite {
+            Ok(rewrite) => rewrite,
+            Err(error) => {
+                start_failure(Outcome::RewriteFailed)?;
+                write_file(failure_dir.join("after.error"), error.to_string())?;
+                self.p.failed(Outcome::RewriteFailed, || error.to_string());
+                dump(Outcome::RewriteFailed, None)?;
+                finish(entry(Outcome::RewriteFailed));
+                return Ok(true);
+            }
+        };
+        let change = &rewrite.content;
+        // the candidate as rewritten
+        let after = (usize::from(range.end()) + change.len()).checked_sub(self.content.len())
+            .and_then(|end| change.get(usize::from(range.start()) .. end));
+        if file.ends_with(".nix") {
+            if let Err(error) = rnix::parse(change).as_result() {
+                let error = ErrorKind::Parse(error.to_string());
+                start_failure(Outcome::InvalidNix)?;
+                write_file(failure_dir.join("after.nix"), change)?;
+                write_file(failure_dir.join("after.error"), error.to_string())?;
+                self.p.failed(Outcome::InvalidNix, || error.to_string());
+                dump(Outcome::InvalidNix, after)?;
+                finish(entry(Outcome::InvalidNix));
+                return Ok(true);
+            }
+        }
+        self.p.phase("building");
+        // the build started ahead, if it was of this very rewrite
+        let ahead = match self.ahead.take() {
+            Some(ahead) if ahead.content == *change && ahead.files == rewrite.files => Some(ahead.build),
+            ahead => {
+                self.cancel(ahead)?;
+                None
+            },
+        };
+        if ahead.is_none() {
+            write_file(&self.f, change)?;
+            for (path, new) in &rewrite.files {
+                let path = self.tmp.path().join(path);
+                match new {
+                    Some(new) => write_file(path, new)?,
+                    None => remove_file(path)?,
+                }
+            }
+        }
+
+        let write_failure = |outcome, error: ErrorKind,
+                             manuals: Option<(&Manual, &Manual)>| -> Result<Outcome, ErrorKind> {
+            start_failure(outcome)?;
+            write_file(failure_dir.join("after.nix"), change)?;
+            match manuals {
+                Some((old, changed)) => {
+                    write_file(failure_dir.join("before.xml"), &old.raw)?;
+                    write_file(failure_dir.join("after.xml"), &changed.raw)?;
+                    if !rewrite.expect.is_empty() {
+                        write_file(failure_dir.join("expected.xml"), rewrite.expected(&old.normalized))?;
+                    }
+                    if let ErrorKind::Warnings { .. } = &error {
+                        write_file(failure_dir.join("after.error"), error.to_string())?;
+                    }
+                },
+                None => {
+                    write_file(failure_dir.join("after.error"), error.to_string())?;
+                    if let ErrorKind::Build { stderr } = &error {
+                        write_file(failure_dir.join("build.log"), stderr)?;
                     }
-                    continue;
                 }
             }
-            SyntaxKind::NODE_ATTR_SET => {
-                let attrs = AttrSet::cast(node.clone()).unwrap();
-                for e in attrs.entries() {
-                    if key_string(&e) == "description"
-                        && parent_is_option
-                        && !e.value().map(|v| is_call_to(v, "mdDoc")).unwrap_or(false)
-                    {
-                        result.push((e.value().unwrap().text_range(), false));
+            Ok(outcome)
+        };
+
+        let db_entry = self.db_entry(option);
+        let built = match ahead {
+            Some(build) => build.join().unwrap(),
+            None => {
+                let alone = db_entry.is_some().then_some(option);
+                build_checked(self.tmp.path(), self.import(), alone, args.pass, &args.build,
+                              &|line| self.p.build_line(line))
+            },
+        };
+        if built.is_err() && self.p.stopped() {
+            return Ok(false);
+        }
+        self.p.built();
+        if let (true, Some(next), Ok(_)) = (args.pipeline && !explain, next, &built) {
+            self.ahead = self.start_ahead(next, &rewrite)?;
+        }
+        let (mut diff, mut allowed, mut elsewhere) = (None, vec![], vec![]);
+        let outcome = match built {
+            Ok((changed, alone)) => {
+                self.p.phase("comparing");
+                let (old, changed) = match (alone, db_entry) {
+                    (true, Some(entry)) => {
+                        let normalized = normalized(&changed.raw);
+                        let found = options_db::rendered(&normalized).into_iter()
+                            .find(|(name, _)| name == option)
+                            .map_or(String::new(), |(_, entry)| entry.to_string());
+                        let Built { raw, warnings } = changed;
+                        (entry, Arc::new(Manual { raw, normalized: found, warnings: Some(warnings) }))
+                    },
+                    _ => (Arc::clone(&self.old), Manual::new(changed)),
+                };
+                // where the warnings of both are known, an option rendered
+                // alone has none of the manual's
+                let warned = match (&old.warnings, &changed.warnings) {
+                    (Some(old), Some(new)) => {
+                        let expected = rewrite.expected_warnings(old);
+                        (expected != *new).then(|| ErrorKind::Warnings {
+                            added: new.difference(&expected).cloned().collect(),
+                            missing: expected.difference(new).cloned().collect(),
+                        })
+                    },
+                    _ => None,
+                };
+                let verified = rewrite.verify(&old.normalized, &changed.normalized);
+                let expected = match verified {
+                    true => String::new(),
+                    false => rewrite.expected(&old.normalized),
+                };
+                let allows = |list: &AllowList| list.allows(&expected, &changed.normalized);
+                let accepted = if warned.is_some() {
+                    None
+                } else if verified {
+                    Some(Outcome::Changed)
+                } else if let Some(names) = args.allowed_diffs.as_ref().and_then(allows) {
+                    allowed = names;
+                    Some(Outcome::Allowed)
+                } else if benign(&args.force_apply_pattern, &expected, &changed.normalized) {
+                    Some(Outcome::Forced)
+                } else {
+                    None
+                };
+                if let Some(outcome) = accepted {
+                    if !verified {
+                        diff = Some(tui::diff_preview(&expected, &changed.normalized));
                     }
+                    if let (Some(own), false) = (&self.own, alone) {
+                        elsewhere = options_db::changed(&old.normalized, &changed.normalized).into_iter()
+                            .filter(|name| !own.contains(name))
+                            .collect::<Vec<_>>();
+                        if !elsewhere.is_empty() {
+                            self.p.error_line(&format!("also changed {}, declared elsewhere", elsewhere.join(", ")));
+                        }
+                    }
+                    self.p.changed_item();
+                    self.manual_changes.extend(final_check::manual_change(&old.normalized,
+                                                                          &changed.normalized));
+                    // later expectations are relative to the accepted changes:
+                    // a verified rewrite without a gain built exactly what it
+                    // expected, and the differences of one allowed or forced
+                    // are kept by every later build, so expecting them again
+                    // would fail the candidates after it
+                    self.old = match alone {
+                        true => {
+                            let raw = self.old.normalized.replacen(&old.normalized, &changed.normalized, 1);
+                            let warnings = self.old.warnings.as_ref().map(|w| rewrite.expected_warnings(w));
+                            Arc::new(Manual { normalized: normalized(&raw), raw, warnings })
+                        },
+                        false => changed,
+                    };
+                    if self.db.is_some() {
+                        let entries = options_db::rendered(&self.old.normalized);
+                        if let Some((_, entry)) = entries.into_iter().find(|(name, _)| name == option) {
+                            self.db_changes.insert(option.to_string(), entry.to_string());
+                        }
+                    }
+                    outcome
+                } else if let Some(error) = warned {
+                    self.p.failed(Outcome::Mismatch, || error.to_string());
+                    write_failure(Outcome::Mismatch, error, Some((&old, &changed)))?
+                } else {
+                    let (score, whitespace) = compare(&expected, &changed.normalized);
+                    similarity.set(Some(score));
+                    let outcome = match whitespace {
+                        true => Outcome::NearMiss,
+                        false => Outcome::Mismatch,
+                    };
+                    self.p.failed(outcome, || tui::diff_preview(&expected, &changed.normalized));
+                    write_failure(outcome, ErrorKind::DiffMismatch, Some((&old, &changed)))?
+                }
+            },
+            Err(error) => {
+                if let Some(headline) = error.headline() {
+                    self.p.error_line(headline);
                 }
+                self.p.failed(Outcome::BuildFailed, || error.to_string());
+                write_failure(Outcome::BuildFailed, error, None)?
             }
-            _ => (),
         };
+        dump(outcome, after)?;
+        finish(report::Entry { diff, allowed, elsewhere, ..entry(outcome) });
+        if outcome.accepted() {
+            if let (Some(dir), Pass::MdDoc, Outcome::Changed) = (&args.record_fixture, args.pass, outcome) {
+                fixture::record(dir, file, name, &self.content[range])?;
+            }
+            if let Some(journal) = self.journal {
+                journal.record(file, &self.content, &rewrite.content, &rewrite.files)?;
+            }
+            self.accepted.push(Candidate { range: found, ..candidate.clone() });
+            self.edits.extend(Edit::between(&self.content, &rewrite.content));
+            self.content = rewrite.content;
+            self.files.extend(rewrite.files);
+        } else {
+            // the build ahead assumed this rewrite
+            let ahead = self.ahead.take();
+            self.cancel(ahead)?;
+            self.restore(&rewrite.files)?;
+        }
+        Ok(true)
+    }
 
-        for c in node.children() {
-            nodes.push_back((c, false));
-        }
-    }
-
-    result.sort_by(|(a, _), (b, _)| b.start().cmp(&a.start()));
-    result
-}
-
-fn markdown_escape(s: &str) -> String {
-    s.replace("`", "\\`")
-     .replace("*", "\\*")
-     .replace("&lt;", "<")
-     .replace("&gt;", ">")
-     .replace("&amp;", "&")
-}
-
-struct SurroundPat(&'static str, &'static str, &'static str);
-
-impl Replacer for SurroundPat {
-    fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
-        dst.push_str(self.0);
-        let mut tmp = String::new();
-        self.1.replace_append(caps, &mut tmp);
-        dst.push_str(&markdown_escape(&tmp));
-        dst.push_str(self.2);
-    }
-}
-
-struct CodePat(&'static str);
-
-impl Replacer for CodePat {
-    fn replace_append(&mut self, caps: &regex::Captures<'_>, dst: &mut String) {
-        dst.push_str(self.0);
-        dst.push_str("`");
-        dst.push_str(&caps[1].replace("&gt;", ">").replace("&lt;", "<"));
-        dst.push_str("`");
-    }
-}
-
-fn convert_one(s: &str, pos: TextRange, add_parens: bool) -> String {
-    let prefix = &s[.. pos.start().into()];
-    let chunk = &s[pos.start().into() .. pos.end().into()];
-    let suffix = &s[usize::from(pos.end()) ..];
-
-    let new_chunk = RegexBuilder::new(r#"<literal>([^`]*?)</literal>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&chunk, CodePat(""));
-    // let new_chunk = RegexBuilder::new(r#"<replaceable>([^»]*?)</replaceable>"#)
-    //     .multi_line(true)
-    //     .dot_matches_new_line(true)
-    //     .build().unwrap()
-    //     .replace_all(&new_chunk, SurroundPat("«", "$1", "»"));
-    let new_chunk = RegexBuilder::new(r#"<filename>([^`]*?)</filename>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, CodePat("{file}"));
-    let new_chunk = RegexBuilder::new(r#"<option>([^`]*?)</option>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, CodePat("{option}"));
-    // let new_chunk = RegexBuilder::new(r#"<code>([^`]*?)</code>"#)
-    //     .multi_line(true)
-    //     .dot_matches_new_line(true)
-    //     .build().unwrap()
-    //     .replace_all(&new_chunk, SurroundPat("`", "$1", "`"));
-    let new_chunk = RegexBuilder::new(r#"<command>([^`]*?)</command>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, CodePat("{command}"));
-    let new_chunk = RegexBuilder::new(r#"<link\s*xlink:href="([^"]+)"\s*/>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, SurroundPat("<", "$1", ">"));
-    let new_chunk = RegexBuilder::new(r#"<link\s*xlink:href="([^"]+)">(.*?)</link>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, SurroundPat("", "[$2]($1)", ""));
-    let new_chunk = RegexBuilder::new(r#"<xref linkend="(.+?)" ?/>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, SurroundPat("[](#", "$1", ")"));
-    let new_chunk = RegexBuilder::new(r#"<link linkend="(.+?)">(.*?)</link>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, SurroundPat("", "[$2](#$1)", ""));
-    // let new_chunk = RegexBuilder::new(r#"<package>([^`]*?)</package>"#)
-    //     .multi_line(true)
-    //     .dot_matches_new_line(true)
-    //     .build().unwrap()
-    //     .replace_all(&new_chunk, SurroundPat("`", "$1", "`"));
-    let new_chunk = RegexBuilder::new(r#"<emphasis>([^*]*?)</emphasis>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, SurroundPat("*", "$1", "*"));
-    let new_chunk = RegexBuilder::new(r#"<emphasis role="strong">([^*]*?)</emphasis>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, SurroundPat("**", "$1", "**"));
-    let new_chunk = RegexBuilder::new(r#"
-            <citerefentry>\s*
-                <refentrytitle>\s*(.*?)\s*</refentrytitle>\s*
-                <manvolnum>\s*(.*?)\s*</manvolnum>\s*
-            </citerefentry>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .ignore_whitespace(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, "{manpage}`$1($2)`");
-    let new_chunk = RegexBuilder::new(r#"</?programlisting>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, "```");
-    let new_chunk = RegexBuilder::new(r#"<varname>([^*]*?)</varname>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, "{var}`$1`");
-    let new_chunk = RegexBuilder::new(r#"<envar>([^*]*?)</envar>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, "{env}`$1`");
-    let new_chunk = RegexBuilder::new(
-        r#"^( *)<note>(?:<para>)?(.*?)(?:</para>)?</note>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, "$1::: {.note}\n$1$2\n$1:::");
-    let new_chunk = RegexBuilder::new(
-        r#"^( *)<warning>(?:<para>)?(.*?)(?:</para>)?</warning>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, "$1::: {.warning}\n$1$2\n$1:::");
-    let new_chunk = RegexBuilder::new(
-        r#"^( *)<important>(?:<para>)?(.*?)(?:</para>)?</important>"#)
-        .multi_line(true)
-        .dot_matches_new_line(true)
-        .build().unwrap()
-        .replace_all(&new_chunk, "$1::: {.important}\n$1$2\n$1:::");
-
-    let (lpar, rpar) = if add_parens {
-        ("(", ")")
-    } else {
-        ("", "")
-    };
+    /// Check the rewrites of `candidates` that only wrap them, with no
+    /// conversion rule firing, with a single build for all of them, and
+    /// keep them if the manual comes out as they expect. Returns the
+    /// candidates left to check one by one, all of them if that build fails.
+    fn check_pure_wraps<'c>(&mut self, candidates: &[&'c Candidate]) -> Result<Vec<&'c Candidate>, Error> {
+        let (file, args) = (self.file, self.args);
+        let (mut content, mut edits, mut files) = (self.content.clone(), self.edits.clone(), vec![]);
+        let (mut wraps, mut rest, mut expected) = (vec![], vec![], self.old.normalized.clone());
+        for &candidate in candidates {
+            let Some(range) = edits.map(candidate.range) else {
+                rest.push(candidate);
+                continue;
+            };
+            let mapped = Candidate { range, ..candidate.clone() };
+            let (rewrite, rules) = trace::record(|| args.pass.rewrite(file, &content, &mapped, &args.convert));
+            let rewrite = match rewrite {
+                Ok(rewrite) if rules.is_empty() && rewrite.gain.is_none()
+                    && (!file.ends_with(".nix") || rnix::parse(&rewrite.content).as_result().is_ok()) => rewrite,
+                _ => {
+                    rest.push(candidate);
+                    continue;
+                },
+            };
+            expected = rewrite.expected(&expected);
+            edits.extend(Edit::between(&content, &rewrite.content));
+            content = rewrite.content;
+            files.extend(rewrite.files);
+            wraps.push(candidate);
+        }
+        if wraps.is_empty() {
+            return Ok(rest);
+        }
 
-    prefix.to_owned()
-        + lpar
-        + "lib.mdDoc "
-        + &new_chunk
-        + rpar
-        + suffix
-}
+        self.p.phase(&format!("building {} pure wraps", wraps.len()));
+        let written = write_file(&self.f, &content).and_then(|()| {
+            files.iter().try_for_each(|(path, new)| match new {
+                Some(new) => write_file(self.tmp.path().join(path), new),
+                None => remove_file(self.tmp.path().join(path)),
+            })
+        });
+        written.map_err(|kind| self.error(None, kind))?;
+        let built = build_manual(&self.tmp, self.import(), args.pass, &args.build,
+                                 &|line| self.p.build_line(line));
+        if built.is_err() && self.p.stopped() {
+            return Ok(candidates.to_vec());
+        }
+        self.p.built();
+        let changed = built.ok().map(Manual::new).filter(|changed| changed.normalized == expected);
+        let Some(changed) = changed else {
+            status::log(status::VERBOSE, format!("{file}: pure wraps changed the manual, checking them one by one"));
+            self.restore(&files).map_err(|kind| self.error(None, kind))?;
+            return Ok(candidates.to_vec());
+        };
 
-fn build_manual(dir: impl AsRef<Path>, import: Option<&str>) -> Result<String> {
-    let tmp = tempdir()?;
-    let f = format!("{}/out", tmp.path().to_str().unwrap());
-    let replace = match import {
-        Some(new) => format!(r#"imports = [ {new} ];"#),
-        _ => "".to_string(),
-    };
-    let result = Command::new("nix-build")
-        .current_dir(dir)
-        .args(["-o", &f, "-E"])
-        .arg(format!(r#"let sys = import ./. {{
-                            configuration = {{
-                                # include the overridden module!
-                                {replace}
-                            }};
-                        }};
-                        in sys.config.system.build.manual.optionsDocBook"#))
-        .output()?;
-    if !result.status.success() {
-        bail!("build failed: {}", String::from_utf8_lossy(&result.stderr));
+        for &candidate in &wraps {
+            self.p.enter_item(format!("pure wrap ({})", candidate.option_path));
+            self.p.changed_item();
+            self.report.add(self.entry(candidate, Outcome::Changed));
+            self.accepted.push(candidate.clone());
+        }
+        if let Some(journal) = self.journal {
+            journal.record(file, &self.content, &content, &files).map_err(|kind| self.error(None, kind))?;
+        }
+        self.manual_changes.extend(final_check::manual_change(&self.old.normalized, &changed.normalized));
+        self.old = changed;
+        (self.content, self.edits) = (content, edits);
+        self.files.extend(files);
+        Ok(rest)
     }
-    // Ok(fs::read_to_string(format!("{f}/share/doc/nixos/options.json"))?)
-    Ok(fs::read_to_string(f)?)
-}
-
-/// Filter out inconsequential differences.
-fn normalize(xml: &str) -> String {
-    xml
-        .replace(['‘', '’'], "'")
-        .replace(['“', '”'], "\"")
-        .replace('…', "...")
-        // HACK: We get additional whitespace for DocBook
-        // descriptions in the nix-darwin manual for some reason.
-        .replace("<para>\n", "<para>")
-        .replace("\n</para>", "</para>")
-}
-
-fn convert_file(file: &str, import: bool, p: &StatusReport) -> Result<String> {
-    let mut content = fs::read_to_string(file)?;
-    let initial_content = content.clone();
-    let candidates = find_candidates(&content);
-    let mut p = StatusPart(p, candidates.len());
-    if candidates.is_empty() {
-        return Ok(content);
+
+    /// Check `candidates` one after another, until interrupted.
+    fn check_all(&mut self, candidates: &[&Candidate]) -> Result<(), Error> {
+        for (i, candidate) in candidates.iter().enumerate() {
+            if self.p.stopped() {
+                break;
+            }
+            let option = &candidate.option_path;
+            self.p.enter_item(match option.as_str() {
+                "" => format!("{}/{}", i + 1, candidates.len()),
+                _ => format!("{}/{} ({option})", i + 1, candidates.len()),
+            });
+            let next = candidates.get(i + 1).copied();
+            if !self.check(candidate, next).map_err(|kind| self.error(Some(option), kind))? {
+                break;
+            }
+        }
+        let ahead = self.ahead.take();
+        self.cancel(ahead).map_err(|kind| self.error(None, kind))
     }
 
-    let tmp = tempdir()?;
-    let result = Command::new("cp")
-        .args(["-at", tmp.path().to_str().unwrap(), "--reflink=always", "."])
-        .output()?;
-    if !result.status.success() {
-        bail!("copy failed: {}", String::from_utf8_lossy(&result.stderr));
+    /// Check `candidates` in `lanes` copies of the tree at once, each taking
+    /// every `lanes`th of them, then rewrite the file with all those kept
+    /// and check that its manual has the changes of every copy.
+    fn check_in_lanes(&mut self, candidates: &[&Candidate], lanes: usize) -> Result<(), Error> {
+        let (file, args) = (self.file, self.args);
+        let baseline = Arc::clone(&self.old);
+        // kept before the checks in parallel started
+        let earlier = self.accepted.clone();
+        let mut others = vec![];
+        for lane in 1 .. lanes {
+            let tmp = tempdir().map_err(|e| self.error(None, io(std::env::temp_dir())(e)))?;
+            copy_tree(tmp.path()).map_err(|kind| self.error(None, kind))?;
+            let f = tmp.path().join(file);
+            let items = candidates.iter().skip(lane).step_by(lanes).count();
+            let mut p = self.p.split(&format!("{file} [{}]", lane + 1), items);
+            // imported files are part of the manual, so it differs per copy
+            let old = match args.import {
+                false => Arc::clone(&baseline),
+                true => {
+                    p.phase("building unchanged manual");
+                    let built = build_imported(tmp.path(), f.to_str().unwrap(), &[&self.initial_content],
+                                               args.pass, &args.build, &|line| p.build_line(line),
+                                               &|| p.stopped());
+                    p.built();
+                    Manual::new(built.map_err(|kind| self.error(None, kind))?)
+                },
+            };
+            others.push(FileRun {
+                file,
+                args,
+                report: self.report,
+                journal: None,
+                p,
+                tmp,
+                f,
+                initial_content: self.initial_content.clone(),
+                content: self.content.clone(),
+                edits: self.edits.clone(),
+                files: vec![],
+                manual_changes: vec![],
+                old,
+                names: self.names.clone(),
+                accepted: vec![],
+                ahead: None,
+                db: self.db,
+                db_changes: self.db_changes.clone(),
+                own: self.own.clone(),
+            });
+        }
+        let journal = self.journal.take();
+        let checked = std::thread::scope(|s| {
+            let handles = std::iter::once(&mut *self).chain(&mut others).enumerate()
+                .map(|(lane, run)| {
+                    let share = candidates.iter().skip(lane).step_by(lanes).copied().collect::<Vec<_>>();
+                    s.spawn(move || run.check_all(&share))
+                })
+                .collect::<Vec<_>>();
+            handles.into_iter().map(|h| h.join().unwrap()).collect::<Result<Vec<_>, _>>()
+        });
+        self.journal = journal;
+        checked?;
+
+        let mut accepted = self.accepted.iter().map(|c| c.range).collect::<HashSet<_>>();
+        let mut manual_changes = std::mem::take(&mut self.manual_changes);
+        for other in others {
+            accepted.extend(other.accepted.iter().map(|c| c.range));
+            manual_changes.extend(other.manual_changes);
+        }
+        // left as it was unless the merged rewrites check out
+        self.content = self.initial_content.clone();
+        self.edits = EditList::default();
+        self.files = vec![];
+        if self.p.stopped() || accepted.is_empty() {
+            return Ok(());
+        }
+        // in the order of a serial run, from the file as it was
+        let merge = |reason| Error { file: file.to_string(), option: None, kind: ErrorKind::Merge(reason) };
+        let mut merged = (self.initial_content.clone(), EditList::default(), vec![]);
+        let checked = candidates.iter().copied().filter(|c| accepted.contains(&c.range));
+        for candidate in earlier.iter().chain(checked) {
+            let range = merged.1.map(candidate.range).ok_or_else(|| merge("overlap"))?;
+            let candidate = Candidate { range, ..candidate.clone() };
+            let rewrite = args.pass.rewrite(file, &merged.0, &candidate, &args.convert)
+                .map_err(|_| merge("can't be rewritten together"))?;
+            merged.1.extend(Edit::between(&merged.0, &rewrite.content));
+            merged.0 = rewrite.content;
+            merged.2.extend(rewrite.files);
+        }
+
+        self.p.phase("building merged");
+        write_file(&self.f, &merged.0).map_err(|kind| self.error(None, kind))?;
+        for (path, new) in &merged.2 {
+            let path = self.tmp.path().join(path);
+            match new {
+                Some(new) => write_file(path, new),
+                None => remove_file(path),
+            }.map_err(|kind| self.error(None, kind))?;
+        }
+        let built = build_manual(&self.tmp, self.import(), args.pass, &args.build,
+                                 &|line| self.p.build_line(line));
+        if built.is_err() && self.p.stopped() {
+            return Ok(());
+        }
+        self.p.built();
+        let changed = Manual::new(built.map_err(|kind| self.error(None, kind))?);
+        let expected = manual_changes.iter()
+            .fold(baseline.normalized.clone(), |manual, (from, to)| manual.replacen(from, to, 1));
+        if changed.normalized != expected {
+            let dir = failure_dir(file, "merged");
+            fs::create_dir_all(&dir).map_err(|e| self.error(None, io(&dir)(e)))?;
+            write_file(dir.join("after.nix"), &merged.0)
+                .and_then(|()| write_file(dir.join("before.xml"), &baseline.raw))
+                .and_then(|()| write_file(dir.join("after.xml"), &changed.raw))
+                .and_then(|()| write_file(dir.join("expected.xml"), &expected))
+                .map_err(|kind| self.error(None, kind))?;
+            return Err(merge("don't render together as they do apart"));
+        }
+        if let Some(journal) = self.journal {
+            journal.record(file, &self.initial_content, &merged.0, &merged.2)
+                .map_err(|kind| self.error(None, kind))?;
+        }
+        (self.content, self.edits, self.files) = merged;
+        self.manual_changes = manual_changes;
+        self.old = changed;
+        Ok(())
     }
 
-    let f = format!("{}/{file}", tmp.path().to_str().unwrap());
-    let import = match import {
-        true => Some(f.as_str()),
-        false => None,
-    };
+    /// Run `formatter` over `content` in the file inside the copy of the
+    /// tree, returning the formatted text.
+    fn run_formatter(&self, formatter: &str, content: &str) -> Result<String, ErrorKind> {
+        write_file(&self.f, content)?;
+        let mut words = formatter.split_whitespace();
+        let program = words.next().unwrap_or_default();
+        let result = Command::new(program).args(words).arg(&self.f).output().map_err(io(program))?;
+        if !result.status.success() {
+            return Err(ErrorKind::Format { stderr: String::from_utf8_lossy(&result.stderr).into_owned() });
+        }
+        fs::read_to_string(&self.f).map_err(io(&self.f))
+    }
 
-    p.update_item(format!("old in {file}"));
-    fs::write(&f, initial_content.as_bytes())?;
-    let old = build_manual(&tmp, import)?;
-
-    for (i, &(range, add_parens)) in candidates.iter().enumerate() {
-        let change = convert_one(&content, range, add_parens);
-        p.enter_item(format!("check {}/{} in {file}", i + 1, candidates.len()));
-        fs::write(&f, change.as_bytes())?;
-
-        let write_failure = |result: Result<&str>| -> Result<()> {
-            let failure_prefix = format!("munge-failures/{}.{i}", file.replace('/', "_"));
-            fs::create_dir_all("munge-failures")?;
-            fs::write(format!("{failure_prefix}.before.nix"), initial_content.as_bytes())?;
-            fs::write(format!("{failure_prefix}.after.nix"), change.as_bytes())?;
-            match result {
-                Ok(changed) => {
-                    fs::write(format!("{failure_prefix}.before.xml"), old.as_bytes())?;
-                    fs::write(format!("{failure_prefix}.after.xml"), changed.as_bytes())?;
-                },
-                Err(error) => {
-                    fs::write(format!("{failure_prefix}.after.error"), error.to_string())?;
+    /// Format the file after its rewrites with `--formatter`, keeping the
+    /// result if the manual is unchanged by it. Files the formatter would
+    /// change anyway are left alone, their formatting isn't ours to fix.
+    fn format(&mut self, formatter: &str) -> Result<(), ErrorKind> {
+        self.p.phase("formatting");
+        let formatted = self.run_formatter(formatter, &self.content)
+            .and_then(|formatted| Ok((formatted, self.run_formatter(formatter, &self.initial_content)?)));
+        let formatted = match formatted {
+            Ok((formatted, original)) if original == self.initial_content => formatted,
+            Ok(_) => {
+                status::log(status::VERBOSE, format!("{}: not formatted before, left alone", self.file));
+                return write_file(&self.f, &self.content);
+            },
+            Err(error) => {
+                self.p.error_line(&error.to_string());
+                return write_file(&self.f, &self.content);
+            },
+        };
+        if formatted == self.content {
+            return Ok(());
+        }
+        write_file(&self.f, &formatted)?;
+        self.p.phase("building formatted");
+        let built = build_manual(&self.tmp, self.import(), self.args.pass, &self.args.build,
+                                 &|line| self.p.build_line(line));
+        if built.is_err() && self.p.stopped() {
+            return write_file(&self.f, &self.content);
+        }
+        self.p.built();
+        match built {
+            Ok(manual) if normalized(&manual.raw) == self.old.normalized => {
+                if let Some(journal) = self.journal {
+                    journal.record(self.file, &self.content, &formatted, &[])?;
                 }
+                self.content = formatted;
+                Ok(())
+            },
+            _ => {
+                self.p.error_line(&format!("{}: formatting changed the manual, left unformatted", self.file));
+                write_file(&self.f, &self.content)
+            },
+        }
+    }
+}
+
+/// Where the files of a failed candidate `name` of `file` are kept. Each is
+/// flattened into a single component, so that absolute paths and `..` stay
+/// inside `munge-failures`, with the `<name>` and `*` of option paths and
+/// the like replaced too.
+fn failure_dir(file: &str, name: &str) -> PathBuf {
+    let flatten = |s: &str| -> String {
+        s.chars()
+            .map(|c| match c {
+                '/' | '\\' | '<' | '>' | '*' | '?' | ':' | '"' | '|' => '_',
+                c => c,
+            })
+            .collect()
+    };
+    Path::new("munge-failures").join(flatten(file)).join(flatten(name))
+}
+
+/// Names for the candidates of a file, unique within it and the same from
+/// run to run: the option path, or the byte range where there is none, with
+/// the start added for all but the first candidate of an option.
+fn candidate_names<'a>(candidates: impl Iterator<Item = &'a Candidate>) -> HashMap<TextRange, String> {
+    let mut candidates = candidates.collect::<Vec<_>>();
+    candidates.sort_by_key(|c| (c.range.start(), c.range.end()));
+    let mut taken = HashSet::new();
+    candidates.into_iter()
+        .map(|c| {
+            let (start, end) = (usize::from(c.range.start()), usize::from(c.range.end()));
+            let mut name = match c.option_path.as_str() {
+                "" => format!("{start}-{end}"),
+                option => option.to_string(),
+            };
+            if !taken.insert(name.clone()) {
+                name = format!("{name}@{start}");
             }
-            Ok(())
+            (c.range, name)
+        })
+        .collect()
+}
+
+fn convert_file(file: &str, content: String, (candidates, overlapping): (Candidates, Candidates),
+                args: &Args, p: &StatusReport, report: &Report,
+                journal: &Journal) -> Result<Converted, Error> {
+    let error = |kind| Error { file: file.to_string(), option: None, kind };
+    let mut p = StatusPart::new(p, file, candidates.len());
+    let names = candidate_names(candidates.iter().chain(&overlapping));
+    // candidates that are never checked
+    let unchecked = |candidate: &Candidate, outcome| report.add(report::Entry {
+        id: format!("{file}#{}", names[&candidate.range]),
+        file: file.to_string(),
+        option: candidate.option_path.clone(),
+        start: candidate.range.start().into(),
+        end: candidate.range.end().into(),
+        kind: candidate.kind.name(),
+        outcome,
+        similarity: None,
+        diff: None,
+        allowed: vec![],
+        elsewhere: vec![],
+        tags: report::tags(&content[candidate.range]),
+    });
+    for candidate in &overlapping {
+        unchecked(candidate, Outcome::Overlapping);
+    }
+    let (skipped, candidates) = candidates.into_iter()
+        .partition::<Vec<_>, _>(|c| args.skip.iter().any(|skip| skip.is_match(&c.option_path)));
+    for candidate in &skipped {
+        unchecked(candidate, Outcome::Skipped);
+    }
+    let (plain, candidates) = match (args.only_markup, args.pass) {
+        (Some(_), Pass::MdDoc) => candidates.into_iter().partition(|c| is_plain_text(&content, c)),
+        _ => (vec![], candidates),
+    };
+
+    // plain text is converted right away for `--only-markup accept`
+    let (mut converted, mut edits, mut files, mut accepted) = (content.clone(), EditList::default(), vec![], vec![]);
+    for candidate in plain {
+        if args.only_markup == Some(PlainText::Skip) {
+            unchecked(&candidate, Outcome::Skipped);
+            continue;
+        }
+        let Some(range) = edits.map(candidate.range) else {
+            unchecked(&candidate, Outcome::Overlapping);
+            continue;
+        };
+        p.enter_item(format!("plain text ({})", candidate.option_path));
+        let Ok(rewrite) = args.pass.rewrite(file, &converted, &Candidate { range, ..candidate.clone() }, &args.convert) else {
+            p.failed(Outcome::RewriteFailed, String::new);
+            unchecked(&candidate, Outcome::RewriteFailed);
+            continue;
         };
+        journal.record(file, &converted, &rewrite.content, &rewrite.files).map_err(error)?;
+        p.changed_item();
+        unchecked(&candidate, Outcome::Unchecked);
+        edits.extend(Edit::between(&converted, &rewrite.content));
+        converted = rewrite.content;
+        files.extend(rewrite.files);
+        accepted.push(candidate);
+    }
+    let unchanged = |converted, files| Converted {
+        file: file.to_string(),
+        content: converted,
+        files,
+        manual_changes: vec![],
+    };
+    if candidates.is_empty() {
+        return Ok(unchanged(converted, files));
+    }
 
-        match build_manual(&tmp, import) {
-            Ok(changed) => {
-                if normalize(&old) == normalize(&changed) {
-                    p.changed_item();
-                    content = change;
-                } else {
-                    write_failure(Ok(&changed))?;
-                }
-            },
-            Err(error) => write_failure(Err(error))?,
+    p.phase("copying");
+    let tmp = tempdir().map_err(|e| error(io(std::env::temp_dir())(e)))?;
+    copy_tree(tmp.path()).map_err(error)?;
+
+    let f = tmp.path().join(file);
+    p.phase("building unchanged manual");
+    let built = match args.import {
+        false => baseline_manual(&tmp, args.pass, &args.build, &mut p),
+        true => build_imported(tmp.path(), f.to_str().unwrap(), &[&content], args.pass, &args.build,
+                               &|line| p.build_line(line), &|| p.stopped()).map(|raw| {
+            p.built();
+            Manual::new(raw)
+        }),
+    };
+    let old = match built {
+        // the build was interrupted along with us
+        Err(_) if p.stopped() => return Ok(unchanged(converted, files)),
+        old => old.map_err(error)?,
+    };
+
+    let own = options_db::declared_in(&old.normalized, file);
+    let mut run = FileRun {
+        file,
+        args,
+        report,
+        journal: Some(journal),
+        p,
+        tmp,
+        f,
+        initial_content: content,
+        content: converted,
+        edits,
+        files,
+        manual_changes: vec![],
+        old,
+        names,
+        accepted,
+        ahead: None,
+        db: OPTIONS_DB.get(),
+        db_changes: HashMap::new(),
+        own,
+    };
+    let mut candidates = candidates.iter().collect::<Vec<_>>();
+    if args.batch_pure_wraps && args.explain.is_none() {
+        candidates = run.check_pure_wraps(&candidates)?;
+    }
+    // the trace of `--explain` is only readable one candidate at a time
+    let lanes = args.jobs_per_file.min(candidates.len());
+    match lanes {
+        0 | 1 => run.check_all(&candidates)?,
+        _ if args.explain.is_some() => run.check_all(&candidates)?,
+        _ => run.check_in_lanes(&candidates, lanes)?,
+    }
+    if let Some(formatter) = &args.formatter {
+        if run.content != run.initial_content && !run.p.stopped() {
+            run.format(formatter).map_err(error)?;
         }
     }
 
-    fs::write(&f, initial_content.as_bytes())?;
-    Ok(content)
+    Ok(Converted {
+        file: file.to_string(),
+        content: run.content,
+        files: run.files,
+        manual_changes: run.manual_changes,
+    })
+}
+
+/// Convert NixOS option descriptions from DocBook to Markdown, checking each
+/// conversion by rebuilding the manual and comparing the rendered output.
+#[derive(clap::Subcommand, Debug)]
+enum Subcommand {
+    /// Check that Nix and the tree at PATH are ready for a run, without
+    /// changing anything.
+    Doctor { path: PathBuf },
+    /// Print the completion script for SHELL.
+    Completions { shell: clap_complete::Shell },
+    /// Print the man page.
+    Manpage,
+    /// Print a Markdown summary of the JSON written by `--report`, for the
+    /// description of the pull request with the changes.
+    Summarize { report: PathBuf },
+    /// Print how the built manual B differs from A option by option, both
+    /// normalized the way manuals are compared when checking candidates.
+    DiffManuals { a: PathBuf, b: PathBuf },
+    /// List the candidates of FILES with the DocBook tags in each and
+    /// whether the pass converts them all, without building anything.
+    Scan {
+        /// Write the list to this file as JSON instead.
+        #[arg(long, value_name = "FILE")]
+        json: Option<PathBuf>,
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+}
+
+/// What `--only-markup` does with candidates of plain text.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum PlainText {
+    /// Leave them as they are.
+    Skip,
+    /// Convert them without checking.
+    Accept,
+}
+
+#[derive(Parser, Debug)]
+#[command(subcommand_negates_reqs = true, args_override_self = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Subcommand>,
+
+    /// Evaluate each file on its own, for modules that aren't part of the
+    /// tree's default module list. Their options are rendered by a harness
+    /// rather than the manual, which takes seconds for a leaf module; the
+    /// manuals of chapters add the files to the `imports` of the system.
+    #[arg(long)]
+    import: bool,
+
+    /// Build the unchanged manual that rewrites are checked against from
+    /// this git revision rather than the working tree, so that doc changes
+    /// already on the branch have to render as they do there as well.
+    #[arg(long, value_name = "REV", conflicts_with = "import")]
+    baseline_ref: Option<String>,
+
+    /// The rewrite to perform.
+    #[arg(long, value_enum, default_value_t)]
+    pass: Pass,
+
+    #[command(flatten)]
+    convert: ConvertOptions,
+
+    #[command(flatten)]
+    build: BuildOptions,
+
+    /// Write the outcome of every candidate to this file as JSON.
+    #[arg(long, value_name = "FILE")]
+    report: Option<String>,
+
+    /// Only print failures and a summary at the end.
+    #[arg(short, long, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print each accepted change instead of status lines, and each
+    /// `nix-build` command if given twice.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Show an interactive interface instead of the status lines, with a
+    /// list of failures and the option to skip files or abort the run.
+    #[arg(long, conflicts_with_all = ["quiet", "verbose"])]
+    tui: bool,
+
+    /// Send a desktop notification when the run is over.
+    #[arg(long)]
+    notify: bool,
+
+    /// POST a JSON message to this URL when the run is over.
+    #[arg(long, value_name = "URL")]
+    notify_webhook: Option<String>,
+
+    /// Also notify once this many candidates have failed.
+    #[arg(long, value_name = "N")]
+    notify_failures: Option<usize>,
+
+    /// Remember the candidates found in each file here, so that files
+    /// unchanged since an earlier run don't have to be parsed again.
+    #[arg(long, value_name = "FILE")]
+    cache: Option<String>,
+
+    /// Run `nix-store --gc` at the end, to get rid of the manuals built for
+    /// checking. Collects all other garbage in the store as well.
+    #[arg(long)]
+    gc_after: bool,
+
+    /// Run even if the tree seems to be locked by another run.
+    #[arg(long)]
+    force: bool,
+
+    /// When interrupted, still write the changes accepted so far instead of
+    /// leaving them in the journal.
+    #[arg(long)]
+    write_on_interrupt: bool,
+
+    /// Record accepted changes here as they happen, for `--recover`.
+    #[arg(long, value_name = "FILE", default_value = "munge-journal.jsonl")]
+    journal: String,
+
+    /// Apply the changes recorded in the journal by an interrupted run
+    /// instead of converting anything.
+    #[arg(long)]
+    recover: bool,
+
+    /// TOML file of differences between the expected and the built manual
+    /// to accept, see `AllowList`.
+    #[arg(long, value_name = "FILE", value_parser = AllowList::load)]
+    allowed_diffs: Option<AllowList>,
+
+    /// Keep rewrites whose manual differs from the expected one only in text
+    /// matching REGEX, such as `[‘’]`, recording the diff in the report.
+    /// Only for differences reviewed to be harmless.
+    #[arg(long, value_name = "REGEX")]
+    force_apply_pattern: Vec<Regex>,
+
+    /// Before writing anything, build the manual with all accepted changes
+    /// at once and check it against the unchanged one, for rewrites of
+    /// different files interacting.
+    #[arg(long)]
+    final_check: bool,
+
+    /// Save the normalized manual of the unchanged tree, that of the tree
+    /// with all accepted changes, and their diff to this directory.
+    #[arg(long, value_name = "DIR")]
+    artifacts: Option<PathBuf>,
+
+    /// Check the candidates of each file in this many copies of the tree at
+    /// once, then check the rewrites kept in all of them together. Hides
+    /// build latency for files with many options, at the cost of more
+    /// builds.
+    #[arg(long, value_name = "K", default_value_t = 1,
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1 ..))]
+    jobs_per_file: usize,
+
+    /// Check rewrites of options by rendering only the option and comparing
+    /// it with its entry in this database of all rendered options, made
+    /// from the manual if the file doesn't exist yet. Remove the file when
+    /// the tree changes.
+    #[arg(long, value_name = "FILE")]
+    options_db: Option<PathBuf>,
+
+    /// Leave candidates of plain text alone, whose conversion only wraps
+    /// them in `lib.mdDoc`, or with `accept` convert them without building
+    /// the manual. Differences they make in it then show up in the checks
+    /// of other candidates of the file and in `--final-check`.
+    #[arg(long, value_enum, value_name = "MODE", num_args = 0 ..= 1, default_missing_value = "skip")]
+    only_markup: Option<PlainText>,
+
+    /// Check the rewrites of a file that only wrap candidates in `lib.mdDoc`
+    /// with one build for all of them, instead of one each. They are
+    /// checked one by one if that build doesn't come out as expected.
+    #[arg(long)]
+    batch_pure_wraps: bool,
+
+    /// Number of files to convert at once.
+    #[arg(long, value_name = "N", default_value_t = 16)]
+    threads: usize,
+
+    /// Leave files and options whose path matches REGEX alone.
+    #[arg(long, value_name = "REGEX")]
+    skip: Vec<Regex>,
+
+    /// Leave out text matching REGEX from manuals before comparing them,
+    /// for differences that don't matter to the tree, like generated IDs.
+    #[arg(long, value_name = "REGEX")]
+    normalize: Vec<Regex>,
+
+    /// Ignore the `nix-doc-munge.toml` of the tree.
+    #[arg(long)]
+    no_config: bool,
+
+    /// Start building the next rewrite of a file while comparing the manual
+    /// of the last one, on the guess that it is kept. Costs a wasted build
+    /// for every rewrite that isn't.
+    #[arg(long)]
+    pipeline: bool,
+
+    /// Formatter to run over each changed file afterwards, such as
+    /// `nixpkgs-fmt`, given the file to format in place. Formatted files
+    /// are checked again, and files not formatted before are left alone.
+    #[arg(long, value_name = "COMMAND")]
+    formatter: Option<String>,
+
+    /// Split the changes into N commits of whole directories, each about
+    /// the same size, to be sent as separate pull requests.
+    #[arg(long, value_name = "dir:N", value_parser = SplitBy::parse)]
+    split_by: Option<SplitBy>,
+
+    /// What to make of the commits of `--split-by`.
+    #[arg(long, value_enum, default_value_t, requires = "split_by")]
+    split_output: SplitOutput,
+
+    /// Replace the `munge/split-N` branches of an earlier `--split-by`,
+    /// rather than failing before the run if there are any.
+    #[arg(long, requires = "split_by")]
+    split_overwrite: bool,
+
+    /// Save the description of each accepted candidate to this fixture
+    /// corpus, such as `tests/fixtures` of a checkout of this tool.
+    #[arg(long, value_name = "DIR")]
+    record_fixture: Option<PathBuf>,
+
+    /// Save the description of each checked candidate to this directory,
+    /// with its rewrite and outcome, named by its hash instead of its file
+    /// and option. For growing the fixture corpus from real trees.
+    #[arg(long, value_name = "DIR")]
+    dump_candidates: Option<PathBuf>,
+
+    /// Check the candidates of this file one after another, printing the
+    /// rules that fired on each and the verdict, without changing the tree.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["tui", "recover"])]
+    explain: Option<String>,
+
+    /// Print the rewrites of the candidates of this file without checking
+    /// them, and again whenever it changes, for working on the rules of
+    /// the pass. Nothing is written.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["tui", "recover", "explain"])]
+    watch: Option<String>,
+
+    /// Read the files to convert from FILE, one per line, or from standard
+    /// input for `-`, in addition to those given as arguments.
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<String>,
+
+    /// Nix files to convert, relative to the root of the tree. `-` reads
+    /// them from standard input, like `--files-from -`.
+    #[arg(required_unless_present_any = ["recover", "explain", "watch", "files_from"])]
+    files: Vec<String>,
+}
+
+/// Replace `-` in `files` and add `--files-from`, so that the list can be
+/// longer than a command line allows.
+fn read_file_list(args: &mut Args) -> Result<()> {
+    let mut lists = args.files_from.take().into_iter().collect::<Vec<_>>();
+    if args.files.iter().any(|f| f == "-") {
+        args.files.retain(|f| f != "-");
+        lists.push("-".to_string());
+    }
+    lists.dedup();
+    for list in lists {
+        let text = match list.as_str() {
+            "-" => std::io::read_to_string(std::io::stdin())?,
+            path => fs::read_to_string(path).with_context(|| format!("reading the file list {path}"))?,
+        };
+        args.files.extend(text.lines().map(str::trim).filter(|l| !l.is_empty()).map(str::to_string));
+    }
+    Ok(())
+}
+
+/// Run `--explain`: convert `file` on this thread with all status output
+/// off, so that only the trace printed by `FileRun::check` remains.
+fn explain(file: &str, args: &Args) -> Result<()> {
+    let content = fs::read_to_string(file)?;
+    let scan = args.pass.scan(&content, &args.convert).map_err(parse(file))?;
+    status::set_verbosity(status::SILENT);
+    let printer = StatusReport::new(1, scan.0.len(), false, Notifier::default());
+    let report = Report::default();
+    let dir = tempdir()?;
+    let journal = Journal::create(&dir.path().join("journal.jsonl").to_string_lossy())?;
+    convert_file(file, content, scan, args, &printer, &report, &journal)?;
+    Ok(())
 }
 
 fn main() -> Result<()> {
-    let (skip, import) = match env::args().skip(1).next() {
-        Some(s) if s == "--import" => (2, true),
-        _ => (1, false),
-    };
+    // before anything is built, so that an interrupt never goes unhandled;
+    // until the workers start there's nothing to wind down
+    ctrlc::set_handler(|| {
+        if PRINTER.get().is_none_or(|printer| printer.abort()) {
+            std::process::exit(130);
+        }
+    })?;
+    let config = tempdir()?;
+    let mut args = Args::parse_from(config::command_line(config.path())?);
+    read_file_list(&mut args)?;
+    args.files.retain(|file| !args.skip.iter().any(|skip| skip.is_match(file)));
+    NORMALIZE.set(args.normalize.clone()).unwrap();
+    let args = Arc::new(args);
+    match &args.command {
+        Some(Subcommand::Doctor { path }) => return doctor::run(path, &args),
+        Some(Subcommand::Summarize { report }) => return summary::run(report),
+        Some(Subcommand::DiffManuals { a, b }) => return diff_manuals::run(a, b),
+        Some(Subcommand::Scan { json, files }) => {
+            return scan::run(files, json.as_deref(), args.pass, &args.convert);
+        },
+        Some(Subcommand::Completions { shell }) => {
+            clap_complete::generate(*shell, &mut Args::command(), "nix-doc-munge", &mut std::io::stdout());
+            return Ok(());
+        }
+        Some(Subcommand::Manpage) => {
+            clap_mangen::Man::new(Args::command()).render(&mut std::io::stdout())?;
+            return Ok(());
+        }
+        None => {}
+    }
+    if let Some(file) = &args.watch {
+        return watch::run(file, args.pass, &args.convert);
+    }
+    let _lock = RunLock::acquire(args.force)?;
+    status::set_verbosity(match args.quiet {
+        true => status::QUIET,
+        false => args.verbose.min(2) as i8,
+    });
+    if args.recover {
+        return journal::recover(&args.journal);
+    }
+    if let Some(file) = &args.explain {
+        return explain(file, &args);
+    }
+    if args.split_by.is_some() {
+        split::check(args.split_output, args.split_overwrite)?;
+    }
 
-    let pool = ThreadPool::new(16);
+    let pool = ThreadPool::new(args.threads);
     let changes = Arc::new(Mutex::new(vec![]));
+    let errors = Arc::new(Mutex::new(vec![]));
 
-    let total_items = env::args().skip(skip).map(|file| {
+    let mut cache = match &args.cache {
+        Some(path) => CandidateCache::load(path)?,
+        None => CandidateCache::default(),
+    };
+    // keep what was parsed for the total, the workers start from it
+    let mut scanned = vec![];
+    let mut unsupported = UnsupportedTags::default();
+    for file in &args.files {
         let content = fs::read_to_string(file)?;
-        let candidates = find_candidates(&content);
-        Ok(candidates.len())
-    }).sum::<Result<usize>>()?;
+        // reported with the files that fail to convert, rather than stopping
+        // the others
+        let scan = match cache.scan(args.pass, &content, &args.convert) {
+            Ok(scan) => scan,
+            Err(error) => {
+                errors.lock().unwrap().push(parse(file)(error));
+                continue;
+            },
+        };
+        unsupported.scan(args.pass, file, &content, &scan.0, &args.convert);
+        if !scan.0.is_empty() || !scan.1.is_empty() {
+            scanned.push((file.clone(), content, scan));
+        }
+    }
+    if let Some(path) = &args.cache {
+        cache.save(path)?;
+    }
+    // rather than finding out from the failures at the end
+    unsupported.print();
+    let total_items = scanned.iter().map(|(_, _, (c, _))| c.len()).sum();
+    // candidates of a file are mostly verified one after another, so start the
+    // biggest files first to keep them from serializing the end of the run
+    scanned.sort_by_key(|(_, _, (c, _))| std::cmp::Reverse(c.len()));
 
-    let printer = Arc::new(StatusReport::new(env::args().count() - skip, total_items));
+    let notifier = Notifier {
+        desktop: args.notify,
+        webhook: args.notify_webhook.clone(),
+        failures: args.notify_failures,
+    };
+    let printer = Arc::new(StatusReport::new(scanned.len(), total_items, args.tui, notifier.clone()));
+    let report = Arc::new(Report::default());
+    let journal = Arc::new(Journal::create(&args.journal)?);
+    if let Some(rev) = &args.baseline_ref {
+        println!("building the manual of {rev} to compare with");
+        let tmp = tempdir()?;
+        copy_revision(rev, tmp.path())?;
+        let manual = Manual::new(build_manual(&tmp, None, args.pass, &args.build, &|_| {})?);
+        *BASELINE.lock().unwrap() = Some(manual);
+    }
+    if let Some(path) = &args.options_db {
+        let db = OptionsDb::load_or_render(path, || {
+            if let Some(manual) = &*BASELINE.lock().unwrap() {
+                return Ok(manual.normalized.clone());
+            }
+            println!("building the manual to render the options of");
+            let tmp = tempdir()?;
+            copy_tree(tmp.path())?;
+            let manual = Manual::new(build_manual(&tmp, None, args.pass, &args.build, &|_| {})?);
+            // which is the unchanged manual the workers start from too
+            *BASELINE.lock().unwrap() = Some(Arc::clone(&manual));
+            Ok(manual.normalized.clone())
+        })?;
+        OPTIONS_DB.set(db).ok().unwrap();
+    }
 
-    for file in env::args().skip(skip) {
+    PRINTER.set(Arc::clone(&printer)).ok().unwrap();
+    for (file, content, scan) in scanned {
         pool.execute({
-            let (changes, printer) = (Arc::clone(&changes), Arc::clone(&printer));
+            let (args, changes, printer) = (Arc::clone(&args), Arc::clone(&changes), Arc::clone(&printer));
+            let errors = Arc::clone(&errors);
+            let (report, journal) = (Arc::clone(&report), Arc::clone(&journal));
             move || {
-                printer.enter_file(&file);
-                let new = convert_file(&file, import, &printer).unwrap();
-                changes.lock().unwrap().push((file, new));
+                match convert_file(&file, content, scan, &args, &printer, &report, &journal) {
+                    Ok(converted) => changes.lock().unwrap().push(converted),
+                    Err(error) => errors.lock().unwrap().push(error),
+                }
             }
         });
     }
+    if args.tui {
+        tui::run(&printer, || pool.active_count() + pool.queued_count() == 0)?;
+    }
     pool.join();
+    let totals = printer.lock().totals();
+    status::log(status::QUIET, &totals);
+    if printer.lock().abort {
+        println!("interrupted at {totals}");
+        notifier.send(&format!("interrupted at {totals}"))?;
+        if !args.write_on_interrupt {
+            bail!("no changes were written, use --recover to apply those accepted so far");
+        }
+    }
 
-    for (file, content) in changes.lock().unwrap().iter() {
-        fs::write(&file, content.as_bytes())?;
+    if let Some(path) = &args.report {
+        report.write(path)?;
+    }
+    // in the order of the files, not that in which the workers finished
+    let mut changes = changes.lock().unwrap();
+    changes.sort_by(|a, b| a.file.cmp(&b.file));
+    if (args.final_check || args.artifacts.is_some()) && !changes.is_empty() {
+        final_check::run(&changes, &args)?;
+    }
+    for Converted { file, content, files, .. } in changes.iter() {
+        fs::write(file, content.as_bytes())?;
+        for (path, new) in files {
+            match new {
+                Some(new) => fs::write(path, new.as_bytes())?,
+                None => fs::remove_file(path)?,
+            }
+        }
+    }
+    if let Some(split) = args.split_by.filter(|_| !changes.is_empty()) {
+        split::run(&changes, split, args.split_output, args.split_overwrite, args.pass)?;
+    }
+    let mut errors = errors.lock().unwrap();
+    errors.sort_by(|a, b| a.file.cmp(&b.file));
+    if !errors.is_empty() {
+        for error in errors.iter() {
+            eprintln!("error: {error}");
+        }
+        bail!("{} files couldn't be converted, the changes accepted in them before the error \
+               are in {}", errors.len(), args.journal);
+    }
+    // every worker is done, so this is the only reference left
+    Arc::into_inner(journal).unwrap().finish()?;
+    if args.gc_after {
+        collect_garbage(&args.build)?;
     }
+    notifier.send(&format!("done, {totals}"))?;
 
     Ok(())
 }
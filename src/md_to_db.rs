@@ -0,0 +1,101 @@
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use anyhow::{Context, Result, bail};
+
+/// Where nixpkgs of the time Markdown release notes were introduced writes
+/// the DocBook it generates from them, relative to the manual.
+pub const FROM_MD: &str = "from_md";
+
+/// A directory mirroring the file system down to a directory of the tree,
+/// with links to everything but the directories on the way and those left
+/// out. Removed when dropped.
+struct Scratch(PathBuf);
+
+impl Scratch {
+    fn new() -> Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("nix-doc-munge-md-to-db-{}-{}", std::process::id(), COUNT.fetch_add(1, Ordering::Relaxed));
+        let dir = std::env::temp_dir().join(name);
+        fs::create_dir(&dir).with_context(|| format!("creating {}", dir.display()))?;
+        Ok(Self(dir))
+    }
+
+    /// Mirror `dir`, an absolute path, leaving out its entries named in
+    /// `without`, and return where the mirror of `dir` is.
+    fn mirror(&self, dir: &Path, without: &[&str]) -> Result<PathBuf> {
+        let (mut real, mut mirror) = (PathBuf::from("/"), self.0.clone());
+        let names = dir.components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for depth in 0 ..= names.len() {
+            let skip = |name: &str| match names.get(depth) {
+                Some(next) => *next == name,
+                None => without.contains(&name),
+            };
+            for entry in fs::read_dir(&real).with_context(|| format!("reading {}", real.display()))? {
+                let name = entry?.file_name();
+                if !skip(&name.to_string_lossy()) {
+                    link(&real.join(&name), &mirror.join(&name))?;
+                }
+            }
+            let Some(next) = names.get(depth) else {
+                break;
+            };
+            real.push(next);
+            mirror.push(next);
+            fs::create_dir(&mirror)?;
+        }
+        Ok(mirror)
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(unix)]
+fn link(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(not(unix))]
+fn link(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "linking to the tree needs a Unix system"))
+}
+
+/// The DocBook the tree's `md-to-db.sh` in `manual` generates from the
+/// Markdown release notes `markdown`, to be saved as `name` in them. The
+/// script converts every Markdown file of the manual, so it runs in a mirror
+/// of the tree with this file the only one, and no generated DocBook yet.
+pub fn release_notes(manual: &Path, name: &str, markdown: &str) -> Result<String> {
+    let script = manual.join("md-to-db.sh");
+    if !script.exists() {
+        bail!("{} doesn't exist, the tree doesn't generate DocBook from Markdown", script.display());
+    }
+    let scratch = Scratch::new()?;
+    let manual = std::path::absolute(manual)?;
+    let mirror = scratch.mirror(&manual, &["release-notes", FROM_MD])?;
+    fs::create_dir(mirror.join("release-notes"))?;
+    fs::write(mirror.join("release-notes").join(name), markdown)?;
+
+    let result = Command::new(mirror.join("md-to-db.sh"))
+        .current_dir(&mirror)
+        .output()
+        .with_context(|| format!("running {}", script.display()))?;
+    if !result.status.success() {
+        bail!("{} failed: {}", script.display(), String::from_utf8_lossy(&result.stderr).trim());
+    }
+    let generated = Path::new(FROM_MD).join("release-notes").join(name).with_extension("xml");
+    fs::read_to_string(mirror.join(&generated))
+        .with_context(|| format!("{} didn't generate {}", script.display(), generated.display()))
+}
@@ -0,0 +1,286 @@
+use rnix::{NodeOrToken, SyntaxKind};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StringKind {
+    /// `"…"`
+    Quoted,
+    /// `''…''`
+    Indented,
+}
+
+/// Interpolations are replaced in `NixString::value` by a single character
+/// from the private use area each, so the conversion passes carry them along
+/// as opaque characters wherever they end up.
+const PLACEHOLDER_BASE: u32 = 0xF0000;
+
+/// A Nix string literal, decoded to the text Nix would see. Conversion works
+/// on `value`; `encode` turns the converted text back into a literal of the
+/// same kind.
+#[derive(Clone, Debug)]
+pub struct NixString {
+    pub kind: StringKind,
+    pub value: String,
+    /// Source text of each interpolation, including `${` and `}`.
+    interpolations: Vec<String>,
+    /// Whether a quoted string spelled its newlines out literally rather than
+    /// as `\n`. Re-encoding keeps whichever style the author used.
+    raw_newlines: bool,
+}
+
+impl NixString {
+    /// Decode `src`, which must be exactly one string literal. Returns `None`
+    /// for anything else.
+    pub fn parse(src: &str) -> Option<Self> {
+        let ast = rnix::parse(src).as_result().ok()?;
+        let node = ast.node().first_child()?;
+        if node.kind() != SyntaxKind::NODE_STRING
+            || usize::from(node.text_range().len()) != src.trim().len()
+        {
+            return None;
+        }
+
+        let kind = match src.trim_start() {
+            s if s.starts_with("''") => StringKind::Indented,
+            _ => StringKind::Quoted,
+        };
+        let mut value = String::new();
+        let mut interpolations = vec![];
+        let mut raw_newlines = false;
+        for child in node.children_with_tokens() {
+            match child {
+                NodeOrToken::Token(t) if t.kind() == SyntaxKind::TOKEN_STRING_CONTENT => {
+                    if t.text().chars().any(is_placeholder) {
+                        return None;
+                    }
+                    raw_newlines |= t.text().contains('\n');
+                    value.push_str(&unescape(t.text(), kind));
+                }
+                NodeOrToken::Token(_) => (),
+                NodeOrToken::Node(n) => {
+                    value.push(char::from_u32(PLACEHOLDER_BASE + interpolations.len() as u32)?);
+                    interpolations.push(n.text().to_string());
+                }
+            }
+        }
+
+        Some(Self { kind, value, interpolations, raw_newlines })
+    }
+
+    pub fn has_interpolations(&self) -> bool {
+        !self.interpolations.is_empty()
+    }
+
+    /// Encode `value` as a literal of the same kind as this string.
+    pub fn encode(&self, value: &str) -> String {
+        match self.kind {
+            StringKind::Quoted => {
+                let mut result = String::from("\"");
+                let mut chars = value.chars().peekable();
+                while let Some(c) = chars.next() {
+                    match c {
+                        c if is_placeholder(c) => result.push_str(self.interpolation(c)),
+                        '\\' => result.push_str("\\\\"),
+                        '"' => result.push_str("\\\""),
+                        '$' if chars.peek() == Some(&'{') => result.push_str("\\$"),
+                        '\n' if !self.raw_newlines => result.push_str("\\n"),
+                        '\t' => result.push_str("\\t"),
+                        '\r' => result.push_str("\\r"),
+                        c => result.push(c),
+                    }
+                }
+                result.push('"');
+                result
+            }
+            StringKind::Indented => {
+                let mut result = String::from("''");
+                let mut chars = value.chars().peekable();
+                while let Some(c) = chars.next() {
+                    match c {
+                        c if is_placeholder(c) => result.push_str(self.interpolation(c)),
+                        '\'' if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            result.push_str("'''");
+                        }
+                        // a trailing quote would merge with the closing delimiter,
+                        // and rnix can't lex its escape `''\'` right before it
+                        '\'' if chars.peek().is_none() => result.push_str("${\"'\"}"),
+                        '$' if chars.peek() == Some(&'{') => result.push_str("''$"),
+                        '\r' => result.push_str("''\\r"),
+                        c => result.push(c),
+                    }
+                }
+                result.push_str("''");
+                result
+            }
+        }
+    }
+
+    /// The indentation Nix strips from every line of an indented string. Zero
+    /// for quoted strings.
+    pub fn common_indent(&self) -> usize {
+        if self.kind == StringKind::Quoted {
+            return 0;
+        }
+        let mut lines = self.value.split('\n').peekable();
+        // a whitespace-only first line is dropped entirely
+        if lines.peek().is_some_and(|l| l.trim_start_matches([' ', '\t']).is_empty()) {
+            lines.next();
+        }
+        lines
+            .filter(|l| !l.trim_start_matches([' ', '\t']).is_empty())
+            .map(|l| l.len() - l.trim_start_matches(' ').len())
+            .min()
+            .unwrap_or(0)
+    }
+
+    /// The text Nix evaluates the string to, with indentation stripped from
+    /// indented strings. Interpolations stay placeholders.
+    pub fn text(&self) -> String {
+        if self.kind == StringKind::Quoted {
+            return self.value.clone();
+        }
+        let indent = self.common_indent();
+        let mut lines = self.value.split('\n').peekable();
+        if lines.peek().is_some_and(|l| l.trim_start_matches([' ', '\t']).is_empty()) {
+            lines.next();
+        }
+        lines
+            .map(|l| l.get(indent ..).unwrap_or(l.trim_start_matches(' ')))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Encode `value` as a quoted string, with newlines escaped.
+    pub fn encode_quoted(&self, value: &str) -> String {
+        let quoted = Self { kind: StringKind::Quoted, raw_newlines: false, ..self.clone() };
+        quoted.encode(value)
+    }
+
+    /// Encode `value` as an indented string for a binding whose line is
+    /// indented by `indent`. The contents go one level deeper and the closing
+    /// quotes get a line of their own, as is usual in nixpkgs.
+    pub fn encode_indented(&self, value: &str, indent: &str) -> String {
+        let mut body = String::from("\n");
+        for line in value.strip_suffix('\n').unwrap_or(value).split('\n') {
+            if !line.is_empty() {
+                body.push_str(indent);
+                body.push_str("  ");
+                body.push_str(line);
+            }
+            body.push('\n');
+        }
+        body.push_str(indent);
+
+        let indented = Self { kind: StringKind::Indented, ..self.clone() };
+        indented.encode(&body)
+    }
+
+    fn interpolation(&self, placeholder: char) -> &str {
+        &self.interpolations[(u32::from(placeholder) - PLACEHOLDER_BASE) as usize]
+    }
+}
+
+fn is_placeholder(c: char) -> bool {
+    (PLACEHOLDER_BASE .. PLACEHOLDER_BASE + 0xFFFE).contains(&u32::from(c))
+}
+
+/// Interpret escape sequences without touching indentation, unlike Nix
+/// itself. Indentation stripping is irrelevant to the Markdown conversion and
+/// keeping it lets `encode` reproduce the original layout.
+fn unescape(raw: &str, kind: StringKind) -> String {
+    let mut result = String::with_capacity(raw.len());
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (kind, c) {
+            (StringKind::Quoted, '\\') => match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('r') => result.push('\r'),
+                Some('t') => result.push('\t'),
+                Some(c) => result.push(c),
+                None => result.push('\\'),
+            },
+            (StringKind::Indented, '\'') if chars.peek() == Some(&'\'') => {
+                chars.next();
+                match chars.next() {
+                    Some('\'') => result.push_str("''"),
+                    Some('$') => result.push('$'),
+                    Some('\\') => match chars.next() {
+                        Some('n') => result.push('\n'),
+                        Some('r') => result.push('\r'),
+                        Some('t') => result.push('\t'),
+                        Some(c) => result.push(c),
+                        None => (),
+                    },
+                    Some(c) => {
+                        result.push_str("''");
+                        result.push(c);
+                    }
+                    None => result.push_str("''"),
+                }
+            }
+            (_, c) => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NixString, StringKind};
+
+    fn round_trip(src: &str) {
+        let string = NixString::parse(src).unwrap();
+        assert_eq!(string.encode(&string.value), src);
+    }
+
+    #[test]
+    fn quoted_strings_round_trip() {
+        round_trip(r#""plain""#);
+        round_trip(r#""a \"quote\", a \\ and \${not} ${interpolated}""#);
+        round_trip(r#""tab\tand\nnewline""#);
+        round_trip("\"raw\nnewline\"");
+    }
+
+    #[test]
+    fn indented_strings_round_trip() {
+        round_trip("''\n  plain\n''");
+        round_trip("''\n  two ''' quotes, ''${not} ${interpolated} and a \\ \n''");
+        round_trip("''\n  ends with a quote${\"'\"}''");
+    }
+
+    #[test]
+    fn values_are_unescaped() {
+        let quoted = NixString::parse(r#""\${a} \\ \"b\"""#).unwrap();
+        assert_eq!(quoted.value, r#"${a} \ "b""#);
+        let indented = NixString::parse("''''${a} ''' \\ ''\\n''").unwrap();
+        assert_eq!(indented.value, "${a} '' \\ \n");
+    }
+
+    #[test]
+    fn values_are_escaped() {
+        let quoted = NixString::parse(r#""""#).unwrap();
+        assert_eq!(quoted.encode(r#"${a} \ "b""#), r#""\${a} \\ \"b\"""#);
+        let indented = NixString::parse("''''").unwrap();
+        assert_eq!(indented.encode("${a} '' \\ '"), "''''${a} ''' \\ ${\"'\"}''");
+    }
+
+    #[test]
+    fn indented_strings_are_dedented() {
+        let string = NixString::parse("''\n    a\n      b\n\n    c\n  ''").unwrap();
+        assert_eq!(string.common_indent(), 4);
+        assert_eq!(string.text(), "a\n  b\n\nc\n");
+        let quoted = NixString::parse("\"  a\n  b\"").unwrap();
+        assert_eq!((quoted.common_indent(), quoted.text()), (0, "  a\n  b".to_string()));
+    }
+
+    #[test]
+    fn indented_encoding_nests_the_lines() {
+        let string = NixString::parse(r#""${x}""#).unwrap();
+        let placeholder = string.value.clone();
+        let encoded = string.encode_indented(&format!("a ''\n\nb {placeholder}\n"), "  ");
+        assert_eq!(encoded, "''\n    a '''\n\n    b ${x}\n  ''");
+        let parsed = NixString::parse(&encoded).unwrap();
+        assert_eq!((parsed.kind, parsed.text()), (StringKind::Indented, format!("a ''\n\nb {placeholder}\n")));
+    }
+}
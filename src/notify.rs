@@ -0,0 +1,41 @@
+use std::process::{Command, Stdio};
+
+use anyhow::{Result, bail};
+
+/// Where to announce the end of a run, for `--notify`.
+#[derive(Clone, Default)]
+pub struct Notifier {
+    pub desktop: bool,
+    pub webhook: Option<String>,
+    /// Also announce when this many candidates have failed.
+    pub failures: Option<usize>,
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let result = command.stdin(Stdio::null()).output()?;
+    if !result.status.success() {
+        bail!("{:?} failed: {}", command.get_program(), String::from_utf8_lossy(&result.stderr));
+    }
+    Ok(())
+}
+
+impl Notifier {
+    /// Send `message` everywhere requested, through `notify-send` (or
+    /// `osascript` on macOS) and `curl`. The webhook receives
+    /// `{"text": message}`.
+    pub fn send(&self, message: &str) -> Result<()> {
+        if self.desktop && cfg!(target_os = "macos") {
+            let script = format!("display notification {message:?} with title \"nix-doc-munge\"");
+            run(Command::new("osascript").args(["-e", &script]))?;
+        } else if self.desktop {
+            run(Command::new("notify-send").args(["nix-doc-munge", message]))?;
+        }
+        if let Some(url) = &self.webhook {
+            let body = serde_json::json!({ "text": message }).to_string();
+            run(Command::new("curl")
+                .args(["-fsS", "-X", "POST", "-H", "Content-Type: application/json"])
+                .args(["--data", &body, url]))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    fs,
+    path::Path,
+    sync::LazyLock,
+};
+
+use anyhow::{Context, Result};
+use nix_doc_munge::pass::option_entries;
+use regex::Regex;
+
+/// Where the declarations of an option are listed. A rendering of the option
+/// on its own links them differently from the manual, so entries end here.
+const DECLARED_BY: &str = "<emphasis>Declared by:</emphasis>";
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&amp;", "&")
+}
+
+/// Like `option_entries`, but each only up to the declarations of the option.
+pub fn rendered(manual: &str) -> Vec<(String, &str)> {
+    option_entries(manual).into_iter()
+        .map(|(name, entry)| (name, &entry[.. entry.find(DECLARED_BY).unwrap_or(entry.len())]))
+        .collect()
+}
+
+/// The files the option of `entry` is declared in, as listed after its
+/// description, e.g. `nixpkgs/nixos/modules/misc/version.nix`.
+pub fn declarations(entry: &str) -> Vec<String> {
+    static FILENAME: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)<filename[^>]*>(.*?)</filename>").unwrap());
+    static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+    let Some(at) = entry.find(DECLARED_BY) else {
+        return vec![];
+    };
+    FILENAME.captures_iter(&entry[at ..])
+        .map(|caps| unescape(&TAG.replace_all(&caps[1], "")).trim().trim_matches(['<', '>']).to_string())
+        .collect()
+}
+
+/// The options of the normalized `manual` declared in `file`, a path from
+/// the root of the tree, or `None` if the manual lists no declarations.
+/// Options declared elsewhere can still render text of `file`, when the
+/// modules declaring them import it.
+pub fn declared_in(manual: &str, file: &str) -> Option<HashSet<String>> {
+    if !manual.contains(DECLARED_BY) {
+        return None;
+    }
+    let file = file.trim_start_matches("./");
+    let is_file = |path: &String| path == file || path.ends_with(&format!("/{file}"));
+    let options = option_entries(manual).into_iter()
+        .filter(|(_, entry)| declarations(entry).iter().any(is_file))
+        .map(|(name, _)| name)
+        .collect();
+    Some(options)
+}
+
+/// The options whose entries differ between the normalized manuals `old`
+/// and `new`, or that only one of them has.
+pub fn changed(old: &str, new: &str) -> BTreeSet<String> {
+    let (old, new) = (option_entries(old), option_entries(new));
+    let new_entries = new.iter().map(|(name, entry)| (name.as_str(), *entry)).collect::<HashMap<_, _>>();
+    let old_names = old.iter().map(|(name, _)| name.as_str()).collect::<HashSet<_>>();
+    old.iter()
+        .filter(|(name, entry)| new_entries.get(name.as_str()) != Some(entry))
+        .map(|(name, _)| name.clone())
+        .chain(new.iter().filter(|(name, _)| !old_names.contains(name.as_str())).map(|(name, _)| name.clone()))
+        .collect()
+}
+
+/// The rendered options of the manual by name, for `--options-db`, so that
+/// a rewrite can be checked by rendering only its option and comparing it
+/// with the entry. Entries are normalized like the manual.
+#[derive(Default)]
+pub struct OptionsDb(HashMap<String, String>);
+
+impl OptionsDb {
+    fn from_manual(manual: &str) -> Self {
+        Self(rendered(manual).into_iter().map(|(name, entry)| (name, entry.to_string())).collect())
+    }
+
+    /// Load the database at `path`, or make it from the normalized manual
+    /// given by `render` and save it there if there is none yet. Remove it
+    /// when the tree changes.
+    pub fn load_or_render(path: &Path, render: impl FnOnce() -> Result<String>) -> Result<Self> {
+        if path.exists() {
+            let json = fs::read_to_string(path)?;
+            let db = serde_json::from_str(&json).with_context(|| format!("reading {}", path.display()))?;
+            return Ok(Self(db));
+        }
+        let db = Self::from_manual(&render()?);
+        fs::write(path, serde_json::to_string(&db.0)?)
+            .with_context(|| format!("writing {}", path.display()))?;
+        println!("rendered {} options into {}", db.0.len(), path.display());
+        Ok(db)
+    }
+
+    pub fn get(&self, option: &str) -> Option<&str> {
+        self.0.get(option).map(String::as_str)
+    }
+}
@@ -0,0 +1,978 @@
+use std::{collections::BTreeSet, fs, ops::Range, path::Path, sync::LazyLock};
+
+use anyhow::{Context, Result, bail};
+use regex::{Captures, Regex};
+use rnix::{
+    parser::ParseError,
+    types::{Apply, AttrSet, EntryHolder, Ident, KeyValue, Lambda, Paren, Select, TokenWrapper, TypedNode, With},
+    SyntaxKind, SyntaxNode, TextRange,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ALIAS_DEPTH, AUTOLINK, ConvertOptions, LINK, LINKEND, REPLACEABLE, XREF, chapter::chapter_to_markdown, code_tag_regex,
+    convert_one, decode_entities, docbook::{markdown_to_docbook, xml_escape}, find_candidates, is_call_to, is_lambda_arg,
+    is_option_call, is_reference_to, key_string, leading_space, md_to_db::{self, FROM_MD},
+    nix_string::{NixString, StringKind}, option_path, resolve_binding,
+};
+
+/// What a candidate is, deciding how it is rewritten and reported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CandidateKind {
+    /// The `description` of an option, or another attribute documenting it.
+    Description,
+    /// A `literalDocBook` call, to become `literalMD`.
+    LiteralDocBook,
+    /// The argument of `mkEnableOption`, which is spliced into a sentence
+    /// and needs parentheses once wrapped in a call.
+    EnableOption,
+    /// A `lib.mdDoc` call.
+    MdDoc,
+    /// A `literalExample` call.
+    LiteralExample,
+    /// The `default` of an option lacking a `defaultText`.
+    Default,
+    /// A `types.string`.
+    TypesString,
+    /// A `meta.doc` reference to a DocBook chapter.
+    Chapter,
+    /// A whole release notes section.
+    ReleaseNotes,
+    /// An assertion or warning message.
+    Message,
+}
+
+impl CandidateKind {
+    /// A short name for the report.
+    pub fn name(self) -> &'static str {
+        match self {
+            CandidateKind::Description => "description",
+            CandidateKind::LiteralDocBook => "literalDocBook",
+            CandidateKind::EnableOption => "mkEnableOption",
+            CandidateKind::MdDoc => "mdDoc",
+            CandidateKind::LiteralExample => "literalExample",
+            CandidateKind::Default => "default",
+            CandidateKind::TypesString => "types.string",
+            CandidateKind::Chapter => "meta.doc",
+            CandidateKind::ReleaseNotes => "release-notes",
+            CandidateKind::Message => "message",
+        }
+    }
+
+    /// Whether the candidate is a function argument, needing parentheses
+    /// once wrapped in a call itself.
+    pub fn needs_parens(self) -> bool {
+        self == CandidateKind::EnableOption
+    }
+}
+
+/// Something to rewrite.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub range: TextRange,
+    pub kind: CandidateKind,
+    /// Attribute path of the option the candidate belongs to, as far as it
+    /// can be told from the file alone, see `option_path`.
+    pub option_path: String,
+    /// What kind of string literal the candidate is, if it is one.
+    pub string_kind: Option<StringKind>,
+}
+
+pub type Candidates = Vec<Candidate>;
+
+/// What the `find_*` functions return, before the rest of each candidate is
+/// filled in.
+type Found = Vec<(TextRange, CandidateKind)>;
+
+/// The rewrites this tool knows how to perform and verify.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Pass {
+    /// Convert DocBook option descriptions to Markdown wrapped in `lib.mdDoc`.
+    #[default]
+    MdDoc,
+    /// Normalize `mkEnableOption` arguments to fit into "Whether to enable
+    /// ….": no such prefix of their own, no trailing period, and a lowercase
+    /// first word.
+    EnableOption,
+    /// Remove `lib.mdDoc` wrappers from Markdown descriptions once Markdown
+    /// is the default, along with the parentheses around them.
+    StripMdDoc,
+    /// Convert `lib.mdDoc` descriptions back to DocBook, for backporting
+    /// modules to releases whose manual only renders DocBook.
+    #[cfg_attr(feature = "cli", value(name = "docbook"))]
+    DocBook,
+    /// Replace the deprecated `literalExample` with `literalExpression` where
+    /// its argument is Nix code and with `literalDocBook` where it is prose.
+    LiteralExample,
+    /// Add a `defaultText` to options whose `default` refers to `cfg` or
+    /// `pkgs`, which the manual can't render usefully.
+    DefaultText,
+    /// Replace the deprecated `types.string` with `types.str`.
+    TypesStr,
+    /// Convert DocBook chapters referenced by `meta.doc` to Markdown files.
+    Chapter,
+    /// Convert `rl-*.section.xml` release notes, given as the files to work
+    /// on, to the Markdown release notes format, along with the DocBook the
+    /// tree's `md-to-db.sh` generates from them for the manual.
+    ReleaseNotes,
+    /// Strip DocBook markup from `assertions` messages and `warnings`, which
+    /// are printed as plain text. Inline code becomes backquoted. The manual
+    /// renders neither, so this only checks that it stays the same, not what
+    /// the messages become.
+    Messages,
+}
+
+/// A rewritten file, along with the replacements the rewrite is expected to
+/// make in the normalized manual. Passes that must not change the manual at
+/// all leave `expect` empty. Passes that add text whose surroundings they
+/// can't predict set `gain` instead.
+#[derive(Default)]
+pub struct Rewrite {
+    pub content: String,
+    pub expect: Vec<(String, String)>,
+    pub gain: Option<String>,
+    /// Other files to write, or to remove if `None`, relative to the tree.
+    pub files: Vec<(String, Option<String>)>,
+    /// The option of the candidate, within whose entry in the manual the
+    /// replacements of `expect` are made. Empty if it can't be told.
+    pub option: String,
+    /// Text of the evaluation warnings about the option that the rewrite is
+    /// expected to silence. All others must stay as they are.
+    pub silences: Option<String>,
+}
+
+impl Rewrite {
+    /// Apply the expected replacements to the manual built before the rewrite,
+    /// within the entry of the option if the manual has one, so that the same
+    /// text in another option doesn't count.
+    pub fn expected(&self, old: &str) -> String {
+        let apply = |s: &str| self.expect.iter()
+            .fold(s.to_string(), |s, (from, to)| s.replacen(from, to, 1));
+        let entry = option_entry_ranges(old).into_iter()
+            .find(|(name, _)| !self.option.is_empty() && *name == self.option);
+        match entry {
+            Some((_, range)) => old[.. range.start].to_string() + &apply(&old[range.clone()]) + &old[range.end ..],
+            None => apply(old),
+        }
+    }
+
+    /// The evaluation warnings expected after the rewrite, of `old` before it.
+    pub fn expected_warnings(&self, old: &BTreeSet<String>) -> BTreeSet<String> {
+        let option = format!("`{}'", self.option);
+        old.iter()
+            .filter(|warning| !self.silences.as_ref().is_some_and(|silenced| {
+                warning.contains(silenced.as_str()) && (self.option.is_empty() || warning.contains(&option))
+            }))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the manual built after the rewrite is what we expected. With a
+    /// `gain`, the manuals must differ in one place only, and the text there,
+    /// widened to whole tags and text, must be the gained text up to tags and
+    /// whitespace.
+    pub fn verify(&self, old: &str, new: &str) -> bool {
+        static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^<>]*>").unwrap());
+        let old = self.expected(old);
+        let Some(gain) = &self.gain else {
+            return old == new;
+        };
+        if old == new {
+            return false;
+        }
+        let (prefix, suffix) = common_affixes(&old, new);
+        let (before, after) = (&new[.. prefix], &new[new.len() - suffix ..]);
+        // affixes can end inside a tag, or share the ends of the old text
+        let start = match (before.rfind('<'), before.rfind('>')) {
+            (Some(open), close) if close.is_none_or(|close| close < open) => open,
+            (_, close) => close.map_or(0, |close| close + 1),
+        };
+        let end = match (after.find('>'), after.find('<')) {
+            (Some(close), open) if open.is_none_or(|open| close < open) => close + 1,
+            (_, open) => open.unwrap_or(after.len()),
+        };
+        let changed = TAG.replace_all(&new[start .. new.len() - suffix + end], " ");
+        let words = |s: &str| s.split_whitespace().collect::<Vec<_>>().join(" ");
+        words(&changed) == words(gain)
+    }
+}
+
+/// The options of the normalized DocBook `manual`, each by name along with
+/// where its entry is. Entries start at the `<varlistentry>` of an option and
+/// end before that of the next or the end of the list, so lists inside
+/// descriptions stay in the entry of their option.
+fn option_entry_ranges(manual: &str) -> Vec<(String, Range<usize>)> {
+    let starts = manual.match_indices("xml:id=\"opt-")
+        .filter_map(|(i, _)| manual[.. i].rfind("<varlistentry"))
+        .collect::<Vec<_>>();
+    let last = manual.rfind("</variablelist>").unwrap_or(manual.len());
+    let ends = starts.iter().skip(1).copied().chain([last]);
+    starts.iter().zip(ends)
+        .filter_map(|(&start, end)| {
+            let (_, name) = manual[start .. end].split_once("<option>")?;
+            let (name, _) = name.split_once("</option>")?;
+            Some((decode_entities(name), start .. end))
+        })
+        .collect()
+}
+
+/// Like `option_entry_ranges`, with the text of each entry.
+pub fn option_entries(manual: &str) -> Vec<(String, &str)> {
+    option_entry_ranges(manual).into_iter()
+        .map(|(name, range)| (name, &manual[range]))
+        .collect()
+}
+
+/// The text of `range` in `s`, or an error instead of a panic where the range
+/// is out of bounds or splits a character, as it can once `s` has changed
+/// since the range was found.
+pub fn text_at(s: &str, range: TextRange) -> Result<&str> {
+    match s.get(usize::from(range.start()) .. range.end().into()) {
+        Some(text) => Ok(text),
+        None => bail!("{range:?} isn't on character boundaries of the {} bytes of the file", s.len()),
+    }
+}
+
+/// Lengths of the longest common prefix and suffix of `old` and `new`,
+/// not overlapping each other. What lies between differs.
+pub fn common_affixes(old: &str, new: &str) -> (usize, usize) {
+    let prefix = old.char_indices().zip(new.chars())
+        .find(|((_, a), b)| a != b)
+        .map_or(old.len().min(new.len()), |((i, _), _)| i);
+    let suffix = old[prefix ..].chars().rev().zip(new[prefix ..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .sum::<usize>();
+    (prefix, suffix)
+}
+
+impl Pass {
+    /// The ranges this pass would rewrite in `s`, in reverse order so that
+    /// rewriting one leaves the positions of the rest intact, and those left
+    /// out because they are inside or overlap with an outer one. Applying
+    /// both could corrupt the file. Fails if `s` is Nix that doesn't parse.
+    pub fn scan(self, s: &str, opts: &ConvertOptions) -> Result<(Candidates, Candidates), ParseError> {
+        // release notes are DocBook rather than Nix
+        let root = rnix::parse(s).as_result().ok().map(|ast| ast.node());
+        let candidates = self.find_all(s, opts)?.into_iter()
+            .map(|(range, kind)| Candidate {
+                range,
+                kind,
+                option_path: root.as_ref().map_or(String::new(), |root| option_path(root, range)),
+                string_kind: NixString::parse(&s[range]).map(|string| string.kind),
+            })
+            .collect();
+        Ok(split_overlapping(candidates))
+    }
+
+    fn find_all(self, s: &str, opts: &ConvertOptions) -> Result<Found, ParseError> {
+        match self {
+            Pass::MdDoc => find_candidates(s, opts.doc_attrs()),
+            Pass::EnableOption => find_enable_options(s),
+            Pass::StripMdDoc | Pass::DocBook => find_md_docs(s),
+            Pass::LiteralExample => find_literal_examples(s),
+            Pass::DefaultText => find_missing_default_texts(s),
+            Pass::TypesStr => find_types_strings(s),
+            Pass::Chapter => find_xml_chapters(s),
+            Pass::ReleaseNotes => find_release_notes(s),
+            Pass::Messages => find_marked_up_messages(s),
+        }
+    }
+
+    /// Rewrite `candidate` of `s`, the contents of `file`.
+    pub fn rewrite(self, file: &str, s: &str, candidate: &Candidate,
+                   opts: &ConvertOptions) -> Result<Rewrite> {
+        let pos = candidate.range;
+        text_at(s, pos)?;
+        let rewrite = match self {
+            Pass::MdDoc => Rewrite {
+                content: convert_one(s, candidate, opts),
+                ..Default::default()
+            },
+            Pass::EnableOption => normalize_enable_option(s, pos)?,
+            Pass::StripMdDoc => Rewrite {
+                content: strip_md_doc(s, pos)?,
+                ..Default::default()
+            },
+            Pass::DocBook => Rewrite {
+                content: md_doc_to_docbook(s, pos, opts)?,
+                ..Default::default()
+            },
+            Pass::LiteralExample => replace_literal_example(s, pos)?,
+            Pass::DefaultText => insert_default_text(s, pos)?,
+            Pass::TypesStr => replace_types_string(s, pos),
+            Pass::Chapter => convert_chapter(file, s, pos, opts)?,
+            Pass::ReleaseNotes => convert_release_notes(file, s, opts)?,
+            Pass::Messages => Rewrite {
+                content: strip_message_markup(s, pos, opts),
+                ..Default::default()
+            },
+        };
+        Ok(Rewrite { option: candidate.option_path.clone(), ..rewrite })
+    }
+
+    /// The attribute of `system.build.manual` the pass is verified against,
+    /// and the file to compare within its output.
+    pub fn manual(self) -> (&'static str, Option<&'static str>) {
+        match self {
+            Pass::Chapter | Pass::ReleaseNotes => ("manualHTML", Some("share/doc/nixos/index.html")),
+            _ => ("optionsDocBook", None),
+        }
+    }
+}
+
+/// Separate the outermost of `candidates` from those overlapping them, both
+/// in reverse order.
+fn split_overlapping(mut candidates: Candidates) -> (Candidates, Candidates) {
+    candidates.sort_by_key(|c| (c.range.start(), std::cmp::Reverse(c.range.end())));
+    let (mut kept, mut overlapping) = (vec![], vec![]);
+    let mut end = None;
+    for candidate in candidates {
+        if end.is_some_and(|end| candidate.range.start() < end) {
+            overlapping.push(candidate);
+        } else {
+            end = Some(candidate.range.end());
+            kept.push(candidate);
+        }
+    }
+    kept.reverse();
+    overlapping.reverse();
+    (kept, overlapping)
+}
+
+/// The string literal given to `mkEnableOption`, possibly inside `lib.mdDoc`.
+fn enable_option_string(node: SyntaxNode) -> Option<SyntaxNode> {
+    if !is_call_to(node.clone(), "mkEnableOption") {
+        return None;
+    }
+    let mut arg = Apply::cast(node)?.value()?;
+    if let Some(p) = Paren::cast(arg.clone()) {
+        arg = p.node().first_child()?;
+    }
+    if is_call_to(arg.clone(), "mdDoc") {
+        arg = Apply::cast(arg)?.value()?;
+    }
+    (arg.kind() == SyntaxKind::NODE_STRING).then_some(arg)
+}
+
+fn find_enable_options(s: &str) -> Result<Found, ParseError> {
+    let ast = rnix::parse(s).as_result()?;
+    let mut result = ast.node().descendants()
+        .filter_map(enable_option_string)
+        .filter(|n| {
+            NixString::parse(&n.text().to_string())
+                .is_some_and(|string| normalize_enable_text(&string.value).0 != string.value)
+        })
+        .map(|n| (n.text_range(), CandidateKind::EnableOption))
+        .collect::<Vec<_>>();
+    result.sort_by_key(|(r, _)| std::cmp::Reverse(r.start()));
+    Ok(result)
+}
+
+/// Normalize the text of a `mkEnableOption` argument, returning it along
+/// with the replacements this makes in the sentence it's rendered into.
+fn normalize_enable_text(value: &str) -> (String, Vec<(String, String)>) {
+    let start = value.len() - value.trim_start().len();
+    let end = value.trim_end().len();
+    let (lead, mut text, trail) = (&value[.. start], value[start .. end].to_string(), &value[end ..]);
+    let mut expect = vec![];
+
+    const PREFIX: &str = "Whether to enable ";
+    for prefix in [PREFIX, "whether to enable ", "Enable ", "Enables "] {
+        if let Some(rest) = text.strip_prefix(prefix) {
+            expect.push((format!("{PREFIX}{prefix}"), PREFIX.to_string()));
+            text = rest.to_string();
+            break;
+        }
+    }
+
+    // only title case words, anything else is likely a name or acronym
+    let word = text.split_whitespace().next().unwrap_or("").to_string();
+    let mut chars = word.chars();
+    if chars.next().is_some_and(char::is_uppercase)
+        && chars.clone().count() > 0
+        && chars.all(char::is_lowercase)
+    {
+        let lower = word.to_lowercase();
+        expect.push((format!("{PREFIX}{word}"), format!("{PREFIX}{lower}")));
+        text = lower + &text[word.len() ..];
+    }
+
+    if let Some(rest) = text.strip_suffix('.').filter(|r| !r.ends_with('.')) {
+        let last = rest.split_whitespace().last().unwrap_or("").to_string();
+        expect.push((format!("{last}.."), format!("{last}.")));
+        text = rest.to_string();
+    }
+
+    (format!("{lead}{text}{trail}"), expect)
+}
+
+fn normalize_enable_option(s: &str, pos: TextRange) -> Result<Rewrite> {
+    let prefix = &s[.. pos.start().into()];
+    let chunk = &s[pos.start().into() .. pos.end().into()];
+    let suffix = &s[usize::from(pos.end()) ..];
+
+    let string = NixString::parse(chunk).context("the argument isn't a string literal")?;
+    let (value, expect) = normalize_enable_text(&string.value);
+    Ok(Rewrite {
+        content: prefix.to_owned() + &string.encode(&value) + suffix,
+        expect,
+        ..Default::default()
+    })
+}
+
+/// `lib.mdDoc` applied to a string literal, including the parentheses around
+/// the application if there are any.
+fn md_doc_call(node: SyntaxNode) -> Option<SyntaxNode> {
+    if !is_call_to(node.clone(), "mdDoc") {
+        return None;
+    }
+    let arg = Apply::cast(node.clone())?.value()?;
+    if arg.kind() != SyntaxKind::NODE_STRING {
+        return None;
+    }
+    match node.parent() {
+        Some(p) if p.kind() == SyntaxKind::NODE_PAREN => Some(p),
+        _ => Some(node),
+    }
+}
+
+fn find_md_docs(s: &str) -> Result<Found, ParseError> {
+    let ast = rnix::parse(s).as_result()?;
+    let mut result = ast.node().descendants()
+        .filter_map(md_doc_call)
+        .map(|n| (n.text_range(), CandidateKind::MdDoc))
+        .collect::<Vec<_>>();
+    result.sort_by_key(|(r, _)| std::cmp::Reverse(r.start()));
+    Ok(result)
+}
+
+/// The string literal inside an `lib.mdDoc` call found by `md_doc_call`.
+fn md_doc_string(chunk: &str) -> Result<String> {
+    let ast = rnix::parse(chunk).as_result()?;
+    let string = ast.node().descendants()
+        .find(|n| n.kind() == SyntaxKind::NODE_STRING)
+        .context("no string literal in the call")?;
+    Ok(string.text().to_string())
+}
+
+fn strip_md_doc(s: &str, pos: TextRange) -> Result<String> {
+    let prefix = &s[.. pos.start().into()];
+    let chunk = &s[pos.start().into() .. pos.end().into()];
+    let suffix = &s[usize::from(pos.end()) ..];
+
+    Ok(prefix.to_owned() + &md_doc_string(chunk)? + suffix)
+}
+
+fn md_doc_to_docbook(s: &str, pos: TextRange, opts: &ConvertOptions) -> Result<String> {
+    let prefix = &s[.. pos.start().into()];
+    let chunk = &s[pos.start().into() .. pos.end().into()];
+    let suffix = &s[usize::from(pos.end()) ..];
+
+    let literal = md_doc_string(chunk)?;
+    let tags = opts.tag_map.clone().unwrap_or_default();
+    let converted = match NixString::parse(&literal) {
+        Some(string) => string.encode(&markdown_to_docbook(&string.value, &tags)),
+        None => literal,
+    };
+    Ok(prefix.to_owned() + &converted + suffix)
+}
+
+/// `literalExample` applied to a string literal.
+fn literal_example_call(node: SyntaxNode) -> Option<SyntaxNode> {
+    if !is_call_to(node.clone(), "literalExample") {
+        return None;
+    }
+    let arg = Apply::cast(node.clone())?.value()?;
+    (arg.kind() == SyntaxKind::NODE_STRING).then_some(node)
+}
+
+fn find_literal_examples(s: &str) -> Result<Found, ParseError> {
+    let ast = rnix::parse(s).as_result()?;
+    let mut result = ast.node().descendants()
+        .filter_map(literal_example_call)
+        .map(|n| (n.text_range(), CandidateKind::LiteralExample))
+        .collect::<Vec<_>>();
+    result.sort_by_key(|(r, _)| std::cmp::Reverse(r.start()));
+    Ok(result)
+}
+
+/// Whether the text of a `literalExample` is Nix code rather than DocBook:
+/// whether it parses without errors. Prose of a few words parses too, but
+/// renders the same as a `literalExpression`.
+fn is_nix_code(text: &str) -> bool {
+    rnix::parse(text).as_result().is_ok()
+}
+
+fn replace_literal_example(s: &str, pos: TextRange) -> Result<Rewrite> {
+    let prefix = &s[.. pos.start().into()];
+    let chunk = &s[pos.start().into() .. pos.end().into()];
+    let suffix = &s[usize::from(pos.end()) ..];
+
+    let ast = rnix::parse(chunk).as_result()?;
+    let call = ast.node().descendants().find_map(Apply::cast).context("no call to literalExample")?;
+    let (Some(lambda), Some(arg)) = (call.lambda(), call.value()) else {
+        bail!("incomplete call to literalExample");
+    };
+    let (lambda, arg) = (lambda.text_range(), arg.text().to_string());
+    // only evaluation tells what text other arguments and interpolations
+    // make, which `literalExpression` renders like `literalExample` does
+    let value = NixString::parse(&arg)
+        .filter(|s| !s.has_interpolations())
+        .map(|s| s.text())
+        .filter(|text| !text.trim().is_empty() && !is_nix_code(text));
+
+    // both render as a program listing, DocBook is inserted as is
+    let (name, expect) = match value {
+        None => ("literalExpression", vec![]),
+        Some(value) => {
+            let listing = format!("<programlisting>{}</programlisting>", xml_escape(&value));
+            ("literalDocBook", vec![(listing, value)])
+        },
+    };
+    let callee = &chunk[lambda.start().into() .. lambda.end().into()];
+    let callee = callee.strip_suffix("literalExample").context("not a call to literalExample")?.to_owned() + name;
+    Ok(Rewrite {
+        content: prefix.to_owned()
+            + &chunk[.. lambda.start().into()]
+            + &callee
+            + &chunk[lambda.end().into() ..]
+            + suffix,
+        expect,
+        ..Default::default()
+    })
+}
+
+/// Whether `node` selects something out of `cfg` or `pkgs`, which either
+/// can't be evaluated for the manual or renders as a store path.
+fn refers_to_config(node: &SyntaxNode) -> bool {
+    node.descendants()
+        .filter_map(Select::cast)
+        .filter_map(|s| s.set().and_then(Ident::cast))
+        .any(|id| matches!(id.as_str(), "cfg" | "pkgs"))
+}
+
+/// `default` entries of options that need a `defaultText` and lack one.
+fn find_missing_default_texts(s: &str) -> Result<Found, ParseError> {
+    let ast = rnix::parse(s).as_result()?;
+    let mut result = ast.node().descendants()
+        .filter(|n| is_option_call(n.clone()))
+        .filter_map(|n| AttrSet::cast(Apply::cast(n)?.value()?))
+        .filter(|attrs| !attrs.entries().any(|e| key_string(&e) == "defaultText"))
+        .filter_map(|attrs| attrs.entries().find(|e| key_string(e) == "default"))
+        .filter(|e| e.value().is_some_and(|v| refers_to_config(&v)))
+        .map(|e| (e.node().text_range(), CandidateKind::Default))
+        .collect::<Vec<_>>();
+    result.sort_by_key(|(r, _)| std::cmp::Reverse(r.start()));
+    Ok(result)
+}
+
+fn insert_default_text(s: &str, pos: TextRange) -> Result<Rewrite> {
+    let end = usize::from(pos.end());
+    let line_start = s[.. pos.start().into()].rfind('\n').map_or(0, |i| i + 1);
+    let indent = leading_space(&s[line_start ..]);
+
+    let ast = rnix::parse(s).as_result()?;
+    let entry = ast.node().descendants()
+        .filter(|n| n.text_range() == pos)
+        .find_map(KeyValue::cast)
+        .context("no `default` entry at the candidate")?;
+    let value = entry.value().context("`default` has no value")?.text().to_string();
+    // call `literalExpression` the same way the option's file calls `mkOption`
+    let callee = entry.node().ancestors()
+        .find(|n| is_option_call(n.clone()))
+        .and_then(|n| Apply::cast(n)?.lambda())
+        .map_or(String::new(), |l| l.text().to_string());
+    let lib = &callee[.. callee.rfind('.').map_or(0, |i| i + 1)];
+
+    let (literal, text) = if value.contains('\n') {
+        // continuation lines are indented relative to the `default` line
+        let text = value.split('\n')
+            .map(|l| l.strip_prefix(indent).unwrap_or(l))
+            .collect::<Vec<_>>()
+            .join("\n");
+        (NixString::parse("''''").unwrap().encode_indented(&text, indent), text)
+    } else {
+        (NixString::parse(r#""""#).unwrap().encode(&value), value)
+    };
+
+    Ok(Rewrite {
+        content: format!("{}\n{indent}defaultText = {lib}literalExpression {literal};{}",
+                         &s[.. end], &s[end ..]),
+        gain: Some(xml_escape(&text)),
+        ..Default::default()
+    })
+}
+
+/// `types.string`, with `types` resolved like helper calls, so also
+/// `lib.types.string` or through aliases of `lib` or `types`, or a free
+/// `string` inside `with types;`.
+fn is_types_string(node: SyntaxNode) -> bool {
+    if let Some(sel) = Select::cast(node.clone()) {
+        return sel.index().and_then(Ident::cast).is_some_and(|i| i.as_str() == "string")
+            && sel.set().is_some_and(|set| is_reference_to(set, "types", ALIAS_DEPTH));
+    }
+    if Ident::cast(node.clone()).is_none_or(|id| id.as_str() != "string") || !is_value(&node) {
+        return false;
+    }
+    resolve_binding(&node, "string").is_none() && !is_lambda_arg(&node, "string")
+        && node.ancestors().skip(1).filter_map(With::cast).any(|with| {
+            with.namespace().is_some_and(|ns| {
+                !ns.text_range().contains_range(node.text_range()) && is_reference_to(ns, "types", ALIAS_DEPTH)
+            })
+        })
+}
+
+/// Whether the identifier `ident` is used as a value, rather than naming an
+/// attribute or an argument.
+fn is_value(ident: &SyntaxNode) -> bool {
+    let Some(parent) = ident.parent() else {
+        return true;
+    };
+    match parent.kind() {
+        SyntaxKind::NODE_KEY | SyntaxKind::NODE_INHERIT | SyntaxKind::NODE_PAT_ENTRY | SyntaxKind::NODE_PAT_BIND => false,
+        SyntaxKind::NODE_SELECT => Select::cast(parent).and_then(|sel| sel.index()).as_ref() != Some(ident),
+        SyntaxKind::NODE_LAMBDA => Lambda::cast(parent).and_then(|l| l.arg()).as_ref() != Some(ident),
+        _ => true,
+    }
+}
+
+fn find_types_strings(s: &str) -> Result<Found, ParseError> {
+    let ast = rnix::parse(s).as_result()?;
+    let mut result = ast.node().descendants()
+        .filter(|n| is_types_string(n.clone()))
+        .map(|n| (n.text_range(), CandidateKind::TypesString))
+        .collect::<Vec<_>>();
+    result.sort_by_key(|(r, _)| std::cmp::Reverse(r.start()));
+    Ok(result)
+}
+
+fn replace_types_string(s: &str, pos: TextRange) -> Rewrite {
+    let end = usize::from(pos.end());
+    Rewrite {
+        content: s[.. end - "string".len()].to_owned() + "str" + &s[end ..],
+        // `types.string` is a `separatedString ""`, described differently
+        expect: vec![("Concatenated string".to_string(), "string".to_string())],
+        // and deprecated, which the module system warns about
+        silences: Some("`types.string'".to_string()),
+        ..Default::default()
+    }
+}
+
+/// `meta.doc` entries pointing at a DocBook chapter.
+fn find_xml_chapters(s: &str) -> Result<Found, ParseError> {
+    let ast = rnix::parse(s).as_result()?;
+    let mut result = ast.node().descendants()
+        .filter_map(KeyValue::cast)
+        .filter(|e| match key_string(e).as_str() {
+            "meta.doc" => true,
+            "doc" => e.node().parent()
+                .and_then(|set| set.parent())
+                .and_then(KeyValue::cast)
+                .is_some_and(|meta| key_string(&meta) == "meta"),
+            _ => false,
+        })
+        .filter_map(|e| e.value())
+        .filter(|v| {
+            let path = v.text().to_string();
+            path.starts_with('.') && path.ends_with(".xml")
+        })
+        .map(|v| (v.text_range(), CandidateKind::Chapter))
+        .collect::<Vec<_>>();
+    result.sort_by_key(|(r, _)| std::cmp::Reverse(r.start()));
+    Ok(result)
+}
+
+fn convert_chapter(file: &str, s: &str, pos: TextRange, opts: &ConvertOptions) -> Result<Rewrite> {
+    let reference = &s[pos.start().into() .. pos.end().into()];
+    let dir = Path::new(file).parent().unwrap_or(Path::new(""));
+    let xml_path = dir.join(reference).to_string_lossy().replace("/./", "/");
+    let xml_path = xml_path.trim_start_matches("./").to_string();
+    let (Some(xml_stem), Some(stem)) = (xml_path.strip_suffix(".xml"), reference.strip_suffix(".xml")) else {
+        bail!("{reference} is not a DocBook file");
+    };
+    let md_path = xml_stem.to_string() + ".md";
+
+    let markdown = chapter_to_markdown(&fs::read_to_string(&xml_path)?, opts)?;
+    let md_reference = stem.to_string() + ".md";
+    Ok(Rewrite {
+        content: s[.. pos.start().into()].to_owned() + &md_reference + &s[pos.end().into() ..],
+        files: vec![(md_path, Some(markdown)), (xml_path, None)],
+        ..Default::default()
+    })
+}
+
+/// Release notes are converted as a whole, the candidate is the entire file.
+fn find_release_notes(s: &str) -> Result<Found, ParseError> {
+    if !s.contains(r#"xml:id="sec-release-"#) {
+        return Ok(vec![]);
+    }
+    Ok(vec![(TextRange::up_to((s.len() as u32).into()), CandidateKind::ReleaseNotes)])
+}
+
+/// Convert a release notes section and point the `xi:include` in the
+/// neighbouring `release-notes.xml` at the DocBook generated from the
+/// Markdown by the tree's `md-to-db.sh`, which nixpkgs of that time commits
+/// along with the Markdown.
+fn convert_release_notes(file: &str, s: &str, opts: &ConvertOptions) -> Result<Rewrite> {
+    let Some(stem) = file.strip_suffix(".xml") else {
+        bail!("{file} is not a DocBook file");
+    };
+    let path = Path::new(file);
+    let name = path.file_name().context("release notes without a file name")?.to_string_lossy();
+    let index = path.with_file_name("release-notes.xml").to_string_lossy().into_owned();
+
+    let markdown = chapter_to_markdown(s, opts)?;
+    let mut generated = vec![];
+    if let Ok(includes) = fs::read_to_string(&index) {
+        let from = format!(r#"href="{name}""#);
+        if !includes.contains(&from) {
+            bail!("{index} doesn't include {name}");
+        }
+        let manual = path.parent().and_then(Path::parent).context("release notes outside of a manual")?;
+        let md_name = Path::new(&*name).with_extension("md").to_string_lossy().into_owned();
+        let docbook = md_to_db::release_notes(manual, &md_name, &markdown)?;
+        let docbook_path = manual.join(FROM_MD).join("release-notes").join(&*name);
+        let to = format!(r#"href="../{FROM_MD}/release-notes/{name}""#);
+        generated.push((index, Some(includes.replace(&from, &to))));
+        generated.push((docbook_path.to_string_lossy().into_owned(), Some(docbook)));
+    }
+    let files = [(format!("{stem}.md"), Some(markdown)), (file.to_string(), None)].into_iter()
+        .chain(generated)
+        .collect();
+    Ok(Rewrite {
+        content: s.to_string(),
+        files,
+        ..Default::default()
+    })
+}
+
+/// Markup that `find_marked_up_messages` looks for.
+static MESSAGE_MARKUP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"</?(option|literal|filename|command|varname|envar|link|xref|code|package)\b").unwrap()
+});
+/// Any tag left once code and links are converted.
+static MESSAGE_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"</?[a-zA-Z][^<>]*>").unwrap());
+
+/// Strings of `assertions` messages and `warnings` that contain markup.
+fn find_marked_up_messages(s: &str) -> Result<Found, ParseError> {
+    let ast = rnix::parse(s).as_result()?;
+    let mut result = ast.node().descendants()
+        .filter_map(KeyValue::cast)
+        .flat_map(|e| {
+            let key = key_string(&e);
+            let strings = e.value().into_iter().flat_map(|v| v.descendants());
+            if key == "assertions" || key.ends_with(".assertions") {
+                strings
+                    .filter_map(KeyValue::cast)
+                    .filter(|m| key_string(m) == "message")
+                    .filter_map(|m| m.value())
+                    .filter(|v| v.kind() == SyntaxKind::NODE_STRING)
+                    .collect::<Vec<_>>()
+            } else if key == "warnings" || key.ends_with(".warnings") {
+                strings
+                    .filter(|n| n.kind() == SyntaxKind::NODE_STRING)
+                    // strings inside interpolations are part of an outer one
+                    .filter(|n| n.ancestors().skip(1).all(|a| a.kind() != SyntaxKind::NODE_STRING))
+                    .collect()
+            } else {
+                vec![]
+            }
+        })
+        .filter(|n| MESSAGE_MARKUP.is_match(&n.text().to_string()))
+        .map(|n| (n.text_range(), CandidateKind::Message))
+        .collect::<Vec<_>>();
+    result.sort_by_key(|(r, _)| std::cmp::Reverse(r.start()));
+    result.dedup();
+    Ok(result)
+}
+
+fn strip_message_markup(s: &str, pos: TextRange, opts: &ConvertOptions) -> String {
+    let prefix = &s[.. pos.start().into()];
+    let chunk = &s[pos.start().into() .. pos.end().into()];
+    let suffix = &s[usize::from(pos.end()) ..];
+
+    let Some(string) = NixString::parse(chunk) else {
+        return s.to_string();
+    };
+    let tags = opts.tag_map.clone().unwrap_or_default();
+    // placeholders stay, as `<name>`, and are escaped until the tags around
+    // them are gone
+    let mut value = REPLACEABLE.replace_all(&string.value, "&lt;$1&gt;").into_owned();
+    for tag in tags.code.keys() {
+        value = code_tag_regex(tag)
+            .replace_all(&value, |caps: &Captures| format!("`{}`", caps[1].replace('<', "&lt;").replace('>', "&gt;")))
+            .into_owned();
+    }
+    let value = LINK.replace_all(&value, "$2 ($1)");
+    let value = LINKEND.replace_all(&value, "$2");
+    let value = AUTOLINK.replace_all(&value, "$1");
+    let value = XREF.replace_all(&value, "$1");
+    let value = MESSAGE_TAG.replace_all(&value, "");
+    prefix.to_owned() + &string.encode(&decode_entities(&value)) + suffix
+}
+
+#[cfg(test)]
+mod tests {
+    use rnix::TextRange;
+
+    use std::fs;
+
+    use super::{Rewrite, convert_release_notes, find_types_strings, is_nix_code, replace_literal_example, strip_message_markup};
+    use crate::ConvertOptions;
+
+    fn strip(message: &str) -> String {
+        let range = TextRange::up_to((message.len() as u32).into());
+        strip_message_markup(message, range, &ConvertOptions::default())
+    }
+
+    fn entry(option: &str, text: &str) -> String {
+        format!(r#"<varlistentry><term xml:id="opt-{option}"><option>{option}</option></term><para>{text}</para></varlistentry>"#)
+    }
+
+    #[test]
+    fn expectations_stay_in_their_option() {
+        let manual = format!("<variablelist>{}{}</variablelist>",
+                             entry("a", "Whether to enable Foo."), entry("b", "Whether to enable Foo."));
+        let rewrite = Rewrite {
+            expect: vec![("Whether to enable Foo".to_string(), "Whether to enable foo".to_string())],
+            option: "b".to_string(),
+            ..Default::default()
+        };
+        let expected = format!("<variablelist>{}{}</variablelist>",
+                               entry("a", "Whether to enable Foo."), entry("b", "Whether to enable foo."));
+        assert_eq!(rewrite.expected(&manual), expected);
+    }
+
+    /// Stands in for `md-to-db.sh`, converting each Markdown file it finds
+    /// below its directory.
+    const MD_TO_DB: &str = r#"#!/bin/sh
+cd "$(dirname "$0")"
+find . -type f -name '*.section.md' | while read -r md; do
+  mkdir -p "from_md/$(dirname "$md")"
+  sed 's/^/converted: /' "$md" > "from_md/${md%.md}.xml"
+done
+"#;
+
+    #[cfg(unix)]
+    #[test]
+    fn release_notes_are_generated_as_docbook() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tree = std::env::temp_dir().join(format!("nix-doc-munge-release-notes-{}", std::process::id()));
+        let (manual, notes) = (tree.join("manual"), tree.join("manual/release-notes"));
+        fs::create_dir_all(&notes).unwrap();
+        fs::write(manual.join("md-to-db.sh"), MD_TO_DB).unwrap();
+        fs::set_permissions(manual.join("md-to-db.sh"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(manual.join("other.section.md"), "not converted").unwrap();
+        fs::write(notes.join("release-notes.xml"), r#"<xi:include href="rl-2205.section.xml" />"#).unwrap();
+        let xml = r#"<section xml:id="sec-release-22.05"><title>Release 22.05</title><para>New.</para></section>"#;
+        let file = notes.join("rl-2205.section.xml").to_string_lossy().into_owned();
+
+        let rewrite = convert_release_notes(&file, xml, &ConvertOptions::default());
+        let written = manual.join("from_md").exists();
+        fs::remove_dir_all(&tree).unwrap();
+        let files = rewrite.unwrap().files.into_iter()
+            .map(|(path, content)| (path.strip_prefix(tree.to_str().unwrap()).unwrap().to_string(), content))
+            .collect::<Vec<_>>();
+        let markdown = "# Release 22.05 {#sec-release-22.05}\n\nNew.\n";
+        assert_eq!(files, [
+            ("/manual/release-notes/rl-2205.section.md".to_string(), Some(markdown.to_string())),
+            ("/manual/release-notes/rl-2205.section.xml".to_string(), None),
+            ("/manual/release-notes/release-notes.xml".to_string(),
+             Some(r#"<xi:include href="../from_md/release-notes/rl-2205.section.xml" />"#.to_string())),
+            ("/manual/from_md/release-notes/rl-2205.section.xml".to_string(),
+             Some(markdown.lines().map(|l| format!("converted: {l}\n")).collect())),
+        ]);
+        assert!(!written, "the generated DocBook was written to the tree");
+    }
+
+    /// The function `literalExample` becomes for `arg`.
+    fn literal(arg: &str) -> String {
+        let s = format!("lib.literalExample {arg}");
+        let rewrite = replace_literal_example(&s, TextRange::up_to((s.len() as u32).into())).unwrap();
+        rewrite.content.split_whitespace().next().unwrap().to_string()
+    }
+
+    #[test]
+    fn nix_code_parses() {
+        assert!(is_nix_code("{ a = 1; }"));
+        assert!(is_nix_code("[ pkgs.hello ]"));
+        assert!(is_nix_code("\"a ${b}\" + c"));
+        assert!(!is_nix_code("See <filename>/etc/hosts</filename>."));
+        assert!(!is_nix_code("a & b"));
+        assert!(!is_nix_code(""));
+    }
+
+    #[test]
+    fn examples_only_become_docbook_when_they_arent_nix() {
+        assert_eq!(literal(r#""{ a = 1; }""#), "lib.literalExpression");
+        assert_eq!(literal(r#""See <filename>/etc/hosts</filename>.""#), "lib.literalDocBook");
+        assert_eq!(literal(r#""${pkgs.foo}/bin/x""#), "lib.literalExpression");
+        assert_eq!(literal("''\n''"), "lib.literalExpression");
+        assert_eq!(literal("(builtins.toJSON { })"), "lib.literalExpression");
+    }
+
+    #[test]
+    fn gains_are_whole() {
+        let rewrite = Rewrite { gain: Some("config.a.package".to_string()), ..Default::default() };
+        let old = "<para>Default: <literal>package</literal></para>";
+        let new = "<para>Default: <programlisting>config.a.package\n</programlisting></para>";
+        assert!(rewrite.verify(old, new));
+        let more = "<para>Default: <programlisting>config.a.package or null</programlisting></para>";
+        assert!(!rewrite.verify(old, more));
+        let elsewhere = "<para>Default: <literal>package</literal>, or config.a.package</para>";
+        assert!(!rewrite.verify(old, elsewhere));
+        assert!(!rewrite.verify(new, new));
+    }
+
+    #[test]
+    fn types_strings_are_found_through_aliases() {
+        let found = |s: &str| {
+            find_types_strings(s).unwrap().into_iter().map(|(range, _)| s[range].to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(found("{ a = types.string; b = lib.types.string; }"), ["lib.types.string", "types.string"]);
+        assert_eq!(found("let l = lib; t = l.types; in [ l.types.string t.string ]"), ["t.string", "l.types.string"]);
+        assert_eq!(found("with lib.types; { a = nullOr string; }"), ["string"]);
+        assert_eq!(found("with lib; types.string"), ["types.string"]);
+        // names of attributes and arguments, and other strings
+        assert!(found("with types; { string = x.string; f = { string }: string: 1; }").is_empty());
+        assert!(found("with types; let string = 1; in string").is_empty());
+        assert!(found("{ a = string; b = other.string; }").is_empty());
+    }
+
+    #[test]
+    fn only_the_option_is_silenced() {
+        let warning = |option: &str| format!("The type `types.string' of option `{option}' defined in m.nix is deprecated.");
+        let old = [warning("a"), warning("b"), "unrelated".to_string()].into();
+        let rewrite = Rewrite {
+            silences: Some("`types.string'".to_string()),
+            option: "b".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(rewrite.expected_warnings(&old), [warning("a"), "unrelated".to_string()].into());
+    }
+
+    #[test]
+    fn placeholders_in_code_are_kept() {
+        assert_eq!(strip(r#""Set <option>services.x.<name>.y</option>.""#), r#""Set `services.x.<name>.y`.""#);
+        assert_eq!(
+            strip(r#""Set <option>services.x.<replaceable>name</replaceable>.y</option>.""#),
+            r#""Set `services.x.<name>.y`.""#,
+        );
+    }
+
+    #[test]
+    fn links_keep_their_target() {
+        assert_eq!(
+            strip(r#"''See <link xlink:href="https://nixos.org">the site</link> or <xref linkend="opt-foo"/>.''"#),
+            "''See the site (https://nixos.org) or opt-foo.''",
+        );
+    }
+
+    #[test]
+    fn other_tags_are_dropped() {
+        assert_eq!(strip(r#""<emphasis>Don't</emphasis> use &lt;3.""#), r#""Don't use <3.""#);
+    }
+}
@@ -0,0 +1,259 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// Tracks whether lines are part of DocBook whose whitespace matters,
+/// `<programlisting>` and `<screen>` that weren't made fenced code blocks.
+#[derive(Default)]
+struct Verbatim {
+    /// The closing tag awaited, if inside such an element.
+    close: Option<&'static str>,
+}
+
+impl Verbatim {
+    /// Whether `line`, the one after those given before, has any of such an
+    /// element in it.
+    fn line(&mut self, line: &str) -> bool {
+        static OPEN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<(programlisting|screen)[\s>]").unwrap());
+        let mut verbatim = self.close.is_some();
+        let mut rest = line;
+        loop {
+            match self.close {
+                Some(close) => match rest.find(close) {
+                    Some(i) => {
+                        rest = &rest[i + close.len() ..];
+                        self.close = None;
+                    },
+                    None => return verbatim,
+                },
+                None => match OPEN.captures(rest) {
+                    Some(caps) => {
+                        verbatim = true;
+                        self.close = Some(match &caps[1] {
+                            "screen" => "</screen>",
+                            _ => "</programlisting>",
+                        });
+                        rest = &rest[caps.get(0).unwrap().end() ..];
+                    },
+                    None => return verbatim,
+                },
+            }
+        }
+    }
+}
+
+/// Re-wrap the prose paragraphs of Markdown `text` so that no line is longer
+/// than `width` where it can be avoided. Code blocks, tables, lists, fenced
+/// div markers and anything else whose line structure matters is left as is.
+pub fn reflow(text: &str, width: usize) -> String {
+    let mut result = vec![];
+    let mut paragraph: Vec<&str> = vec![];
+    let mut in_code = false;
+    let mut verbatim = Verbatim::default();
+
+    for line in text.split('\n') {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            flush(&mut paragraph, &mut result, width);
+            in_code = !in_code;
+            result.push(line.to_string());
+        } else if in_code || verbatim.line(line) || trimmed.is_empty() || starts_block(trimmed) {
+            flush(&mut paragraph, &mut result, width);
+            result.push(line.to_string());
+        } else {
+            paragraph.push(line);
+            // hard line breaks end the paragraph as far as we're concerned
+            if line.ends_with("  ") || line.ends_with('\\') {
+                flush(&mut paragraph, &mut result, width);
+            }
+        }
+    }
+    flush(&mut paragraph, &mut result, width);
+
+    result.join("\n")
+}
+
+/// Whether `s` at the start of a line would be taken as something other than
+/// paragraph text.
+fn starts_block(s: &str) -> bool {
+    let word = s.split_whitespace().next().unwrap_or("");
+    let digits = word.trim_start_matches(|c: char| c.is_ascii_digit());
+    matches!(word, "-" | "+" | "*" | ">")
+        || word.starts_with('#')
+        || word.starts_with('|')
+        || word.starts_with(":::")
+        || word.starts_with("```")
+        || word.starts_with("<")
+        || word.starts_with("===")
+        || word.starts_with("---")
+        || (digits.len() < word.len() && matches!(digits, "." | ")"))
+}
+
+fn flush(paragraph: &mut Vec<&str>, result: &mut Vec<String>, width: usize) {
+    let Some(first) = paragraph.first() else {
+        return;
+    };
+    let indent = &first[.. first.len() - first.trim_start().len()];
+    // backslash breaks survive as part of the last word, trailing spaces don't
+    let hard_break = match paragraph.last() {
+        Some(l) if l.ends_with("  ") => "  ",
+        _ => "",
+    };
+    let words = words(&paragraph.iter().map(|l| l.trim()).collect::<Vec<_>>().join("\n"));
+
+    let mut line = indent.to_string();
+    let mut line_has_words = false;
+    for word in words {
+        let fits = line.chars().count() + 1 + word.chars().count() <= width;
+        if line_has_words && !fits && !starts_block(&word) {
+            result.push(std::mem::replace(&mut line, indent.to_string()));
+            line_has_words = false;
+        }
+        if line_has_words {
+            line.push(' ');
+        }
+        line.push_str(&word);
+        line_has_words = true;
+    }
+    line.push_str(hard_break);
+    result.push(line);
+    paragraph.clear();
+}
+
+/// The words of the paragraph `text`, split at whitespace but for that in
+/// code spans, which are kept whole and with their spaces. Line breaks in
+/// them are spaces in Markdown, so they become spaces.
+fn words(text: &str) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            i += 1;
+        } else if c == '\\' {
+            word.extend(&chars[i .. (i + 2).min(chars.len())]);
+            i += 2;
+        } else if c == '`' {
+            let run = chars[i ..].iter().take_while(|&&c| c == '`').count();
+            let end = closing_run(&chars, i + run, run).unwrap_or(i + run);
+            word.extend(chars[i .. end].iter().map(|&c| if c == '\n' { ' ' } else { c }));
+            i = end;
+        } else {
+            word.push(c);
+            i += 1;
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// The end of the first run of exactly `length` backticks in `chars` from
+/// `start`, which closes a code span.
+fn closing_run(chars: &[char], start: usize, length: usize) -> Option<usize> {
+    let mut i = start;
+    while i < chars.len() {
+        let run = chars[i ..].iter().take_while(|&&c| c == '`').count();
+        if run == length {
+            return Some(i + run);
+        }
+        i += run.max(1);
+    }
+    None
+}
+
+/// Strip trailing whitespace from lines and collapse runs of blank lines into
+/// one, outside of code blocks, fenced or not. Trailing spaces would turn
+/// into hard line breaks in Markdown where DocBook ignored them.
+pub fn tidy_whitespace(text: &str) -> String {
+    let mut result: Vec<&str> = vec![];
+    let mut in_code = false;
+    let mut verbatim = Verbatim::default();
+
+    for line in text.split('\n') {
+        let trimmed = line.trim_start();
+        if in_code {
+            in_code = !(trimmed.starts_with("```") || trimmed.starts_with("~~~"));
+            result.push(line);
+            continue;
+        }
+        in_code = trimmed.starts_with("```") || trimmed.starts_with("~~~");
+        if verbatim.line(line) {
+            result.push(line);
+            continue;
+        }
+
+        let line = line.trim_end();
+        // keep the first line even if blank, it determines whether the
+        // contents of an indented string start on the line of the quotes
+        if line.is_empty() && result.len() > 1 && result.last() == Some(&"") {
+            continue;
+        }
+        result.push(line);
+    }
+
+    result.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reflow, tidy_whitespace};
+
+    #[test]
+    fn paragraphs_are_rewrapped() {
+        assert_eq!(reflow("  one two\n  three four five", 12), "  one two\n  three four\n  five");
+    }
+
+    #[test]
+    fn long_words_are_kept_whole() {
+        let word = "services.example.settings.a.very.long.option";
+        assert_eq!(reflow(&format!("see {word} here"), 10), format!("see\n{word}\nhere"));
+        // a line starting with one would be HTML
+        let url = "<https://example.com/a/very/long/path>";
+        assert_eq!(reflow(&format!("see {url} here"), 10), format!("see {url}\nhere"));
+    }
+
+    #[test]
+    fn words_that_start_blocks_dont_start_lines() {
+        assert_eq!(reflow("aaaa bbbb - cccc 1. dddd", 9), "aaaa bbbb -\ncccc 1.\ndddd");
+    }
+
+    #[test]
+    fn code_spans_are_kept_whole() {
+        assert_eq!(reflow("see `a  b` and ``c `d`` e", 5), "see\n`a  b`\nand\n``c `d``\ne");
+        // across lines too, where the line break is a space
+        assert_eq!(reflow("one `two\n  three` four", 80), "one `two three` four");
+        // a backtick without its match is just a character
+        assert_eq!(reflow("a ` b  c", 80), "a ` b c");
+    }
+
+    #[test]
+    fn list_items_are_kept() {
+        let text = "Either:\n- one two three four\n- five\n1. six seven\n2) eight";
+        assert_eq!(reflow(text, 8), "Either:\n- one two three four\n- five\n1. six seven\n2) eight");
+    }
+
+    #[test]
+    fn admonition_fences_are_kept() {
+        let text = "::: {.note}\nsome text that wraps\n:::\nafter";
+        assert_eq!(reflow(text, 10), "::: {.note}\nsome text\nthat wraps\n:::\nafter");
+    }
+
+    #[test]
+    fn code_blocks_are_kept() {
+        let text = "```nix\n{ a = 1;   b = 2; }\n\n\n  long line of code\n```\n<programlisting>x   y\nz</programlisting>";
+        assert_eq!(reflow(text, 5), text);
+        assert_eq!(tidy_whitespace(text), text);
+    }
+
+    #[test]
+    fn whitespace_is_tidied() {
+        assert_eq!(tidy_whitespace("\na  \n\n\n\nb\t"), "\na\n\nb");
+    }
+}
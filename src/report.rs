@@ -0,0 +1,109 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    sync::{LazyLock, Mutex},
+};
+
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// What happened to a candidate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    /// The rewrite was verified and kept.
+    Changed,
+    /// The manual differed from the expected one only in ways allowed by
+    /// `--allowed-diffs`, and the rewrite was kept.
+    Allowed,
+    /// The manual differed from the expected one only in text matching
+    /// `--force-apply-pattern`, and the rewrite was kept anyway.
+    Forced,
+    /// The manual built, but not into what the rewrite expected.
+    Mismatch,
+    /// The manual built into what the rewrite expected but for whitespace,
+    /// such as the indentation of a code block.
+    NearMiss,
+    /// The manual didn't build after the rewrite.
+    BuildFailed,
+    /// The pass couldn't produce a rewrite at all.
+    RewriteFailed,
+    /// The rewritten file isn't valid Nix, a bug in the pass.
+    InvalidNix,
+    /// Skipped for overlapping with another candidate.
+    Overlapping,
+    /// Plain text, converted by `--only-markup accept` without checking.
+    Unchecked,
+    /// Left alone for `--skip`, or for being plain text by `--only-markup`.
+    Skipped,
+}
+
+impl Outcome {
+    /// Whether the rewrite was kept.
+    pub fn accepted(self) -> bool {
+        matches!(self, Outcome::Changed | Outcome::Allowed | Outcome::Forced | Outcome::Unchecked)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Entry {
+    /// The file and a name for the candidate unique within it, the same
+    /// from run to run, which its directory in `munge-failures` is named
+    /// after.
+    pub id: String,
+    pub file: String,
+    /// Attribute path of the option the candidate belongs to, as far as it
+    /// can be told from the file alone.
+    pub option: String,
+    pub start: usize,
+    pub end: usize,
+    /// What the candidate is, see `CandidateKind::name`.
+    pub kind: &'static str,
+    pub outcome: Outcome,
+    /// For mismatches, how alike the differing parts of the manual and the
+    /// expected one are, from 0 to 1.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub similarity: Option<f32>,
+    /// How the manual differed from the expected one, for changes kept
+    /// despite differences.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    /// The entries of `--allowed-diffs` the change was accepted with.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub allowed: Vec<String>,
+    /// Options declared outside the file whose entries the change changed
+    /// as well, the file being imported by the modules declaring them.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub elsewhere: Vec<String>,
+    /// The DocBook tags in the candidate before the rewrite.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// The names of the tags opened in `text`, sorted and without duplicates.
+pub fn tags(text: &str) -> Vec<String> {
+    static TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<([a-zA-Z][\w:]*)").unwrap());
+    TAG.captures_iter(text)
+        .map(|c| c[1].to_string())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// Outcomes of all candidates, for `--report`.
+#[derive(Default)]
+pub struct Report(Mutex<Vec<Entry>>);
+
+impl Report {
+    pub fn add(&self, entry: Entry) {
+        self.0.lock().unwrap().push(entry);
+    }
+
+    pub fn write(&self, path: &str) -> Result<()> {
+        let mut entries = self.0.lock().unwrap();
+        entries.sort_by(|a, b| (&a.file, a.start, a.end).cmp(&(&b.file, b.start, b.end)));
+        fs::write(path, serde_json::to_string_pretty(&*entries)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+use crate::{markup, trace::fired};
+
+/// Replacement rules for the house style of a tree, applied to the value of
+/// each description before the built-in conversion or after it. Given as a
+/// TOML file to `--rules`, e.g.
+///
+/// ```toml
+/// [[pre]]
+/// regex = '<productname>([^<]*)</productname>'
+/// template = '*$1*'
+///
+/// [[post]]
+/// name = "house style"
+/// command = "sed -e 's/Nixpkgs/nixpkgs/g'"
+/// ```
+///
+/// Templates are those of `Regex::replace_all`, and commands are run by
+/// `sh -c` with the text on their standard input. The text templates make
+/// before the conversion is kept as it is, while the output of commands is
+/// converted and escaped like the rest of the DocBook.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(try_from = "RulesFile")]
+pub struct Rules {
+    pre: Vec<Rule>,
+    post: Vec<Rule>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    pre: Vec<RuleEntry>,
+    #[serde(default)]
+    post: Vec<RuleEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleEntry {
+    name: Option<String>,
+    regex: Option<String>,
+    template: Option<String>,
+    command: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+enum Action {
+    Replace(Regex, String),
+    Command(String),
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    /// What `--explain` calls the rule.
+    name: String,
+    action: Action,
+}
+
+impl Rule {
+    fn new(entry: RuleEntry) -> Result<Self, String> {
+        let action = match entry {
+            RuleEntry { regex: Some(regex), template: Some(template), command: None, .. } => {
+                Action::Replace(Regex::new(&regex).map_err(|e| e.to_string())?, template)
+            },
+            RuleEntry { regex: None, template: None, command: Some(command), .. } => Action::Command(command),
+            _ => return Err("a rule needs either a regex and a template, or a command".to_string()),
+        };
+        let name = entry.name.unwrap_or_else(|| match &action {
+            Action::Replace(regex, _) => regex.as_str().to_string(),
+            Action::Command(command) => command.clone(),
+        });
+        Ok(Self { name, action })
+    }
+
+    /// `s` with the rule applied. Text a command fails on is left as it is,
+    /// the check of the rewrite tells whether the conversion is still right.
+    /// Replacements before the conversion are Markdown, which the conversion
+    /// leaves as it is with `mark`.
+    fn apply(&self, s: &str, mark: fn(&str) -> String) -> String {
+        let new = match &self.action {
+            Action::Replace(regex, template) => regex.replace_all(s, |caps: &Captures<'_>| {
+                let mut replacement = String::new();
+                caps.expand(template, &mut replacement);
+                mark(&replacement)
+            }),
+            Action::Command(command) => run(command, s).map_or(s.into(), Into::into),
+        };
+        fired(&self.name, s, new)
+    }
+}
+
+impl TryFrom<RulesFile> for Rules {
+    type Error = String;
+
+    fn try_from(file: RulesFile) -> Result<Self, String> {
+        let rules = |entries: Vec<RuleEntry>| entries.into_iter().map(Rule::new).collect::<Result<Vec<_>, _>>();
+        Ok(Self { pre: rules(file.pre)?, post: rules(file.post)? })
+    }
+}
+
+/// The output of `command` given `input`, if it succeeds.
+fn run(command: &str, input: &str) -> Option<String> {
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .ok()?;
+    // written from another thread, the command may well output while reading
+    let mut stdin = child.stdin.take()?;
+    let input = input.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().ok()?;
+    writer.join().ok()?.ok()?;
+    output.status.success().then(|| String::from_utf8(output.stdout).ok())?
+}
+
+impl Rules {
+    /// Load the rules at `path`. Meant to be used as a clap value parser.
+    #[cfg(feature = "cli")]
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        toml::from_str(&text).map_err(|e| format!("{path}: {e}"))
+    }
+
+    /// `s` with the rules to apply before the conversion applied.
+    pub fn pre(&self, s: &str) -> String {
+        self.pre.iter().fold(s.to_string(), |s, rule| rule.apply(&s, markup))
+    }
+
+    /// `s` with the rules to apply after the conversion applied.
+    pub fn post(&self, s: &str) -> String {
+        self.post.iter().fold(s.to_string(), |s, rule| rule.apply(&s, str::to_string))
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::LazyLock,
+};
+
+use anyhow::{Context, Result, bail};
+use nix_doc_munge::{
+    ConvertOptions,
+    pass::{Candidate, Pass},
+};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{error, report};
+
+/// A candidate left to convert, as listed by `scan`.
+#[derive(Serialize)]
+struct Entry {
+    file: String,
+    option: String,
+    start: usize,
+    end: usize,
+    kind: &'static str,
+    tags: Vec<String>,
+    /// Whether the rewrite comes out without any DocBook left in it.
+    supported: bool,
+    /// The tags left in the rewrite, which the pass has no rule for.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    unsupported: Vec<String>,
+}
+
+/// What `pass` rewrites `candidate` of `s` into, or `None` if it can't.
+pub fn rewritten(pass: Pass, file: &str, s: &str, candidate: &Candidate,
+                 opts: &ConvertOptions) -> Option<String> {
+    let rewrite = pass.rewrite(file, s, candidate, opts).ok()?;
+    // the candidate as rewritten, the rest of the file is left as it was
+    let start = usize::from(candidate.range.start());
+    let end = (usize::from(candidate.range.end()) + rewrite.content.len()).checked_sub(s.len())?;
+    Some(rewrite.content.get(start .. end)?.to_string())
+}
+
+/// The tags left in `rewritten`. Code spans are skipped, they may well show
+/// placeholders like `<name>`.
+pub fn tags_left(rewritten: &str) -> Vec<String> {
+    static CODE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"`[^`]*`").unwrap());
+    report::tags(&CODE.replace_all(rewritten, ""))
+}
+
+/// The tags `pass` leaves in its rewrite of `candidate` of `s`, or `None` if
+/// it can't rewrite it at all.
+fn leftover_tags(pass: Pass, file: &str, s: &str, candidate: &Candidate,
+                 opts: &ConvertOptions) -> Option<Vec<String>> {
+    rewritten(pass, file, s, candidate, opts).map(|rewritten| tags_left(&rewritten))
+}
+
+/// Inline tags that can be made code spans by adding them to `--tag-map`.
+const CODE_LIKE: &[&str] = &[
+    "application", "code", "computeroutput", "constant", "function", "literal", "package",
+    "parameter", "productname", "prompt", "property", "replaceable", "symbol", "systemitem",
+    "type", "uri", "userinput",
+];
+
+/// What to do about `tag`, which the pass left in a rewrite.
+fn suggestion(tag: &str) -> String {
+    match tag {
+        _ if CODE_LIKE.contains(&tag) => {
+            format!("make it a code span with `--tag-map`, a file with `[code]` and `{tag} = \"\"`")
+        },
+        "quote" => "write its quotes out as text".to_string(),
+        "itemizedlist" | "orderedlist" | "variablelist" | "simplelist" | "listitem" => {
+            "convert the list into a Markdown one by hand".to_string()
+        },
+        "link" | "xref" | "emphasis" | "citerefentry" => {
+            "its rule didn't match, check it for nested markup or unusual attributes".to_string()
+        },
+        _ => "convert it by hand".to_string(),
+    }
+}
+
+/// The tags the pass has no rule for, with the number of candidates that
+/// are left with each after their rewrite.
+#[derive(Default)]
+pub struct UnsupportedTags(BTreeMap<String, usize>);
+
+impl UnsupportedTags {
+    fn add(&mut self, tags: &[String]) {
+        for tag in tags {
+            *self.0.entry(tag.clone()).or_default() += 1;
+        }
+    }
+
+    /// Count the tags left in the rewrites of `candidates` of `s`.
+    pub fn scan(&mut self, pass: Pass, file: &str, s: &str, candidates: &[Candidate], opts: &ConvertOptions) {
+        for candidate in candidates {
+            self.add(&leftover_tags(pass, file, s, candidate, opts).unwrap_or_default());
+        }
+    }
+
+    /// Print the tags by how often they are left, with what to do about
+    /// them.
+    pub fn print(&self) {
+        if self.0.is_empty() {
+            return;
+        }
+        let mut tags = self.0.iter().collect::<Vec<_>>();
+        tags.sort_by_key(|(tag, n)| (std::cmp::Reverse(**n), *tag));
+        println!("tags without a rule, the candidates with them are likely to fail:");
+        for (tag, n) in tags {
+            println!("  <{tag}> in {n} candidates: {}", suggestion(tag));
+        }
+    }
+}
+
+/// Run `scan`: list the candidates of `files` with their tags and whether
+/// they would convert cleanly, printing them or writing them to `json`.
+pub fn run(files: &[String], json: Option<&Path>, pass: Pass, opts: &ConvertOptions) -> Result<()> {
+    let mut entries = vec![];
+    let mut broken = 0;
+    for file in files {
+        let content = fs::read_to_string(file).with_context(|| format!("reading {file}"))?;
+        let (mut candidates, overlapping) = match pass.scan(&content, opts) {
+            Ok(scan) => scan,
+            Err(e) => {
+                eprintln!("error: {}", error::parse(file)(e));
+                broken += 1;
+                continue;
+            },
+        };
+        candidates.extend(overlapping);
+        candidates.sort_by_key(|c| c.range.start());
+        for candidate in candidates {
+            let leftover = leftover_tags(pass, file, &content, &candidate, opts);
+            entries.push(Entry {
+                file: file.clone(),
+                option: candidate.option_path.clone(),
+                start: candidate.range.start().into(),
+                end: candidate.range.end().into(),
+                kind: candidate.kind.name(),
+                tags: report::tags(&content[candidate.range]),
+                supported: leftover.as_ref().is_some_and(Vec::is_empty),
+                unsupported: leftover.unwrap_or_default(),
+            });
+        }
+    }
+
+    if let Some(path) = json {
+        fs::write(path, serde_json::to_string_pretty(&entries)?)
+            .with_context(|| format!("writing {}", path.display()))?;
+    } else {
+        for e in &entries {
+            let option = match e.option.as_str() {
+                "" => format!("{}-{}", e.start, e.end),
+                option => option.to_string(),
+            };
+            let support = match (e.supported, e.unsupported.as_slice()) {
+                (true, _) => "supported".to_string(),
+                (false, []) => "can't be rewritten".to_string(),
+                (false, tags) => format!("leaves <{}>", tags.join(">, <")),
+            };
+            println!("{}: {option} ({}) [{}] {support}", e.file, e.kind, e.tags.join(", "));
+        }
+    }
+    let supported = entries.iter().filter(|e| e.supported).count();
+    eprintln!("{} candidates left, {supported} of them supported", entries.len());
+    if json.is_none() {
+        let mut unsupported = UnsupportedTags::default();
+        for e in &entries {
+            unsupported.add(&e.unsupported);
+        }
+        unsupported.print();
+    }
+    if broken > 0 {
+        bail!("{broken} files don't parse and weren't scanned");
+    }
+    Ok(())
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    process::Command,
+};
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use nix_doc_munge::pass::Pass;
+use tempfile::tempdir;
+
+use crate::Converted;
+
+/// How `--split-by` divides the changed files, parsed from `dir:N`.
+#[derive(Clone, Copy, Debug)]
+pub struct SplitBy {
+    chunks: usize,
+}
+
+impl SplitBy {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let Some(("dir", n)) = s.split_once(':') else {
+            return Err("expected dir:N".to_string());
+        };
+        match n.parse() {
+            Ok(0) | Err(_) => Err(format!("{n} isn't a positive number of chunks")),
+            Ok(chunks) => Ok(Self { chunks }),
+        }
+    }
+}
+
+/// What to make of each chunk of `--split-by`.
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum SplitOutput {
+    /// A patch in `munge-split/`, for `git am`.
+    #[default]
+    Patches,
+    /// A branch `munge/split-N` off `HEAD`.
+    Branches,
+}
+
+/// Where patches of `SplitOutput::Patches` are written.
+const PATCH_DIR: &str = "munge-split";
+
+/// Group `files` by directory into at most `chunks` parts of about the same
+/// number of files. Directories are kept whole and in order, so each part
+/// covers a contiguous part of the tree.
+fn chunk(files: &[String], chunks: usize) -> Vec<Vec<(String, Vec<String>)>> {
+    let mut dirs = BTreeMap::<String, Vec<String>>::new();
+    for file in files {
+        let dir = Path::new(file).parent()
+            .filter(|d| !d.as_os_str().is_empty())
+            .map_or(".".to_string(), |d| d.to_string_lossy().into_owned());
+        dirs.entry(dir).or_default().push(file.clone());
+    }
+    let target = files.len().div_ceil(chunks);
+    let mut result: Vec<Vec<(String, Vec<String>)>> = vec![vec![]];
+    let mut size = 0;
+    for (dir, files) in dirs {
+        if size >= target && result.len() < chunks {
+            result.push(vec![]);
+            size = 0;
+        }
+        size += files.len();
+        result.last_mut().unwrap().push((dir, files));
+    }
+    result
+}
+
+fn git(args: &[&str], index: Option<&Path>) -> Result<String> {
+    let mut command = Command::new("git");
+    if let Some(index) = index {
+        command.env("GIT_INDEX_FILE", index);
+    }
+    let result = command.args(args).output().context("running git")?;
+    if !result.status.success() {
+        bail!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&result.stderr));
+    }
+    Ok(String::from_utf8_lossy(&result.stdout).trim().to_string())
+}
+
+/// Commit the changes to `files` in the work tree on top of `HEAD`, without
+/// touching the index or the work tree.
+fn commit(files: &[String], message: &str) -> Result<String> {
+    let tmp = tempdir()?;
+    let index = tmp.path().join("index");
+    git(&["read-tree", "HEAD"], Some(&index))?;
+    let mut add = vec!["add", "-A", "--"];
+    add.extend(files.iter().map(String::as_str));
+    git(&add, Some(&index))?;
+    let tree = git(&["write-tree"], Some(&index))?;
+    git(&["commit-tree", &tree, "-p", "HEAD", "-m", message], None)
+}
+
+/// The branches of `SplitOutput::Branches` there already are.
+fn existing_branches() -> Result<Vec<String>> {
+    let refs = git(&["for-each-ref", "--format=%(refname:short)", "refs/heads/munge/split-*"], None)?;
+    Ok(refs.lines().map(str::to_string).collect())
+}
+
+/// Fail if `output` would overwrite branches of an earlier split, unless
+/// `overwrite` allows it, so that a run doesn't only find out at the end.
+pub fn check(output: SplitOutput, overwrite: bool) -> Result<()> {
+    let SplitOutput::Branches = output else {
+        return Ok(());
+    };
+    let existing = existing_branches()?;
+    if !overwrite && !existing.is_empty() {
+        bail!("split branches already exist: {}, remove them or pass --split-overwrite", existing.join(", "));
+    }
+    Ok(())
+}
+
+/// Split the changes written to the tree into chunks of directories, each
+/// committed on its own as a patch or a branch. Existing branches are only
+/// replaced with `overwrite`.
+pub fn run(converted: &[Converted], split: SplitBy, output: SplitOutput, overwrite: bool, pass: Pass) -> Result<()> {
+    let mut files = converted.iter()
+        .flat_map(|c| [c.file.clone()].into_iter().chain(c.files.iter().map(|(path, _)| path.clone())))
+        .collect::<Vec<_>>();
+    files.sort();
+    files.dedup();
+    let chunks = chunk(&files, split.chunks);
+    let pass = pass.to_possible_value().unwrap();
+    let branch = |i: usize| format!("munge/split-{}", i + 1);
+    match output {
+        SplitOutput::Patches => fs::create_dir_all(PATCH_DIR)?,
+        SplitOutput::Branches if !overwrite => {
+            let existing = existing_branches()?;
+            let taken = (0 .. chunks.len()).map(branch).filter(|b| existing.contains(b)).collect::<Vec<_>>();
+            if !taken.is_empty() {
+                bail!("split branches already exist: {}, remove them or pass --split-overwrite", taken.join(", "));
+            }
+        },
+        SplitOutput::Branches => {},
+    }
+    for (i, dirs) in chunks.iter().enumerate() {
+        let part = format!("{}/{}", i + 1, chunks.len());
+        let listing = dirs.iter().map(|(dir, _)| format!("- {dir}/\n")).collect::<String>();
+        let message = format!("treewide: apply the {} pass of nix-doc-munge ({part})\n\n{listing}",
+                              pass.get_name());
+        let files = dirs.iter().flat_map(|(_, files)| files.clone()).collect::<Vec<_>>();
+        let commit = commit(&files, &message)?;
+        match output {
+            SplitOutput::Patches => {
+                let patch = git(&["format-patch", "-1", "--stdout", &commit], None)?;
+                let path = Path::new(PATCH_DIR).join(format!("{:04}.patch", i + 1));
+                fs::write(&path, patch + "\n")?;
+                println!("{part}: {} files in {}", files.len(), path.display());
+            },
+            SplitOutput::Branches => {
+                let branch = branch(i);
+                match overwrite {
+                    true => git(&["branch", "--force", &branch, &commit], None)?,
+                    false => git(&["branch", &branch, &commit], None)?,
+                };
+                println!("{part}: {} files on {branch}", files.len());
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::chunk;
+
+    fn files(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn dirs(chunks: &[Vec<(String, Vec<String>)>]) -> Vec<Vec<&str>> {
+        chunks.iter().map(|dirs| dirs.iter().map(|(dir, _)| dir.as_str()).collect()).collect()
+    }
+
+    #[test]
+    fn directories_are_kept_whole() {
+        let files = files(&["a/1.nix", "a/2.nix", "a/3.nix", "b/1.nix", "c/1.nix", "c/2.nix"]);
+        let chunks = chunk(&files, 2);
+        assert_eq!(dirs(&chunks), [vec!["a"], vec!["b", "c"]]);
+        assert_eq!(chunks[0][0].1, ["a/1.nix", "a/2.nix", "a/3.nix"]);
+    }
+
+    #[test]
+    fn files_at_the_top_are_a_directory() {
+        let chunks = chunk(&files(&["default.nix", "a/1.nix"]), 2);
+        assert_eq!(dirs(&chunks), [vec!["."], vec!["a"]]);
+    }
+
+    #[test]
+    fn chunks_are_at_most_the_directories() {
+        let chunks = chunk(&files(&["a/1.nix", "a/2.nix", "b/1.nix"]), 5);
+        assert_eq!(dirs(&chunks), [vec!["a"], vec!["b"]]);
+    }
+}
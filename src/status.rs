@@ -0,0 +1,384 @@
+use std::{
+    collections::VecDeque,
+    sync::{LazyLock, Mutex, MutexGuard, atomic::{AtomicI8, Ordering}},
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+
+use crate::{notify::Notifier, report::Outcome};
+
+/// Output level, set once from `-q` and `-v`. At any level but the default
+/// there are no status lines, only the messages of the levels enabled.
+static VERBOSITY: AtomicI8 = AtomicI8::new(0);
+
+/// Nothing at all, for `--explain`.
+pub const SILENT: i8 = -2;
+/// Failures, and the summary at the end.
+pub const QUIET: i8 = -1;
+/// Each accepted change.
+pub const VERBOSE: i8 = 1;
+/// Each `nix-build` command.
+pub const DEBUG: i8 = 2;
+
+pub fn set_verbosity(level: i8) {
+    VERBOSITY.store(level, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> i8 {
+    VERBOSITY.load(Ordering::Relaxed)
+}
+
+/// Print `message` if the output level includes `level`.
+pub fn log(level: i8, message: impl AsRef<str>) {
+    let verbosity = verbosity();
+    if verbosity != 0 && level <= verbosity {
+        println!("{}", message.as_ref());
+    }
+}
+
+/// What one worker is doing.
+#[derive(Default)]
+pub struct WorkerStatus {
+    pub file: String,
+    pub item: String,
+    pub phase: String,
+    /// The latest line logged by the build of the phase, if any.
+    pub build: String,
+    /// Set from the TUI to give up on the rest of the file.
+    pub skip: bool,
+}
+
+/// A candidate that wasn't changed, as listed by the TUI.
+pub struct Failure {
+    pub file: String,
+    pub item: String,
+    pub outcome: Outcome,
+    /// What went wrong: a diff of the manuals, or the error.
+    pub preview: String,
+}
+
+pub struct StatusReportData {
+    files: usize,
+    items: usize,
+    total_files: usize,
+    total_items: usize,
+    changed_items: usize,
+    failed_items: usize,
+    /// What went wrong in the latest failed build.
+    pub last_error: Option<String>,
+    notifier: Notifier,
+    /// One slot per worker, `None` while it is idle.
+    pub workers: Vec<Option<WorkerStatus>>,
+    pub failures: Vec<Failure>,
+    /// Drawn by the TUI instead of printed.
+    tui: bool,
+    /// Set from the TUI or by Ctrl-C to stop all workers.
+    pub abort: bool,
+    /// Lines of the previous `print`, to be overwritten by the next one.
+    printed: usize,
+    /// When the status was last printed, so that build logs don't redraw
+    /// it for every line.
+    printed_at: Option<Instant>,
+    /// When the most recent manual builds finished, for the build rate.
+    builds: VecDeque<Instant>,
+}
+
+/// Number of builds the build rate is averaged over.
+const RATE_WINDOW: usize = 50;
+
+/// Shortest time between redraws for build log lines.
+const BUILD_LINE_INTERVAL: Duration = Duration::from_millis(100);
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    match secs {
+        0 .. 60 => format!("{secs}s"),
+        60 .. 3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+/// Cut `line` to `width` columns, so that it doesn't wrap and throw off the
+/// cursor movement of the next `print`.
+fn fit(line: &str, width: usize) -> String {
+    if line.chars().count() <= width {
+        return line.to_string();
+    }
+    let mut line = line.chars().take(width.saturating_sub(1)).collect::<String>();
+    line.push('…');
+    line
+}
+
+impl StatusReportData {
+    /// Builds per minute over the last `RATE_WINDOW` builds, and how long
+    /// the remaining ones will take at that rate.
+    fn rate(&self) -> Option<(f64, Duration)> {
+        let (first, last) = (self.builds.front()?, self.builds.back()?);
+        let elapsed = last.duration_since(*first).as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+        let per_sec = (self.builds.len() - 1) as f64 / elapsed;
+        // files not yet started may need a build of the unchanged manual too
+        let remaining = (self.total_items - self.items) + (self.total_files - self.files);
+        Some((per_sec * 60.0, Duration::from_secs_f64(remaining as f64 / per_sec)))
+    }
+
+    pub fn totals(&self) -> String {
+        let totals = format!("{}/{} files, {}/{} ({}) items", self.files, self.total_files,
+                             self.items, self.total_items, self.changed_items);
+        match self.rate() {
+            Some((per_min, eta)) => {
+                format!("{totals}, {per_min:.1} builds/min, ETA {}", format_duration(eta))
+            }
+            None => totals,
+        }
+    }
+
+    pub fn worker_line(i: usize, worker: Option<&WorkerStatus>) -> String {
+        let line = match worker {
+            Some(w) if w.item.is_empty() => format!("[{i:2}] {}: {}", w.file, w.phase),
+            Some(w) => format!("[{i:2}] {} {}: {}", w.file, w.item, w.phase),
+            None => return format!("[{i:2}] idle"),
+        };
+        match worker.map(|w| w.build.as_str()) {
+            Some("") | None => line,
+            Some(build) => format!("{line} | {build}"),
+        }
+    }
+
+    fn print(&mut self) {
+        if self.tui || verbosity() != 0 {
+            return;
+        }
+        if self.printed > 0 {
+            print!("\x1b[{}F\x1b[0J", self.printed);
+        }
+        let width = terminal_size::terminal_size().map_or(usize::MAX, |(w, _)| w.0 as usize);
+        println!("{}", fit(&self.totals(), width));
+        if let Some(error) = &self.last_error {
+            println!("{}", fit(&format!("last build failure: {error}"), width));
+        }
+        for (i, worker) in self.workers.iter().enumerate() {
+            println!("{}", fit(&Self::worker_line(i, worker.as_ref()), width));
+        }
+        self.printed = self.workers.len() + 1 + usize::from(self.last_error.is_some());
+        self.printed_at = Some(Instant::now());
+    }
+}
+
+pub struct StatusReport(Mutex<StatusReportData>);
+
+impl StatusReport {
+    pub fn new(total_files: usize, total_items: usize, tui: bool, notifier: Notifier) -> Self {
+        Self(Mutex::new(StatusReportData {
+            files: 0,
+            items: 0,
+            total_files,
+            total_items,
+            changed_items: 0,
+            failed_items: 0,
+            last_error: None,
+            notifier,
+            workers: vec![],
+            failures: vec![],
+            tui,
+            abort: false,
+            printed: 0,
+            printed_at: None,
+            builds: VecDeque::new(),
+        }))
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, StatusReportData> {
+        self.0.lock().unwrap()
+    }
+
+    /// Stop all workers once their current build is done, returning
+    /// whether they had already been told to.
+    pub fn abort(&self) -> bool {
+        let mut m = self.lock();
+        std::mem::replace(&mut m.abort, true)
+    }
+
+    /// Claim a status line for a worker starting on `file`.
+    fn enter_file(&self, file: &str) -> usize {
+        self.lock().files += 1;
+        self.claim(file)
+    }
+
+    /// Claim a status line labelled `file`.
+    fn claim(&self, file: &str) -> usize {
+        let mut m = self.lock();
+        let status = WorkerStatus { file: file.to_string(), ..Default::default() };
+        let slot = match m.workers.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => {
+                m.workers.push(None);
+                m.workers.len() - 1
+            }
+        };
+        m.workers[slot] = Some(status);
+        m.print();
+        slot
+    }
+
+    fn leave_file(&self, slot: usize, skipped: usize) {
+        let mut m = self.lock();
+        m.items += skipped;
+        m.workers[slot] = None;
+        m.print();
+    }
+
+    fn update(&self, slot: usize, f: impl FnOnce(&mut WorkerStatus)) {
+        let mut m = self.lock();
+        if let Some(worker) = &mut m.workers[slot] {
+            f(worker);
+        }
+        m.print();
+    }
+
+    /// Show `line` of the running build on the line of `slot`, redrawing at
+    /// most every `BUILD_LINE_INTERVAL`.
+    fn build_line(&self, slot: usize, line: &str) {
+        // store paths are long and their hashes of no help
+        static STORE_HASH: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"/nix/store/[0-9a-z]{32}-").unwrap());
+        let line = line.trim();
+        if line.is_empty() {
+            return;
+        }
+        let mut m = self.lock();
+        if let Some(worker) = &mut m.workers[slot] {
+            worker.build = STORE_HASH.replace_all(line, "").into_owned();
+        }
+        if m.printed_at.is_none_or(|at| at.elapsed() >= BUILD_LINE_INTERVAL) {
+            m.print();
+        }
+    }
+
+    fn enter_item(&self, slot: usize, item: String) {
+        self.lock().items += 1;
+        self.update(slot, |w| w.item = item);
+    }
+
+    fn built(&self) {
+        let mut m = self.lock();
+        if m.builds.len() == RATE_WINDOW {
+            m.builds.pop_front();
+        }
+        m.builds.push_back(Instant::now());
+        m.print();
+    }
+
+    fn changed_item(&self) {
+        let mut m = self.lock();
+        m.changed_items += 1;
+        m.print();
+    }
+}
+
+/// The status of a single file, freeing its line when dropped.
+pub struct StatusPart<'a> {
+    report: &'a StatusReport,
+    slot: usize,
+    remaining: usize,
+}
+
+impl<'a> StatusPart<'a> {
+    pub fn new(report: &'a StatusReport, file: &str, items: usize) -> Self {
+        let slot = report.enter_file(file);
+        Self { report, slot, remaining: items }
+    }
+
+    /// A line of its own for `items` of the remaining items of the file,
+    /// worked on alongside them.
+    pub fn split(&mut self, label: &str, items: usize) -> Self {
+        self.remaining -= items;
+        Self { report: self.report, slot: self.report.claim(label), remaining: items }
+    }
+
+    pub fn enter_item(&mut self, i: String) {
+        self.report.enter_item(self.slot, i);
+        self.remaining -= 1;
+    }
+
+    pub fn phase(&mut self, phase: &str) {
+        self.report.update(self.slot, |w| {
+            w.phase = phase.to_string();
+            w.build.clear();
+        });
+    }
+
+    /// Show `line` logged by the build of the current phase after it.
+    pub fn build_line(&self, line: &str) {
+        self.report.build_line(self.slot, line);
+    }
+
+    pub fn built(&mut self) {
+        self.report.built();
+    }
+
+    pub fn changed_item(&mut self) {
+        self.report.changed_item();
+        log(VERBOSE, format!("{}: changed", self.current()));
+    }
+
+    /// The file and item being worked on, for messages.
+    fn current(&self) -> String {
+        let m = self.report.lock();
+        let worker = m.workers[self.slot].as_ref().unwrap();
+        format!("{} {}", worker.file, worker.item)
+    }
+
+    /// Record a candidate that wasn't changed. The preview is only
+    /// computed if there is a TUI to show it.
+    pub fn failed(&mut self, outcome: Outcome, preview: impl FnOnce() -> String) {
+        log(QUIET, format!("{}: {outcome:?}", self.current()));
+        let (notifier, tui) = {
+            let mut m = self.report.lock();
+            m.failed_items += 1;
+            let threshold = m.notifier.failures == Some(m.failed_items);
+            (threshold.then(|| m.notifier.clone()), m.tui)
+        };
+        if let Some(notifier) = notifier {
+            // nowhere to show errors in the middle of the run
+            let failures = notifier.failures.unwrap();
+            let _ = notifier.send(&format!("{failures} candidates failed so far"));
+        }
+        if !tui {
+            return;
+        }
+        // diffing manuals takes a while, don't hold up the other workers
+        let preview = preview();
+        let mut m = self.report.lock();
+        let worker = m.workers[self.slot].as_ref().unwrap();
+        let failure = Failure {
+            file: worker.file.clone(),
+            item: worker.item.clone(),
+            outcome,
+            preview,
+        };
+        m.failures.push(failure);
+    }
+
+    /// Show `line` as the reason the latest build failed.
+    pub fn error_line(&mut self, line: &str) {
+        let mut m = self.report.lock();
+        let worker = m.workers[self.slot].as_ref().unwrap();
+        m.last_error = Some(format!("{} {}: {line}", worker.file, worker.item));
+        m.print();
+    }
+
+    /// Whether the rest of the file should be left alone.
+    pub fn stopped(&self) -> bool {
+        let m = self.report.lock();
+        m.abort || m.workers[self.slot].as_ref().is_some_and(|w| w.skip)
+    }
+}
+
+impl<'a> Drop for StatusPart<'a> {
+    fn drop(&mut self) {
+        self.report.leave_file(self.slot, self.remaining);
+    }
+}
@@ -0,0 +1,114 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    fs,
+    path::Path,
+};
+
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::report::Outcome;
+
+/// The parts of a `report::Entry` the summary needs.
+#[derive(Deserialize)]
+struct Entry {
+    file: String,
+    option: String,
+    kind: String,
+    outcome: Outcome,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    elsewhere: Vec<String>,
+}
+
+/// What is left to do about candidates with `outcome`.
+fn heading(outcome: Outcome) -> &'static str {
+    match outcome {
+        Outcome::Changed | Outcome::Allowed | Outcome::Forced | Outcome::Unchecked => "Converted",
+        Outcome::Mismatch => "Rendered differently",
+        Outcome::NearMiss => "Rendered differently in whitespace only",
+        Outcome::BuildFailed => "Broke the manual build",
+        Outcome::RewriteFailed => "Couldn't be rewritten",
+        Outcome::InvalidNix => "Rewritten into invalid Nix",
+        Outcome::Overlapping => "Inside other candidates",
+        Outcome::Skipped => "Left alone",
+    }
+}
+
+fn summarize(entries: &[Entry]) -> String {
+    let converted = entries.iter().filter(|e| e.outcome.accepted()).collect::<Vec<_>>();
+    let files = converted.iter().map(|e| &e.file).collect::<BTreeSet<_>>();
+    let count = |outcome| converted.iter().filter(|e| e.outcome == outcome).count();
+    let mut out = String::new();
+
+    writeln!(out, "Converted {} of {} candidates in {} files.\n",
+             converted.len(), entries.len(), files.len()).unwrap();
+    let (allowed, forced) = (count(Outcome::Allowed), count(Outcome::Forced));
+    if allowed + forced > 0 {
+        writeln!(out, "Of these, {allowed} render with differences allowed by `--allowed-diffs` and \
+                       {forced} with differences matching `--force-apply-pattern`, all reviewed \
+                       to be harmless.\n").unwrap();
+    }
+    let elsewhere = converted.iter().filter(|e| !e.elsewhere.is_empty()).count();
+    if elsewhere > 0 {
+        writeln!(out, "Of these, {elsewhere} also change the documentation of options declared in \
+                       other files, which import theirs.\n").unwrap();
+    }
+    let unchecked = count(Outcome::Unchecked);
+    if unchecked > 0 {
+        writeln!(out, "Of these, {unchecked} are plain text converted by `--only-markup accept` \
+                       without a check of their own.\n").unwrap();
+    }
+
+    let mut kinds = BTreeMap::<&str, (usize, usize)>::new();
+    for e in entries {
+        let counts = kinds.entry(&e.kind).or_default();
+        match e.outcome.accepted() {
+            true => counts.0 += 1,
+            false => counts.1 += 1,
+        }
+    }
+    writeln!(out, "| Kind | Converted | Left |\n| --- | --- | --- |").unwrap();
+    for (kind, (done, left)) in &kinds {
+        writeln!(out, "| {kind} | {done} | {left} |").unwrap();
+    }
+
+    let mut tags = BTreeMap::<&str, usize>::new();
+    for tag in converted.iter().flat_map(|e| &e.tags) {
+        *tags.entry(tag).or_default() += 1;
+    }
+    if !tags.is_empty() {
+        writeln!(out, "\n### Tags converted\n\n| Tag | Candidates |\n| --- | --- |").unwrap();
+        for (tag, n) in &tags {
+            writeln!(out, "| `<{tag}>` | {n} |").unwrap();
+        }
+    }
+
+    let mut left = BTreeMap::<Outcome, Vec<&Entry>>::new();
+    for e in entries.iter().filter(|e| !e.outcome.accepted()) {
+        left.entry(e.outcome).or_default().push(e);
+    }
+    if !left.is_empty() {
+        writeln!(out, "\n### Left to convert by hand").unwrap();
+    }
+    for (outcome, entries) in &left {
+        writeln!(out, "\n<details><summary>{} ({})</summary>\n", heading(*outcome), entries.len()).unwrap();
+        for e in entries {
+            match e.option.as_str() {
+                "" => writeln!(out, "- `{}`", e.file).unwrap(),
+                option => writeln!(out, "- `{}`: `{option}`", e.file).unwrap(),
+            }
+        }
+        writeln!(out, "\n</details>").unwrap();
+    }
+    out
+}
+
+/// Run `summarize`: print the summary of the report at `path`.
+pub fn run(path: &Path) -> Result<()> {
+    let entries: Vec<Entry> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    print!("{}", summarize(&entries));
+    Ok(())
+}
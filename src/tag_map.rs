@@ -0,0 +1,51 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+/// How inline DocBook tags map to Markdown. The defaults match the roles
+/// understood by the nixpkgs manual renderer; other projects can override
+/// them with a TOML file passed to `--tag-map`, e.g.
+///
+/// ```toml
+/// [code]
+/// filename = ""
+/// package = "package"
+/// ```
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct TagMap {
+    /// Tags whose contents become code spans, mapped to the role the span is
+    /// given. An empty role produces a plain code span. Entries in a file are
+    /// merged over the defaults.
+    #[serde(default)]
+    pub code: BTreeMap<String, String>,
+}
+
+impl Default for TagMap {
+    fn default() -> Self {
+        let code = [
+            ("literal", ""),
+            ("filename", "file"),
+            ("option", "option"),
+            ("command", "command"),
+            ("varname", "var"),
+            ("envar", "env"),
+        ];
+        Self {
+            code: code.into_iter().map(|(t, r)| (t.to_string(), r.to_string())).collect(),
+        }
+    }
+}
+
+impl TagMap {
+    /// Load a tag map from `path`, filling in defaults for unmentioned tags.
+    /// Meant to be used as a clap value parser.
+    #[cfg(feature = "cli")]
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let overrides: Self = toml::from_str(&text).map_err(|e| format!("{path}: {e}"))?;
+        let mut result = Self::default();
+        result.code.extend(overrides.code);
+        Ok(result)
+    }
+}
@@ -0,0 +1,28 @@
+use std::{borrow::Cow, cell::RefCell};
+
+/// A conversion rule that changed the text, and the text it produced.
+pub type Fired = (String, String);
+
+thread_local! {
+    static FIRED: RefCell<Option<Vec<Fired>>> = const { RefCell::new(None) };
+}
+
+/// Pass on the result of applying `rule` to `old`, noting it down if it
+/// changed anything while `record` runs.
+pub fn fired(rule: &str, old: &str, new: Cow<'_, str>) -> String {
+    FIRED.with_borrow_mut(|fired| {
+        if let Some(fired) = fired {
+            if new != old {
+                fired.push((rule.to_string(), new.to_string()));
+            }
+        }
+    });
+    new.into_owned()
+}
+
+/// Run `f`, collecting the rules that fired in it, for `--explain`.
+pub fn record<T>(f: impl FnOnce() -> T) -> (T, Vec<Fired>) {
+    FIRED.set(Some(vec![]));
+    let result = f();
+    (result, FIRED.take().unwrap_or_default())
+}
@@ -0,0 +1,167 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use ratatui::{
+    DefaultTerminal, Frame,
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    widgets::{Block, List, ListState, Paragraph},
+};
+use similar::TextDiff;
+
+use crate::status::{StatusReport, StatusReportData};
+
+/// A unified diff of two manuals, small enough to look at.
+pub fn diff_preview(old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .to_string()
+}
+
+#[derive(PartialEq)]
+enum Focus {
+    Workers,
+    Failures,
+}
+
+struct Ui {
+    focus: Focus,
+    workers: ListState,
+    failures: ListState,
+    scroll: u16,
+}
+
+impl Ui {
+    fn draw(&mut self, frame: &mut Frame, status: &StatusReport, done: bool) {
+        let m = status.lock();
+        let [totals, last_error, workers, bottom, help] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Length(m.workers.len().min(16) as u16 + 2),
+            Constraint::Min(5),
+            Constraint::Length(1),
+        ]).areas(frame.area());
+        let [failures, preview] = Layout::horizontal([
+            Constraint::Percentage(40),
+            Constraint::Percentage(60),
+        ]).areas(bottom);
+
+        let title = match (done, m.abort) {
+            (true, _) => format!("{} (done)", m.totals()),
+            (_, true) => format!("{} (aborting)", m.totals()),
+            _ => m.totals(),
+        };
+        frame.render_widget(Paragraph::new(title), totals);
+        if let Some(error) = &m.last_error {
+            frame.render_widget(Paragraph::new(format!("last build failure: {error}")), last_error);
+        }
+
+        let highlight = |focus| match self.focus == focus {
+            true => Style::new().add_modifier(Modifier::REVERSED),
+            false => Style::new().add_modifier(Modifier::BOLD),
+        };
+        let lines = m.workers.iter().enumerate().map(|(i, w)| {
+            let line = StatusReportData::worker_line(i, w.as_ref());
+            match w {
+                Some(w) if w.skip => format!("{line} (skipping)"),
+                _ => line,
+            }
+        });
+        let list = List::new(lines)
+            .block(Block::bordered().title("Workers"))
+            .highlight_style(highlight(Focus::Workers));
+        frame.render_stateful_widget(list, workers, &mut self.workers);
+
+        let list = List::new(m.failures.iter().map(|f| format!("{:?} {} {}", f.outcome, f.file, f.item)))
+            .block(Block::bordered().title(format!("Failures ({})", m.failures.len())))
+            .highlight_style(highlight(Focus::Failures));
+        frame.render_stateful_widget(list, failures, &mut self.failures);
+
+        let text = self.failures.selected()
+            .and_then(|i| m.failures.get(i))
+            .map_or("", |f| f.preview.as_str());
+        let paragraph = Paragraph::new(text)
+            .block(Block::bordered().title("Preview"))
+            .scroll((self.scroll, 0));
+        frame.render_widget(paragraph, preview);
+
+        frame.render_widget(
+            Paragraph::new("tab: switch list  up/down: select  pgup/pgdn: scroll preview  \
+                            s: skip rest of file  q: abort, or quit when done"),
+            help);
+    }
+
+    /// Handle a key, returning whether to leave the TUI.
+    fn key(&mut self, code: KeyCode, status: &StatusReport, done: bool) -> bool {
+        let mut m = status.lock();
+        let (state, len) = match self.focus {
+            Focus::Workers => (&mut self.workers, m.workers.len()),
+            Focus::Failures => (&mut self.failures, m.failures.len()),
+        };
+        match code {
+            KeyCode::Tab => {
+                self.focus = match self.focus {
+                    Focus::Workers => Focus::Failures,
+                    Focus::Failures => Focus::Workers,
+                };
+            }
+            KeyCode::Up => {
+                state.select(Some(state.selected().map_or(0, |i| i.saturating_sub(1))));
+                self.scroll = 0;
+            }
+            KeyCode::Down if len > 0 => {
+                state.select(Some(state.selected().map_or(0, |i| (i + 1).min(len - 1))));
+                self.scroll = 0;
+            }
+            KeyCode::PageUp => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_add(10),
+            KeyCode::Char('s') if self.focus == Focus::Workers => {
+                let selected = self.workers.selected();
+                if let Some(Some(worker)) = selected.and_then(|i| m.workers.get_mut(i)) {
+                    worker.skip = true;
+                }
+            }
+            KeyCode::Char('q') => {
+                if !done {
+                    m.abort = true;
+                }
+                return true;
+            }
+            _ => {}
+        }
+        false
+    }
+}
+
+fn run_in(terminal: &mut DefaultTerminal, status: &StatusReport,
+          done: impl Fn() -> bool) -> Result<()> {
+    let mut ui = Ui {
+        focus: Focus::Workers,
+        workers: ListState::default(),
+        failures: ListState::default(),
+        scroll: 0,
+    };
+    loop {
+        let done = done();
+        terminal.draw(|frame| ui.draw(frame, status, done))?;
+        if !event::poll(Duration::from_millis(200))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && ui.key(key.code, status, done) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Show the progress of the run until the user leaves, which aborts the run
+/// unless `done` says it has finished.
+pub fn run(status: &StatusReport, done: impl Fn() -> bool) -> Result<()> {
+    let mut terminal = ratatui::init();
+    let result = run_in(&mut terminal, status, done);
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,65 @@
+use std::{
+    fs,
+    io::IsTerminal,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result};
+use nix_doc_munge::{ConvertOptions, pass::Pass};
+
+use crate::{error, scan};
+
+/// How often the file is checked for changes.
+const POLL: Duration = Duration::from_millis(300);
+
+/// Print the rewrite of each candidate of `file`, each on its own against
+/// the file as it is.
+fn show(file: &str, pass: Pass, opts: &ConvertOptions) -> Result<()> {
+    let content = fs::read_to_string(file).with_context(|| format!("reading {file}"))?;
+    let (mut candidates, overlapping) = pass.scan(&content, opts).map_err(error::parse(file))?;
+    candidates.extend(overlapping);
+    candidates.sort_by_key(|c| c.range.start());
+    let mut supported = 0;
+    for candidate in &candidates {
+        let option = match candidate.option_path.as_str() {
+            "" => format!("{:?}", candidate.range),
+            option => option.to_string(),
+        };
+        println!("── {option} ({})", candidate.kind.name());
+        let Some(rewritten) = scan::rewritten(pass, file, &content, candidate, opts) else {
+            println!("can't be rewritten\n");
+            continue;
+        };
+        println!("{rewritten}");
+        match scan::tags_left(&rewritten).as_slice() {
+            [] => supported += 1,
+            tags => println!("leaves <{}>", tags.join(">, <")),
+        }
+        println!();
+    }
+    println!("{} candidates, {supported} of them supported", candidates.len());
+    Ok(())
+}
+
+/// Run `--watch`: show the rewrites of the candidates of `file`, and again
+/// each time it is saved, until interrupted.
+pub fn run(file: &str, pass: Pass, opts: &ConvertOptions) -> Result<()> {
+    let mut shown = None::<SystemTime>;
+    loop {
+        let modified = fs::metadata(file).and_then(|m| m.modified())
+            .with_context(|| format!("reading {file}"))?;
+        if shown != Some(modified) {
+            shown = Some(modified);
+            if std::io::stdout().is_terminal() {
+                print!("\x1b[2J\x1b[H");
+            }
+            // editors may save the file half way, or broken
+            if let Err(error) = show(file, pass, opts) {
+                println!("{error:#}");
+            }
+            println!("watching {file} for changes");
+        }
+        thread::sleep(POLL);
+    }
+}
@@ -0,0 +1,47 @@
+use std::fs;
+
+use nix_doc_munge::{
+    ConvertOptions, convert_one, normalize,
+    nix_string::NixString,
+    pass::{Candidate, CandidateKind},
+};
+use clap::Parser;
+use rnix::TextRange;
+
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    opts: ConvertOptions,
+}
+
+/// Each `.nix` fixture is a single description string, as found in a
+/// module, converted as an `mdDoc` candidate on its own, with the
+/// conversion options in a `.args` file next to it if there is one.
+#[test]
+fn convert() {
+    insta::with_settings!({ snapshot_path => "fixtures/snapshots", prepend_module_to_snapshot => false }, {
+        insta::glob!("fixtures", "*.nix", |path| {
+            let content = fs::read_to_string(path).unwrap();
+            let chunk = content.trim_end();
+            let candidate = Candidate {
+                range: TextRange::up_to((chunk.len() as u32).into()),
+                kind: CandidateKind::Description,
+                option_path: String::new(),
+                string_kind: NixString::parse(chunk).map(|string| string.kind),
+            };
+            let args = fs::read_to_string(path.with_extension("args")).unwrap_or_default();
+            let opts = Args::parse_from(["fixture"].into_iter().chain(args.split_whitespace())).opts;
+            insta::assert_snapshot!(convert_one(chunk, &candidate, &opts));
+        });
+    });
+}
+
+/// Each `.xml` fixture is a piece of a built manual.
+#[test]
+fn normalize_manual() {
+    insta::with_settings!({ snapshot_path => "fixtures/snapshots", prepend_module_to_snapshot => false }, {
+        insta::glob!("fixtures", "*.xml", |path| {
+            insta::assert_snapshot!(normalize(&fs::read_to_string(path).unwrap()));
+        });
+    });
+}
@@ -0,0 +1,6 @@
+''
+  Enable the service.
+  <note><para>
+    This needs <command>systemctl restart</command> to apply.
+  </para></note>
+''
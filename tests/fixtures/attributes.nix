@@ -0,0 +1,8 @@
+''
+  Written to <filename remap="file">/etc/foo.conf</filename>, see
+  <link xml:id="foo-docs" xlink:href="https://foo.org/docs">the
+  <literal remap="code">foo</literal> documentation</link> and
+  <xref linkend="sec-foo" xrefstyle="short"/>. <emphasis xml:lang="en"
+  role="strong">Never</emphasis> set <varname xml:space="preserve">x</varname>
+  by <emphasis remap="i">hand</emphasis>.
+''
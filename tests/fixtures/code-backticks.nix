@@ -0,0 +1 @@
+"Set <literal>foo = `bar`;</literal> or <literal>`</literal>, quoting with <command>echo &quot;a &amp;&amp; b&quot; &#x3E; c</command>, but not `this`."
@@ -0,0 +1,8 @@
+''
+  Extra configuration, for example:
+  <programlisting>
+  {
+    foo = "bar";
+  }
+  </programlisting>
+''
@@ -0,0 +1 @@
+"Path of the <filename>foo.conf</filename> used, " + cfg.extraDescription
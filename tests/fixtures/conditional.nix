@@ -0,0 +1 @@
+if pkgs.stdenv.isLinux then "Uses <filename>/etc/foo</filename>." else "Uses <filename>/usr/local/etc/foo</filename>."
@@ -0,0 +1 @@
+"<emphasis role=\"bold\">Bold</emphasis>, <emphasis role='strong'>strong</emphasis> and <emphasis>plain</emphasis> before <emphasis role=\"strong\">strong again</emphasis>, but not <emphasis role=\"underline\">underlined</emphasis>."
@@ -0,0 +1 @@
+"This is <emphasis>important</emphasis> and <emphasis role=\"strong\">really</emphasis> so, see <citerefentry><refentrytitle>systemd.unit</refentrytitle><manvolnum>5</manvolnum></citerefentry>."
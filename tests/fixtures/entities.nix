@@ -0,0 +1 @@
+"Values &lt; 10 &amp; greater than <literal>0</literal>, using *stars* and _underscores_."
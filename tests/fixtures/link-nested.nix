@@ -0,0 +1,5 @@
+''
+  Run <link xlink:href="https://example.org/foo"><literal>foo --bar</literal></link>,
+  see <link linkend="sec-foo">the <emphasis>foo</emphasis> section</link> and
+  <link xlink:href="https://example.org/a_b">the [old] <command>a_b</command> page</link>.
+''
@@ -0,0 +1,4 @@
+''
+  See <link xlink:href="https://example.org/docs">the documentation</link>
+  or <link xlink:href="https://example.org"/>, and <xref linkend="opt-services.foo.enable"/>.
+''
@@ -0,0 +1 @@
+"Path to the <literal>config.toml</literal> file, see <option>services.foo.enable</option>."
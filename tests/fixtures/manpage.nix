@@ -0,0 +1,7 @@
+''
+  See <link xlink:href="https://www.freedesktop.org/software/systemd/man/systemd.unit.html"><citerefentry><refentrytitle>systemd.unit</refentrytitle><manvolnum>5</manvolnum></citerefentry></link>
+  and <citerefentry class="manpage">
+    <refentrytitle>nix.conf</refentrytitle>
+    <manvolnum >5</manvolnum>
+  </citerefentry>.
+''
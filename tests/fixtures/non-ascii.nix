@@ -0,0 +1,4 @@
+''
+  Grüße aus <filename>/etc/münchen</filename> — the “quoted” <literal>naïve</literal> value…
+  See <link xlink:href="https://example.org/ü">Ümlaut docs</link>.
+''
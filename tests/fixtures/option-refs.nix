@@ -0,0 +1,6 @@
+''
+  Only used when <xref linkend="opt-services.foo.enable"/> is set, see
+  <xref linkend="opt-services.foo.instances._name_.port"/> and
+  <option>services.foo.instances.<replaceable>name</replaceable>.user</option>
+  for each instance, or <xref linkend="sec-foo"/> for more.
+''
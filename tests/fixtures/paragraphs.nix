@@ -0,0 +1,9 @@
+''
+  <para>The first paragraph.</para><para>The second, on the same line.</para>
+  <para>A third,
+  over two lines.</para>
+  Text after them.
+  <para>
+    And one more.
+  </para>
+''
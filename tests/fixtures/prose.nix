@@ -0,0 +1,5 @@
+''
+  Names like _a_ and [x](y) in the text stay text, as does a list of
+  1. item, even next to <literal>code</literal>
+  2) or a <link xlink:href="https://example.com">[link]</link>.
+''
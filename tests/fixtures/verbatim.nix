@@ -0,0 +1,13 @@
+''
+  Run this:
+  <screen>
+  $ foo --bar   
+
+
+  done
+  </screen>
+  and   
+
+
+  that.
+''
@@ -0,0 +1,150 @@
+use nix_doc_munge::{
+    ConvertOptions, convert_one,
+    edit::{Edit, EditList},
+    pass::{Candidate, Pass},
+};
+use rnix::{TextRange, TextSize};
+use proptest::prelude::*;
+use regex::Regex;
+
+/// A word that is also valid as an attribute name inside interpolations.
+fn word() -> impl Strategy<Value = String> {
+    const KEYWORDS: &[&str] = &["assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with"];
+    "[a-z]{1,8}".prop_filter("Nix keyword", |s| !KEYWORDS.contains(&s.as_str()))
+}
+
+/// Description text made of DocBook markup, plain words and interpolations.
+fn description() -> impl Strategy<Value = String> {
+    let piece = prop_oneof![
+        word(),
+        word().prop_map(|s| format!("<literal>{s}</literal>")),
+        word().prop_map(|s| format!("<option>services.{s}.enable</option>")),
+        word().prop_map(|s| format!("<emphasis>{s}</emphasis>")),
+        word().prop_map(|s| format!("<link xlink:href=\"https://{s}.org\">{s}</link>")),
+        word().prop_map(|s| format!("<filename>${{cfg.{s}}}/etc</filename>")),
+        word().prop_map(|s| format!("${{toString cfg.{s}}}")),
+        Just("&lt;".to_string()),
+        Just("“Grüße” …".to_string()),
+        Just("<para>".to_string()),
+        Just("</para>".to_string()),
+    ];
+    prop::collection::vec(piece, 1 .. 12).prop_map(|pieces| pieces.join(" "))
+}
+
+fn module(descriptions: &[String]) -> String {
+    let options = descriptions.iter().enumerate()
+        .map(|(i, d)| format!("  options.o{i} = lib.mkOption {{\n    description = \"{d}\";\n  }};\n"))
+        .collect::<String>();
+    format!("{{ lib, cfg, ... }}: {{\n{options}}}\n")
+}
+
+/// Like `module`, with each description given on its own, concatenated with
+/// more text or to `mkEnableOption` by its placement. Its quotes are escaped,
+/// the file has to parse for the placement to matter.
+fn placed_module(placed: &[(String, usize)]) -> String {
+    let options = placed.iter().enumerate()
+        .map(|(i, (d, placement))| (i, d.replace('"', "\\\""), placement))
+        .map(|(i, d, placement)| match placement {
+            0 => format!("  options.o{i} = lib.mkOption {{\n    description = \"{d}\";\n  }};\n"),
+            1 => format!("  options.o{i} = lib.mkOption {{\n    description = \"{d}\" + \".\";\n  }};\n"),
+            _ => format!("  options.o{i} = lib.mkEnableOption \"{d}\";\n"),
+        })
+        .collect::<String>();
+    format!("{{ lib, cfg, ... }}: {{\n{options}}}\n")
+}
+
+fn interpolations(s: &str) -> Vec<&str> {
+    let re = Regex::new(r"\$\{[^}]*\}").unwrap();
+    re.find_iter(s).map(|m| m.as_str()).collect()
+}
+
+proptest! {
+    #[test]
+    fn interpolations_are_kept(descriptions in prop::collection::vec(description(), 1 .. 4)) {
+        let file = module(&descriptions);
+        let (candidates, _) = Pass::MdDoc.scan(&file, &ConvertOptions::default()).unwrap();
+        prop_assert_eq!(candidates.len(), descriptions.len());
+        for candidate in candidates {
+            let new = convert_one(&file, &candidate, &ConvertOptions::default());
+            prop_assert_eq!(interpolations(&new), interpolations(&file));
+        }
+    }
+
+    /// Converting all candidates back to front, as a run does, grows the file
+    /// by the growth of each candidate converted on its own.
+    #[test]
+    fn deltas_add_up(descriptions in prop::collection::vec(description(), 1 .. 4)) {
+        let file = module(&descriptions);
+        let opts = ConvertOptions::default();
+        let (candidates, _) = Pass::MdDoc.scan(&file, &opts).unwrap();
+        let mut content = file.clone();
+        let mut expected = file.len() as isize;
+        for candidate in &candidates {
+            let alone = convert_one(&file, candidate, &opts);
+            expected += alone.len() as isize - file.len() as isize;
+            content = convert_one(&content, candidate, &opts);
+        }
+        prop_assert_eq!(content.len() as isize, expected);
+        prop_assert!(content.ends_with(&file[usize::from(candidates[0].range.end()) ..]));
+    }
+
+    /// Carried through an `EditList`, candidates can be converted front to
+    /// back as well, with the same result.
+    #[test]
+    fn edit_order_does_not_matter(descriptions in prop::collection::vec(description(), 1 .. 4)) {
+        let file = module(&descriptions);
+        let opts = ConvertOptions::default();
+        let (candidates, _) = Pass::MdDoc.scan(&file, &opts).unwrap();
+        let back_to_front = candidates.iter()
+            .fold(file.clone(), |content, candidate| convert_one(&content, candidate, &opts));
+        let (mut content, mut edits) = (file.clone(), EditList::default());
+        for candidate in candidates.iter().rev() {
+            let range = edits.map(candidate.range).unwrap();
+            let new = convert_one(&content, &Candidate { range, ..candidate.clone() }, &opts);
+            edits.extend(Edit::between(&content, &new));
+            content = new;
+        }
+        prop_assert_eq!(content, back_to_front);
+    }
+
+    /// Wherever a description is, its `lib.mdDoc` call is parenthesized so
+    /// that the call is the whole of it, leaving nothing to convert again.
+    #[test]
+    fn calls_are_parenthesized(placed in prop::collection::vec((description(), 0 .. 3usize), 1 .. 4)) {
+        let file = placed_module(&placed);
+        let opts = ConvertOptions::default();
+        let (candidates, _) = Pass::MdDoc.scan(&file, &opts).unwrap();
+        prop_assert_eq!(candidates.len(), placed.len());
+        let content = candidates.iter()
+            .fold(file.clone(), |content, candidate| convert_one(&content, candidate, &opts));
+        prop_assert!(rnix::parse(&content).as_result().is_ok(), "{}", content);
+        prop_assert_eq!(Pass::MdDoc.scan(&content, &opts).unwrap().0, vec![]);
+    }
+}
+
+/// Ranges gone stale can land inside a multi-byte character, which must be
+/// reported as a failed rewrite rather than panic.
+#[test]
+fn ranges_inside_characters_fail() {
+    let file = module(&["Grüße aus <literal>München</literal>".to_string()]);
+    let opts = ConvertOptions::default();
+    let (candidates, _) = Pass::MdDoc.scan(&file, &opts).unwrap();
+    let candidate = &candidates[0];
+    assert!(Pass::MdDoc.rewrite("m.nix", &file, candidate, &opts).is_ok());
+    // from the second byte of the ü
+    let inside = TextSize::from(file.find('ü').unwrap() as u32 + 1);
+    let stale = Candidate { range: TextRange::new(inside, candidate.range.end()), ..candidate.clone() };
+    assert!(Pass::MdDoc.rewrite("m.nix", &file, &stale, &opts).is_err());
+}
+
+/// A file that doesn't parse is an error to report, not a panic, for every
+/// pass that scans Nix.
+#[test]
+fn broken_files_fail_to_scan() {
+    let file = module(&["Unfinished <literal>business</literal>".to_string()]);
+    let broken = &file[.. file.len() / 2];
+    let opts = ConvertOptions::default();
+    for pass in [Pass::MdDoc, Pass::EnableOption, Pass::LiteralExample, Pass::DefaultText, Pass::Messages] {
+        assert!(pass.scan(broken, &opts).is_err(), "{pass:?}");
+    }
+}
@@ -0,0 +1,29 @@
+//! The conversion of the `md-doc` pass for the browser, built with
+//! `wasm-pack build --target web`. Nothing is checked against a manual, so
+//! this is for seeing what the tool makes of a description, or for
+//! converting one by hand that it refuses to.
+
+use nix_doc_munge::{ConvertOptions, convert_docbook, pass::Pass};
+use wasm_bindgen::{JsError, prelude::wasm_bindgen};
+
+/// The Markdown of the DocBook description `docbook`.
+#[wasm_bindgen]
+pub fn convert(docbook: &str) -> String {
+    convert_docbook(docbook, &ConvertOptions::default())
+}
+
+/// The module `source` with all the descriptions converted that can be.
+/// Throws if `source` doesn't parse.
+#[wasm_bindgen]
+pub fn convert_module(source: &str) -> Result<String, JsError> {
+    let opts = ConvertOptions::default();
+    let (mut candidates, _) = Pass::MdDoc.scan(source, &opts).map_err(|e| JsError::new(&e.to_string()))?;
+    // from the end, so that the ranges of those before stay where they are
+    candidates.sort_by_key(|c| std::cmp::Reverse(c.range.start()));
+    Ok(candidates.iter().fold(source.to_string(), |s, candidate| {
+        match Pass::MdDoc.rewrite("module.nix", &s, candidate, &opts) {
+            Ok(rewrite) => rewrite.content,
+            Err(_) => s,
+        }
+    }))
+}